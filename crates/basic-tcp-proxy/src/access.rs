@@ -0,0 +1,70 @@
+use std::net::IpAddr;
+
+use crate::{AccessConfig, AccessPolicy, AccessRule, Cidr};
+
+// One rule per line: `allow <cidr>` or `deny <cidr>`; `#` starts a comment.
+pub fn parse_access_list(content: &str) -> Result<Vec<AccessRule>, String> {
+    let mut rules = Vec::new();
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || format!("line {}: expected 'allow <cidr>' or 'deny <cidr>'", idx + 1);
+        let mut parts = line.split_whitespace();
+        let policy = match parts.next() {
+            Some("allow") => AccessPolicy::Allow,
+            Some("deny") => AccessPolicy::Deny,
+            _ => return Err(invalid()),
+        };
+        let (Some(cidr), None) = (parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let cidr: Cidr = cidr
+            .parse()
+            .map_err(|e| format!("line {}: {}", idx + 1, e))?;
+        rules.push(AccessRule { policy, cidr });
+    }
+    Ok(rules)
+}
+
+impl AccessConfig {
+    pub fn is_open(&self) -> bool {
+        self.default == AccessPolicy::Allow && self.deny.is_empty() && self.list_rules.is_empty()
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = AccessRule> + '_ {
+        let allow = self.allow.iter().map(|&cidr| AccessRule {
+            policy: AccessPolicy::Allow,
+            cidr,
+        });
+        let deny = self.deny.iter().map(|&cidr| AccessRule {
+            policy: AccessPolicy::Deny,
+            cidr,
+        });
+        allow.chain(deny).chain(self.list_rules.iter().copied())
+    }
+
+    // The most specific matching rule wins and deny wins a tie. Returns the rule that denied
+    // the client, or `None` when it was turned away by the default policy.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Option<Cidr>> {
+        if self.is_open() {
+            return Ok(());
+        }
+
+        let best = self
+            .rules()
+            .filter(|rule| rule.cidr.contains(ip))
+            .max_by_key(|rule| (rule.cidr.prefix_len(), rule.policy == AccessPolicy::Deny));
+        match best {
+            Some(AccessRule {
+                policy: AccessPolicy::Allow,
+                ..
+            }) => Ok(()),
+            Some(rule) => Err(Some(rule.cidr)),
+            None if self.default == AccessPolicy::Allow => Ok(()),
+            None => Err(None),
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    fs::{self, File},
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+use crate::{CaptureConfig, Cidr, Config, Direction};
+
+const CAPTURE_QUEUE_SIZE: usize = 4096;
+
+// pcapng block types and the raw IP link type, so no Ethernet header has to be made up.
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const LINKTYPE_RAW: u16 = 101;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct CaptureFilter {
+    pub routes: BTreeSet<String>,
+    pub clients: BTreeSet<String>,
+}
+
+impl CaptureFilter {
+    fn from_config(capture: &CaptureConfig) -> Self {
+        Self {
+            routes: capture.routes.iter().cloned().collect(),
+            clients: capture.clients.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn matches(&self, route: &str, ip: IpAddr) -> bool {
+        self.routes.contains(route)
+            || self
+                .clients
+                .iter()
+                .filter_map(|cidr| cidr.parse::<Cidr>().ok())
+                .any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Flow {
+    client: SocketAddr,
+    proxy: SocketAddr,
+}
+
+enum Record {
+    Open,
+    Data(Direction, Vec<u8>),
+    Close,
+}
+
+struct CaptureMessage {
+    flow: Flow,
+    at: SystemTime,
+    record: Record,
+}
+
+// Decides which connections are captured and feeds them to a single writer task. Like toxics,
+// the filter can be changed at runtime and a reload only resets it when the config's lists changed.
+pub struct CaptureRegistry {
+    filter: Mutex<CaptureFilter>,
+    configured: Mutex<CaptureFilter>,
+    tx: mpsc::Sender<CaptureMessage>,
+}
+
+impl CaptureRegistry {
+    pub fn new(config: &Config) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel(CAPTURE_QUEUE_SIZE);
+        let writer = CaptureWriter {
+            dir: config.capture_dir(),
+            max_file_size: config.capture.max_file_size,
+            file: None,
+            written: 0,
+            files: 0,
+            flows: HashMap::new(),
+        };
+        tokio::spawn(writer.run(rx));
+
+        let filter = CaptureFilter::from_config(&config.capture);
+        Arc::new(Self {
+            filter: Mutex::new(filter.clone()),
+            configured: Mutex::new(filter),
+            tx,
+        })
+    }
+
+    pub fn filter(&self) -> CaptureFilter {
+        self.filter.lock().expect("capture filter poisoned").clone()
+    }
+
+    pub fn sync_config(&self, config: &Config) {
+        let next = CaptureFilter::from_config(&config.capture);
+        let mut configured = self.configured.lock().expect("capture filter poisoned");
+        if *configured != next {
+            *self.filter.lock().expect("capture filter poisoned") = next.clone();
+            *configured = next;
+        }
+    }
+
+    pub fn set_route(&self, route: &str, enabled: bool) -> CaptureFilter {
+        let mut filter = self.filter.lock().expect("capture filter poisoned");
+        if enabled {
+            filter.routes.insert(route.to_string());
+        } else {
+            filter.routes.remove(route);
+        }
+        filter.clone()
+    }
+
+    pub fn set_client(&self, cidr: Cidr, enabled: bool) -> CaptureFilter {
+        let mut filter = self.filter.lock().expect("capture filter poisoned");
+        if enabled {
+            filter.clients.insert(cidr.to_string());
+        } else {
+            filter.clients.remove(&cidr.to_string());
+        }
+        filter.clone()
+    }
+
+    // Whether a connection is captured is decided once, when it opens.
+    pub fn tap(&self, route: &str, client: SocketAddr, proxy: SocketAddr) -> Option<CaptureTap> {
+        if !self.filter().matches(route, client.ip()) {
+            return None;
+        }
+        let tap = CaptureTap {
+            tx: self.tx.clone(),
+            flow: Flow { client, proxy },
+        };
+        tap.send(Record::Open);
+        Some(tap)
+    }
+}
+
+#[derive(Clone)]
+pub struct CaptureTap {
+    tx: mpsc::Sender<CaptureMessage>,
+    flow: Flow,
+}
+
+impl CaptureTap {
+    // Capturing must never hold up the relay, so records are dropped when the writer falls behind.
+    fn send(&self, record: Record) {
+        let _ = self.tx.try_send(CaptureMessage {
+            flow: self.flow,
+            at: SystemTime::now(),
+            record,
+        });
+    }
+
+    pub fn record(&self, direction: Direction, data: &[u8]) {
+        self.send(Record::Data(direction, data.to_vec()));
+    }
+
+    pub fn close(&self) {
+        self.send(Record::Close);
+    }
+}
+
+#[derive(Default)]
+struct FlowState {
+    client_seq: u32,
+    proxy_seq: u32,
+}
+
+struct CaptureWriter {
+    dir: PathBuf,
+    max_file_size: u64,
+    file: Option<BufWriter<File>>,
+    written: u64,
+    files: u64,
+    flows: HashMap<Flow, FlowState>,
+}
+
+impl CaptureWriter {
+    async fn run(mut self, mut rx: mpsc::Receiver<CaptureMessage>) {
+        while let Some(message) = rx.recv().await {
+            let mut result = self.write(message).await;
+            if result.is_ok()
+                && rx.is_empty()
+                && let Some(file) = self.file.as_mut()
+            {
+                result = file.flush().await;
+            }
+            if let Err(e) = result {
+                eprintln!("[CAPTURE] Failed to write capture: {}", e);
+                self.file = None;
+            }
+        }
+        if let Some(file) = self.file.as_mut() {
+            let _ = file.flush().await;
+        }
+    }
+
+    async fn write(&mut self, message: CaptureMessage) -> io::Result<()> {
+        let flow = message.flow;
+        let state = self.flows.entry(flow).or_default();
+        let mut segments = Vec::new();
+        match message.record {
+            Record::Open => {
+                state.client_seq = fastrand::u32(..);
+                state.proxy_seq = fastrand::u32(..);
+                segments.push((true, TCP_SYN, state.client_seq, 0, Vec::new()));
+                segments.push((
+                    false,
+                    TCP_SYN | TCP_ACK,
+                    state.proxy_seq,
+                    state.client_seq.wrapping_add(1),
+                    Vec::new(),
+                ));
+                state.client_seq = state.client_seq.wrapping_add(1);
+                state.proxy_seq = state.proxy_seq.wrapping_add(1);
+                segments.push((true, TCP_ACK, state.client_seq, state.proxy_seq, Vec::new()));
+            }
+            Record::Data(direction, data) => {
+                let from_client = direction == Direction::Upstream;
+                let (seq, ack) = if from_client {
+                    (state.client_seq, state.proxy_seq)
+                } else {
+                    (state.proxy_seq, state.client_seq)
+                };
+                // Sequence numbers are 32 bits and wrap by design.
+                #[allow(clippy::cast_possible_truncation)]
+                let len = data.len() as u32;
+                if from_client {
+                    state.client_seq = state.client_seq.wrapping_add(len);
+                } else {
+                    state.proxy_seq = state.proxy_seq.wrapping_add(len);
+                }
+                segments.push((from_client, TCP_PSH | TCP_ACK, seq, ack, data));
+            }
+            Record::Close => {
+                let (client_seq, proxy_seq) = (state.client_seq, state.proxy_seq);
+                self.flows.remove(&flow);
+                segments.push((true, TCP_FIN | TCP_ACK, client_seq, proxy_seq, Vec::new()));
+                segments.push((
+                    false,
+                    TCP_FIN | TCP_ACK,
+                    proxy_seq,
+                    client_seq.wrapping_add(1),
+                    Vec::new(),
+                ));
+                segments.push((
+                    true,
+                    TCP_ACK,
+                    client_seq.wrapping_add(1),
+                    proxy_seq.wrapping_add(1),
+                    Vec::new(),
+                ));
+            }
+        }
+
+        for (from_client, flags, seq, ack, payload) in segments {
+            let (src, dst) = if from_client {
+                (flow.client, flow.proxy)
+            } else {
+                (flow.proxy, flow.client)
+            };
+            let packet = tcp_packet(src, dst, flags, seq, ack, &payload);
+            self.write_block(&enhanced_packet_block(message.at, &packet))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn write_block(&mut self, block: &[u8]) -> io::Result<()> {
+        let full = self.written + block.len() as u64 > self.max_file_size;
+        if self.file.is_none() || full {
+            self.rotate().await?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(block).await?;
+            self.written += block.len() as u64;
+        }
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+        }
+        fs::create_dir_all(&self.dir).await?;
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.files += 1;
+        let path = self
+            .dir
+            .join(format!("capture-{}-{:04}.pcapng", started, self.files));
+        let mut file = BufWriter::new(File::create(&path).await?);
+        let header = [section_header_block(), interface_description_block()].concat();
+        file.write_all(&header).await?;
+        println!("[CAPTURE] Writing {}", path.display());
+        self.written = header.len() as u64;
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padded = body.len().div_ceil(4) * 4;
+    let total = u32::try_from(12 + padded).unwrap_or(u32::MAX);
+    let mut block = Vec::with_capacity(12 + padded);
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&total.to_le_bytes());
+    block.extend_from_slice(body);
+    block.resize(8 + padded, 0);
+    block.extend_from_slice(&total.to_le_bytes());
+    block
+}
+
+fn section_header_block() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&0x1A2B_3C4D_u32.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    block(SECTION_HEADER_BLOCK, &body)
+}
+
+fn interface_description_block() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    block(INTERFACE_DESCRIPTION_BLOCK, &body)
+}
+
+// Timestamps use the default resolution of microseconds.
+fn enhanced_packet_block(at: SystemTime, packet: &[u8]) -> Vec<u8> {
+    let micros = at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros();
+    let micros = u64::try_from(micros).unwrap_or(u64::MAX);
+    let len = u32::try_from(packet.len()).unwrap_or(u32::MAX);
+    let mut body = Vec::with_capacity(20 + packet.len());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+    #[allow(clippy::cast_possible_truncation)]
+    body.extend_from_slice(&(micros as u32).to_le_bytes());
+    body.extend_from_slice(&len.to_le_bytes());
+    body.extend_from_slice(&len.to_le_bytes());
+    body.extend_from_slice(packet);
+    block(ENHANCED_PACKET_BLOCK, &body)
+}
+
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            let word = u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]);
+            sum += u32::from(word);
+        }
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let sum = sum as u16;
+    !sum
+}
+
+// One IPv4 or IPv6 packet carrying a TCP segment. Mixed address families, which a dual-stack
+// listener can produce, are written as IPv6 with IPv4-mapped addresses.
+fn tcp_packet(
+    src: SocketAddr,
+    dst: SocketAddr,
+    flags: u8,
+    seq: u32,
+    ack: u32,
+    payload: &[u8],
+) -> Vec<u8> {
+    let tcp_len = 20 + payload.len();
+    let mut tcp = Vec::with_capacity(tcp_len);
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    tcp.push(5 << 4);
+    tcp.push(flags);
+    tcp.extend_from_slice(&u16::MAX.to_be_bytes());
+    tcp.extend_from_slice(&[0, 0, 0, 0]);
+    tcp.extend_from_slice(payload);
+
+    let tcp_len_bytes = u16::try_from(tcp_len).unwrap_or(u16::MAX).to_be_bytes();
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let pseudo = [
+                &src.octets()[..],
+                &dst.octets()[..],
+                &[0, 6],
+                &tcp_len_bytes,
+            ]
+            .concat();
+            let sum = checksum(&[&pseudo, &tcp]);
+            tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+
+            let total_len = u16::try_from(20 + tcp_len).unwrap_or(u16::MAX);
+            let mut ip = vec![0x45, 0];
+            ip.extend_from_slice(&total_len.to_be_bytes());
+            ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+            ip.extend_from_slice(&src.octets());
+            ip.extend_from_slice(&dst.octets());
+            let sum = checksum(&[&ip]);
+            ip[10..12].copy_from_slice(&sum.to_be_bytes());
+            ip.extend_from_slice(&tcp);
+            ip
+        }
+        (src, dst) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let (src, dst) = (to_v6(src), to_v6(dst));
+            let pseudo = [
+                &src.octets()[..],
+                &dst.octets()[..],
+                &[0, 0],
+                &tcp_len_bytes,
+                &[0, 0, 0, 6],
+            ]
+            .concat();
+            let sum = checksum(&[&pseudo, &tcp]);
+            tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+
+            let mut ip = vec![0x60, 0, 0, 0];
+            ip.extend_from_slice(&tcp_len_bytes);
+            ip.extend_from_slice(&[6, 64]);
+            ip.extend_from_slice(&src.octets());
+            ip.extend_from_slice(&dst.octets());
+            ip.extend_from_slice(&tcp);
+            ip
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid CIDR '{0}'")]
+pub struct CidrParseError(String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn truncate(addr: IpAddr, prefix_len: u8) -> Self {
+        match addr.to_canonical() {
+            IpAddr::V4(v4) => {
+                let prefix_len = prefix_len.min(32);
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(prefix_len))
+                    .unwrap_or(0);
+                Self {
+                    addr: IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask)),
+                    prefix_len,
+                }
+            }
+            IpAddr::V6(v6) => Self::truncate_v6(v6, prefix_len),
+        }
+    }
+
+    fn truncate_v6(addr: Ipv6Addr, prefix_len: u8) -> Self {
+        let prefix_len = prefix_len.min(128);
+        let mask = u128::MAX
+            .checked_shl(128 - u32::from(prefix_len))
+            .unwrap_or(0);
+        Self {
+            addr: IpAddr::V6(Ipv6Addr::from(u128::from(addr) & mask)),
+            prefix_len,
+        }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        ip.is_ipv4() == self.addr.is_ipv4() && Self::truncate(ip, self.prefix_len) == *self
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || CidrParseError(s.to_string());
+        let (addr, prefix_len) = if let Some((addr, len)) = s.split_once('/') {
+            let addr: IpAddr = addr.parse().map_err(|_| err())?;
+            let len: u8 = len.parse().map_err(|_| err())?;
+            (addr, len)
+        } else {
+            let addr: IpAddr = s.parse().map_err(|_| err())?;
+            (addr, if addr.is_ipv4() { 32 } else { 128 })
+        };
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_len {
+            return Err(err());
+        }
+
+        // IPv4-mapped IPv6 ranges are matched as the IPv4 range they embed.
+        match addr {
+            IpAddr::V6(v6) if prefix_len >= 96 && v6.to_ipv4_mapped().is_some() => {
+                Ok(Self::truncate(addr, prefix_len - 96))
+            }
+            IpAddr::V6(v6) => Ok(Self::truncate_v6(v6, prefix_len)),
+            IpAddr::V4(_) => Ok(Self::truncate(addr, prefix_len)),
+        }
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = CidrParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
@@ -1,8 +1,20 @@
-use std::{fs, io, path::Path};
+use std::{
+    collections::HashSet,
+    fs, io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::{
+    Cidr, DestinationRule, Direction, ListenerAddr, METRICS_LISTENER, MUX_INITIAL_WINDOW,
+    SniffRegex, parse_access_list, unix_path,
+};
+
+pub const DEFAULT_ROUTE: &str = "default";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub listen_addr: String,
@@ -11,6 +23,684 @@ pub struct Config {
     pub grace_period_secs: u64,
     pub metrics_log_interval_secs: u64,
     pub channel_buffer_size: usize,
+    pub watch_config: bool,
+    pub watch_interval_secs: u64,
+    pub upgrade_socket: Option<PathBuf>,
+    pub limits: LimitsConfig,
+    pub bandwidth: BandwidthConfig,
+    pub access: AccessConfig,
+    pub toxics: Vec<ToxicConfig>,
+    pub mirror: Option<MirrorConfig>,
+    pub warm_pool: Option<WarmPoolConfig>,
+    pub socks5: Option<Socks5Config>,
+    pub http_connect: Option<HttpConnectConfig>,
+    pub sniff: Option<SniffConfig>,
+    pub http: Option<HttpProxyConfig>,
+    pub ws_tunnel: Option<WsTunnelConfig>,
+    pub mux: Option<MuxConfig>,
+    pub reverse_tunnel: Option<ReverseTunnelConfig>,
+    pub capture: CaptureConfig,
+    pub record: RecordConfig,
+    pub tap: TapConfig,
+    pub routes: Vec<RouteConfig>,
+    pub udp_routes: Vec<UdpRouteConfig>,
+    pub reverse_agents: Vec<ReverseAgentConfig>,
+    pub unix_socket: UnixSocketConfig,
+    pub sockets: SocketConfig,
+    pub resolver: ResolverConfig,
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RouteConfig {
+    pub name: String,
+    pub listen_addr: String,
+    pub target_addr: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub bandwidth: Option<RateLimit>,
+    #[serde(default)]
+    pub toxics: Vec<ToxicConfig>,
+    #[serde(default)]
+    pub mirror: Option<MirrorConfig>,
+    #[serde(default)]
+    pub warm_pool: Option<WarmPoolConfig>,
+    #[serde(default)]
+    pub socks5: Option<Socks5Config>,
+    #[serde(default)]
+    pub http_connect: Option<HttpConnectConfig>,
+    #[serde(default)]
+    pub sniff: Option<SniffConfig>,
+    #[serde(default)]
+    pub http: Option<HttpProxyConfig>,
+    #[serde(default)]
+    pub ws_tunnel: Option<WsTunnelConfig>,
+    #[serde(default)]
+    pub mux: Option<MuxConfig>,
+    #[serde(default)]
+    pub reverse_tunnel: Option<ReverseTunnelConfig>,
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            listen_addr: "127.0.0.1:0".to_string(),
+            target_addr: "127.0.0.1:0".to_string(),
+            enabled: true,
+            bandwidth: None,
+            toxics: Vec::new(),
+            mirror: None,
+            warm_pool: None,
+            socks5: None,
+            http_connect: None,
+            sniff: None,
+            http: None,
+            ws_tunnel: None,
+            mux: None,
+            reverse_tunnel: None,
+        }
+    }
+}
+
+impl RouteConfig {
+    // SOCKS5 and HTTP CONNECT clients pick their own destination instead of `target_addr`, and
+    // reverse tunnel connections go back to whichever agent registered their port.
+    pub fn has_fixed_target(&self) -> bool {
+        self.socks5.is_none() && self.http_connect.is_none() && self.reverse_tunnel.is_none()
+    }
+
+    // Every target this route may connect to, as written in the config.
+    pub fn targets(&self) -> Vec<String> {
+        let sniffed = self.sniff.iter().flat_map(|sniff| &sniff.rules);
+        let routed = self.http.iter().flat_map(|http| &http.rules);
+        self.has_fixed_target()
+            .then(|| self.target_addr.clone())
+            .into_iter()
+            .chain(sniffed.map(|rule| rule.target_addr.clone()))
+            .chain(routed.map(|rule| rule.target_addr.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpPreference {
+    #[default]
+    Ipv6First,
+    Ipv4First,
+    Ipv6Only,
+    Ipv4Only,
+}
+
+// Hostname targets are resolved through a cache that is refreshed in the background. Entries in
+// `hosts_file` (hosts(5) format) take precedence over the system resolver.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ResolverConfig {
+    pub hosts_file: Option<PathBuf>,
+    pub ttl_secs: u64,
+    pub refresh_interval_secs: u64,
+    pub ip_preference: IpPreference,
+    pub attempt_delay_ms: u64,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self {
+            hosts_file: None,
+            ttl_secs: 30,
+            refresh_interval_secs: 30,
+            ip_preference: IpPreference::default(),
+            attempt_delay_ms: 250,
+        }
+    }
+}
+
+// How `unix:` listen sockets are created and cleaned up. `owner` and `group` are numeric ids.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct UnixSocketConfig {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+    pub remove_existing: bool,
+    pub remove_on_close: bool,
+}
+
+impl Default for UnixSocketConfig {
+    fn default() -> Self {
+        Self {
+            mode: None,
+            owner: None,
+            group: None,
+            remove_existing: true,
+            remove_on_close: true,
+        }
+    }
+}
+
+// TCP socket options for accepted client connections and for connections to targets. Unset
+// options keep the OS defaults; Unix sockets ignore them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SocketConfig {
+    // Pending connections queued on each TCP listener; changes apply to listeners bound afterwards.
+    pub listen_backlog: u32,
+    // Connections that may wait in the TCP Fast Open queue of each TCP listener when
+    // `client.fastopen` is on.
+    pub fastopen_queue: u32,
+    pub client: SocketOptions,
+    pub upstream: SocketOptions,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            listen_backlog: 1024,
+            fastopen_queue: 256,
+            client: SocketOptions::default(),
+            upstream: SocketOptions::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SocketOptions {
+    pub nodelay: Option<bool>,
+    // TCP Fast Open: accepted on listeners for `client`, attempted by connects for `upstream`.
+    pub fastopen: Option<bool>,
+    pub keepalive: Option<KeepaliveConfig>,
+    pub recv_buffer_size: Option<usize>,
+    pub send_buffer_size: Option<usize>,
+    // Seconds close() waits for unsent data; 0 resets the connection instead.
+    pub linger_secs: Option<u64>,
+    // The whole TOS byte, or traffic class on IPv6: DSCP sits in the upper six bits, so EF (46)
+    // is 184.
+    pub tos: Option<u8>,
+    // Drops the connection once sent data goes unacknowledged this long.
+    pub user_timeout_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct KeepaliveConfig {
+    #[serde(default = "default_keepalive_idle")]
+    pub idle_secs: u64,
+    #[serde(default = "default_keepalive_interval")]
+    pub interval_secs: u64,
+    #[serde(default = "default_keepalive_count")]
+    pub count: u32,
+}
+
+fn default_keepalive_idle() -> u64 {
+    60
+}
+
+fn default_keepalive_interval() -> u64 {
+    10
+}
+
+fn default_keepalive_count() -> u32 {
+    6
+}
+
+// Datagrams are relayed per client: each client address gets its own upstream socket, which is
+// closed after `idle_timeout_secs` without traffic in either direction.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct UdpRouteConfig {
+    pub name: String,
+    pub listen_addr: String,
+    pub target_addr: String,
+    #[serde(default = "default_udp_idle_timeout")]
+    pub idle_timeout_secs: u64,
+}
+
+fn default_udp_idle_timeout() -> u64 {
+    60
+}
+
+// Copies the client's bytes to a shadow target. Up to `buffer_size` bytes wait for the shadow;
+// anything beyond that is dropped so a slow shadow never holds back the primary target.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MirrorConfig {
+    pub target_addr: String,
+    #[serde(default = "default_mirror_buffer_size")]
+    pub buffer_size: usize,
+}
+
+fn default_mirror_buffer_size() -> usize {
+    1024 * 1024
+}
+
+// Keeps `size` connections to the route's `target_addr` open ahead of time, so a client can be
+// paired with one without waiting for a connect. Connections idle for `max_idle_secs` are
+// replaced with fresh ones.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WarmPoolConfig {
+    pub size: usize,
+    #[serde(default = "default_warm_pool_max_idle")]
+    pub max_idle_secs: u64,
+}
+
+fn default_warm_pool_max_idle() -> u64 {
+    30
+}
+
+// Makes a route a SOCKS5 server: each client names its destination in the handshake and
+// `target_addr` is unused. With `users` set, clients must log in with one of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Socks5Config {
+    pub users: Vec<ProxyUser>,
+    pub allow: Vec<DestinationRule>,
+}
+
+// Makes a route an HTTP forward proxy that only tunnels: clients send `CONNECT host:port` and
+// `target_addr` is unused. With `users` set, requests need a matching Basic Proxy-Authorization.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct HttpConnectConfig {
+    pub users: Vec<ProxyUser>,
+    pub allow: Vec<DestinationRule>,
+}
+
+// Picks the target of each connection from its first bytes, so several services can share one
+// port. Rules are tried in order; connections that match none of them, or send nothing within
+// `peek_timeout_ms`, go to the route's `target_addr`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SniffConfig {
+    #[serde(default = "default_peek_timeout_ms")]
+    pub peek_timeout_ms: u64,
+    #[serde(default = "default_max_peek_bytes")]
+    pub max_peek_bytes: usize,
+    #[serde(default)]
+    pub rules: Vec<SniffRule>,
+}
+
+fn default_peek_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_max_peek_bytes() -> usize {
+    1024
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SniffRule {
+    pub target_addr: String,
+    #[serde(flatten)]
+    pub pattern: SniffPattern,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SniffPattern {
+    Protocol(SniffProtocol),
+    // Bytes the connection starts with.
+    Prefix(String),
+    // Matched against the bytes read so far; anchor it with `^` to match the start.
+    Regex(SniffRegex),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SniffProtocol {
+    Tls,
+    Http,
+    Ssh,
+    // HAProxy PROXY protocol, v1 or v2.
+    Proxy,
+}
+
+// Makes a route an HTTP/1.1 reverse proxy. Each request goes to the first rule matching its Host
+// header and path, or to the route's `target_addr` when none does.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HttpProxyConfig {
+    #[serde(default)]
+    pub rules: Vec<HttpRule>,
+    // Idle keep-alive connections kept per upstream.
+    #[serde(default = "default_pool_max_idle")]
+    pub pool_max_idle: usize,
+    #[serde(default = "default_pool_idle_timeout")]
+    pub pool_idle_timeout_secs: u64,
+}
+
+fn default_pool_max_idle() -> usize {
+    16
+}
+
+fn default_pool_idle_timeout() -> u64 {
+    60
+}
+
+impl Default for HttpProxyConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            pool_max_idle: default_pool_max_idle(),
+            pool_idle_timeout_secs: default_pool_idle_timeout(),
+        }
+    }
+}
+
+// `host` is a hostname or `*.domain` and `path_prefix` matches whole path segments; a rule
+// without one of them matches any value.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct HttpRule {
+    pub name: String,
+    #[serde(default)]
+    pub host: Option<String>,
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    pub target_addr: String,
+}
+
+// Carries connections inside WebSockets, for networks that only let HTTP out. The `client` side
+// accepts TCP and opens a WebSocket per connection to the tunnel server at `target_addr`; the
+// `server` side accepts those handshakes and dials its own `target_addr` for each of them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WsTunnelConfig {
+    pub side: TunnelSide,
+    // Sent and expected as `Authorization: Bearer <token>`.
+    pub token: String,
+    #[serde(default = "default_ws_tunnel_path")]
+    pub path: String,
+    // Host header sent by the client side, `target_addr` when unset.
+    #[serde(default)]
+    pub host: Option<String>,
+    // Client side: tries to reach the tunnel server before giving up on a connection, with the
+    // backoff doubling after each failure.
+    #[serde(default = "default_connect_attempts")]
+    pub connect_attempts: u32,
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+}
+
+fn default_ws_tunnel_path() -> String {
+    "/".to_string()
+}
+
+fn default_connect_attempts() -> u32 {
+    3
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    200
+}
+
+// Which end of a tunnel between two proxies a route is: the client side accepts connections and
+// carries them to the server side at its `target_addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TunnelSide {
+    Client,
+    Server,
+}
+
+// Carries many connections as streams over a few long-lived connections between two proxies,
+// saving a handshake per connection on slow links. The server side opens a connection to its
+// own `target_addr` for every stream.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct MuxConfig {
+    pub side: TunnelSide,
+    // Client side: connections kept open to the server side.
+    #[serde(default = "default_mux_connections")]
+    pub connections: usize,
+    // Bytes a stream may have in flight before the receiver reads them.
+    #[serde(default = "default_mux_window_size")]
+    pub window_size: u32,
+}
+
+fn default_mux_connections() -> usize {
+    2
+}
+
+fn default_mux_window_size() -> u32 {
+    MUX_INITIAL_WINDOW
+}
+
+// Makes a route the public side of reverse tunnels: agents behind NAT connect to its
+// `listen_addr` and register a service, which gets a public port of its own. Connections to that
+// port are carried back over the agent's connection. `target_addr` is unused.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ReverseTunnelConfig {
+    pub token: String,
+    // Address the public ports are opened on.
+    #[serde(default = "default_public_host")]
+    pub public_host: String,
+    // Inclusive; without it every service gets a port picked by the OS.
+    #[serde(default)]
+    pub port_range: Option<[u16; 2]>,
+}
+
+fn default_public_host() -> String {
+    "0.0.0.0".to_string()
+}
+
+// Runs inside the private network: keeps a connection to the relay at `relay_addr` registered as
+// service `name`, and connects each public connection the relay sends back to `target_addr`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ReverseAgentConfig {
+    pub name: String,
+    pub relay_addr: String,
+    pub target_addr: String,
+    pub token: String,
+    // Wait before reconnecting after losing the relay; doubles up to a minute.
+    #[serde(default = "default_reverse_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+}
+
+fn default_reverse_retry_backoff_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ProxyUser {
+    pub username: String,
+    pub password: String,
+}
+
+// Same shape as toxiproxy's toxics, so definitions can be copied between the two.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToxicConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ToxicKind,
+    #[serde(default = "default_toxic_stream")]
+    pub stream: Direction,
+    #[serde(default = "default_toxicity")]
+    pub toxicity: f64,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "attributes", rename_all = "snake_case")]
+pub enum ToxicKind {
+    // Milliseconds added to every chunk, +/- jitter.
+    Latency {
+        #[serde(default)]
+        latency: u64,
+        #[serde(default)]
+        jitter: u64,
+    },
+    // KB/s.
+    Bandwidth {
+        rate: u64,
+    },
+    // Bytes per slice and microseconds between slices.
+    Slicer {
+        average_size: usize,
+        #[serde(default)]
+        size_variation: usize,
+        #[serde(default)]
+        delay: u64,
+    },
+    // Drops all data and closes after `timeout` ms; 0 blackholes until the toxic is removed.
+    Timeout {
+        #[serde(default)]
+        timeout: u64,
+    },
+    // Resets the connection after `timeout` ms.
+    ResetPeer {
+        #[serde(default)]
+        timeout: u64,
+    },
+    // Chance of flipping a bit in each byte.
+    Corrupt {
+        probability: f64,
+    },
+}
+
+fn default_toxic_stream() -> Direction {
+    Direction::Downstream
+}
+
+fn default_toxicity() -> f64 {
+    1.0
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitAction {
+    #[default]
+    Close,
+    Queue,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub max_connections_per_prefix: Option<usize>,
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+    pub connections_per_sec_per_ip: Option<f64>,
+    pub connection_burst_per_ip: Option<u32>,
+    pub on_limit: LimitAction,
+    pub queue_size: usize,
+    pub queue_timeout_secs: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_connections_per_prefix: None,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 64,
+            connections_per_sec_per_ip: None,
+            connection_burst_per_ip: None,
+            on_limit: LimitAction::Close,
+            queue_size: 128,
+            queue_timeout_secs: 5,
+        }
+    }
+}
+
+// Bytes per second in each direction; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    pub upstream: Option<u64>,
+    pub downstream: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    pub per_connection: RateLimit,
+    pub per_client: RateLimit,
+    pub per_route: RateLimit,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessPolicy {
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessRule {
+    pub policy: AccessPolicy,
+    pub cidr: Cidr,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    pub default: AccessPolicy,
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    pub list_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub list_rules: Vec<AccessRule>,
+}
+
+// Relayed data of the listed routes and client networks is written to rotating pcapng files.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    pub dir: PathBuf,
+    pub max_file_size: u64,
+    pub routes: Vec<String>,
+    pub clients: Vec<Cidr>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("captures"),
+            max_file_size: 64 * 1024 * 1024,
+            routes: Vec::new(),
+            clients: Vec::new(),
+        }
+    }
+}
+
+// Connections on the listed routes are recorded, one file per connection, for later replay.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct RecordConfig {
+    pub dir: PathBuf,
+    pub routes: Vec<String>,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("recordings"),
+            routes: Vec::new(),
+        }
+    }
+}
+
+// Connections from the listed client networks have their first `max_bytes` logged as hex dumps.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct TapConfig {
+    pub clients: Vec<Cidr>,
+    pub max_bytes: usize,
+}
+
+impl Default for TapConfig {
+    fn default() -> Self {
+        Self {
+            clients: Vec::new(),
+            max_bytes: 4096,
+        }
+    }
 }
 
 impl Default for Config {
@@ -22,6 +712,54 @@ impl Default for Config {
             grace_period_secs: 60,
             metrics_log_interval_secs: 10,
             channel_buffer_size: 1000,
+            watch_config: false,
+            watch_interval_secs: 2,
+            upgrade_socket: None,
+            limits: LimitsConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            access: AccessConfig::default(),
+            toxics: Vec::new(),
+            mirror: None,
+            warm_pool: None,
+            socks5: None,
+            http_connect: None,
+            sniff: None,
+            http: None,
+            ws_tunnel: None,
+            mux: None,
+            reverse_tunnel: None,
+            capture: CaptureConfig::default(),
+            record: RecordConfig::default(),
+            tap: TapConfig::default(),
+            routes: Vec::new(),
+            udp_routes: Vec::new(),
+            reverse_agents: Vec::new(),
+            unix_socket: UnixSocketConfig::default(),
+            sockets: SocketConfig::default(),
+            resolver: ResolverConfig::default(),
+            path: None,
+        }
+    }
+}
+
+impl ToxicConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let invalid = |msg: &str| Err(format!("toxic '{}': {}", self.name, msg));
+        if self.name.is_empty() || self.name.contains('/') {
+            return invalid("name must be non-empty and must not contain '/'");
+        }
+        if !(0.0..=1.0).contains(&self.toxicity) {
+            return invalid("toxicity must be between 0 and 1");
+        }
+        match self.kind {
+            ToxicKind::Bandwidth { rate: 0 } => invalid("rate must be greater than 0"),
+            ToxicKind::Slicer {
+                average_size: 0, ..
+            } => invalid("average_size must be greater than 0"),
+            ToxicKind::Corrupt { probability } if !(0.0..=1.0).contains(&probability) => {
+                invalid("probability must be between 0 and 1")
+            }
+            _ => Ok(()),
         }
     }
 }
@@ -33,12 +771,508 @@ pub enum ConfigError {
 
     #[error("Failed to parse config: {0}")]
     Parse(#[from] toml::de::Error),
+
+    #[error("Invalid config: {0}")]
+    Invalid(String),
 }
 
 impl Config {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
-        let content = fs::read_to_string(path)?;
-        let config = toml::from_str(&content)?;
+        let content = fs::read_to_string(&path)?;
+        let mut config: Self = toml::from_str(&content)?;
+        config.path = Some(path.as_ref().to_path_buf());
+        config.load_access_list()?;
         Ok(config)
     }
+
+    // Relative list paths are resolved against the config file's directory.
+    pub fn access_list_path(&self) -> Option<PathBuf> {
+        let list_file = self.access.list_file.as_ref()?;
+        let base = self.path.as_ref().and_then(|path| path.parent());
+        Some(base.map_or_else(|| list_file.clone(), |base| base.join(list_file)))
+    }
+
+    // Like the access list, relative output dirs are resolved against the config file's directory.
+    fn resolve_path(&self, path: &Path) -> PathBuf {
+        let base = self.path.as_ref().and_then(|config| config.parent());
+        base.map_or_else(|| path.to_path_buf(), |base| base.join(path))
+    }
+
+    pub fn capture_dir(&self) -> PathBuf {
+        self.resolve_path(&self.capture.dir)
+    }
+
+    pub fn record_dir(&self) -> PathBuf {
+        self.resolve_path(&self.record.dir)
+    }
+
+    pub fn hosts_file_path(&self) -> Option<PathBuf> {
+        let hosts_file = self.resolver.hosts_file.as_ref()?;
+        Some(self.resolve_path(hosts_file))
+    }
+
+    pub fn load_access_list(&mut self) -> Result<(), ConfigError> {
+        self.access.list_rules = match self.access_list_path() {
+            Some(path) => parse_access_list(&fs::read_to_string(&path)?)
+                .map_err(|e| ConfigError::Invalid(format!("{}: {}", path.display(), e)))?,
+            None => Vec::new(),
+        };
+        Ok(())
+    }
+
+    pub fn watched_files(&self) -> Vec<PathBuf> {
+        self.path
+            .iter()
+            .cloned()
+            .chain(self.access_list_path())
+            .collect()
+    }
+
+    pub fn all_routes(&self) -> Vec<RouteConfig> {
+        let default = RouteConfig {
+            name: DEFAULT_ROUTE.to_string(),
+            listen_addr: self.listen_addr.clone(),
+            target_addr: self.target_addr.clone(),
+            enabled: true,
+            bandwidth: None,
+            toxics: self.toxics.clone(),
+            mirror: self.mirror.clone(),
+            warm_pool: self.warm_pool.clone(),
+            socks5: self.socks5.clone(),
+            http_connect: self.http_connect.clone(),
+            sniff: self.sniff.clone(),
+            http: self.http.clone(),
+            ws_tunnel: self.ws_tunnel.clone(),
+            mux: self.mux.clone(),
+            reverse_tunnel: self.reverse_tunnel.clone(),
+        };
+        std::iter::once(default)
+            .chain(self.routes.iter().cloned())
+            .collect()
+    }
+
+    pub fn enabled_routes(&self) -> Vec<RouteConfig> {
+        self.all_routes()
+            .into_iter()
+            .filter(|route| route.enabled)
+            .collect()
+    }
+
+    pub fn route(&self, name: &str) -> Option<RouteConfig> {
+        self.all_routes()
+            .into_iter()
+            .find(|route| route.name == name)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+
+        if self.metrics_addr.parse::<SocketAddr>().is_err() {
+            return invalid(format!(
+                "metrics_addr '{}' is not a socket address",
+                self.metrics_addr
+            ));
+        }
+        if self.channel_buffer_size == 0 {
+            return invalid("channel_buffer_size must be greater than 0".to_string());
+        }
+        if self.metrics_log_interval_secs == 0 {
+            return invalid("metrics_log_interval_secs must be greater than 0".to_string());
+        }
+        if self.watch_config && self.watch_interval_secs == 0 {
+            return invalid("watch_interval_secs must be greater than 0".to_string());
+        }
+
+        let limits = &self.limits;
+        if limits.ipv4_prefix_len > 32 || limits.ipv6_prefix_len > 128 {
+            return invalid("limits: prefix length out of range".to_string());
+        }
+        if limits
+            .connections_per_sec_per_ip
+            .is_some_and(|rate| !rate.is_finite() || rate <= 0.0)
+        {
+            return invalid("limits: connections_per_sec_per_ip must be positive".to_string());
+        }
+        if limits.on_limit == LimitAction::Queue && limits.queue_size == 0 {
+            return invalid("limits: queue_size must be greater than 0".to_string());
+        }
+
+        let bandwidth = &self.bandwidth;
+        let rate_limits = [
+            bandwidth.per_connection,
+            bandwidth.per_client,
+            bandwidth.per_route,
+        ];
+        let route_rate_limits = self.routes.iter().filter_map(|route| route.bandwidth);
+        for rate_limit in rate_limits.into_iter().chain(route_rate_limits) {
+            if rate_limit.upstream == Some(0) || rate_limit.downstream == Some(0) {
+                return invalid("bandwidth: rates must be greater than 0".to_string());
+            }
+        }
+
+        // Capture packets carry their own IP and TCP headers, so every block needs some room.
+        if self.capture.max_file_size < 4096 {
+            return invalid("capture: max_file_size must be at least 4096 bytes".to_string());
+        }
+
+        let mut names = HashSet::new();
+        let mut listen_addrs = HashSet::new();
+        for (i, route) in self.all_routes().iter().enumerate() {
+            if route.name.is_empty() || route.name.contains('/') {
+                return invalid(format!(
+                    "route name '{}' must be non-empty and must not contain '/'",
+                    route.name
+                ));
+            }
+            if i > 0 && [DEFAULT_ROUTE, METRICS_LISTENER].contains(&route.name.as_str()) {
+                return invalid(format!("route name '{}' is reserved", route.name));
+            }
+            if !names.insert(route.name.as_str()) {
+                return invalid(format!("duplicate route name '{}'", route.name));
+            }
+
+            // The default route's address is what `Proxy::new` hands back, so it stays TCP.
+            let unix_listener = i > 0 && unix_path(&route.listen_addr).is_some();
+            let listen_addr = route.listen_addr.parse::<SocketAddr>();
+            if !unix_listener && listen_addr.is_err() {
+                return invalid(format!(
+                    "route '{}': listen_addr '{}' is not a socket address{}",
+                    route.name,
+                    route.listen_addr,
+                    if i > 0 { " or unix:/path" } else { "" }
+                ));
+            }
+            let in_use = match (listen_addr, unix_path(&route.listen_addr)) {
+                (Ok(addr), _) => addr.port() != 0 && !listen_addrs.insert(ListenerAddr::Tcp(addr)),
+                (Err(_), Some(path)) => !listen_addrs.insert(ListenerAddr::Unix(path.into())),
+                (Err(_), None) => false,
+            };
+            if in_use {
+                return invalid(format!(
+                    "route '{}': listen_addr '{}' is already used by another route",
+                    route.name, route.listen_addr
+                ));
+            }
+
+            let mut toxic_names = HashSet::new();
+            for toxic in &route.toxics {
+                if !toxic_names.insert(toxic.name.as_str()) {
+                    return invalid(format!(
+                        "route '{}': duplicate toxic name '{}'",
+                        route.name, toxic.name
+                    ));
+                }
+                if let Err(e) = toxic.validate() {
+                    return invalid(format!("route '{}': {}", route.name, e));
+                }
+            }
+
+            let modes = [
+                route.socks5.is_some(),
+                route.http_connect.is_some(),
+                route.sniff.is_some(),
+                route.http.is_some(),
+                route.ws_tunnel.is_some(),
+                route.mux.is_some(),
+                route.reverse_tunnel.is_some(),
+            ];
+            if modes.into_iter().filter(|&mode| mode).count() > 1 {
+                return invalid(format!(
+                    "route '{}' can only use one of socks5, http_connect, sniff, http, ws_tunnel, mux and reverse_tunnel",
+                    route.name
+                ));
+            }
+            if let Some(socks5) = &route.socks5 {
+                // RFC 1929 sends both fields with a one-byte length.
+                let too_long = |user: &&ProxyUser| {
+                    user.username.is_empty()
+                        || user.username.len() > 255
+                        || user.password.len() > 255
+                };
+                if let Some(user) = socks5.users.iter().find(too_long) {
+                    return invalid(format!(
+                        "route '{}': socks5 user '{}' needs a username and both fields must be at most 255 bytes",
+                        route.name, user.username
+                    ));
+                }
+            }
+            if let Some(http_connect) = &route.http_connect {
+                // Basic credentials are `username:password`, split at the first colon.
+                let unusable =
+                    |user: &&ProxyUser| user.username.is_empty() || user.username.contains(':');
+                if let Some(user) = http_connect.users.iter().find(unusable) {
+                    return invalid(format!(
+                        "route '{}': http_connect user '{}' needs a username without ':'",
+                        route.name, user.username
+                    ));
+                }
+            }
+            if let Some(sniff) = &route.sniff {
+                if sniff.max_peek_bytes == 0 {
+                    return invalid(format!(
+                        "route '{}': sniff max_peek_bytes must be greater than 0",
+                        route.name
+                    ));
+                }
+                for rule in &sniff.rules {
+                    if !is_host_port(&rule.target_addr) && unix_path(&rule.target_addr).is_none() {
+                        return invalid(format!(
+                            "route '{}': sniff target_addr '{}' must be host:port or unix:/path",
+                            route.name, rule.target_addr
+                        ));
+                    }
+                    if matches!(&rule.pattern, SniffPattern::Prefix(prefix) if prefix.is_empty()) {
+                        return invalid(format!(
+                            "route '{}': sniff prefix must not be empty",
+                            route.name
+                        ));
+                    }
+                }
+            }
+            if let Some(http) = &route.http {
+                let mut rule_names = HashSet::from([DEFAULT_ROUTE]);
+                for rule in &http.rules {
+                    if rule.name.is_empty() || !rule_names.insert(rule.name.as_str()) {
+                        return invalid(format!(
+                            "route '{}': http rule name '{}' must be non-empty, unique and not '{}'",
+                            route.name, rule.name, DEFAULT_ROUTE
+                        ));
+                    }
+                    if rule
+                        .path_prefix
+                        .as_ref()
+                        .is_some_and(|prefix| !prefix.starts_with('/'))
+                    {
+                        return invalid(format!(
+                            "route '{}': http rule '{}' path_prefix must start with '/'",
+                            route.name, rule.name
+                        ));
+                    }
+                    if !is_host_port(&rule.target_addr) && unix_path(&rule.target_addr).is_none() {
+                        return invalid(format!(
+                            "route '{}': http rule '{}' target_addr '{}' must be host:port or unix:/path",
+                            route.name, rule.name, rule.target_addr
+                        ));
+                    }
+                }
+            }
+            if let Some(ws_tunnel) = &route.ws_tunnel {
+                if ws_tunnel.token.is_empty() {
+                    return invalid(format!(
+                        "route '{}': ws_tunnel token must not be empty",
+                        route.name
+                    ));
+                }
+                // The token goes into a header, the path into the request line.
+                if !ws_tunnel.token.bytes().all(|b| b.is_ascii_graphic()) {
+                    return invalid(format!(
+                        "route '{}': ws_tunnel token must be printable ASCII without spaces",
+                        route.name
+                    ));
+                }
+                if !ws_tunnel.path.starts_with('/') || ws_tunnel.path.parse::<hyper::Uri>().is_err()
+                {
+                    return invalid(format!(
+                        "route '{}': ws_tunnel path must be a valid URI path starting with '/'",
+                        route.name
+                    ));
+                }
+                if ws_tunnel
+                    .host
+                    .as_deref()
+                    .is_some_and(|host| hyper::header::HeaderValue::from_str(host).is_err())
+                {
+                    return invalid(format!(
+                        "route '{}': ws_tunnel host is not a valid header value",
+                        route.name
+                    ));
+                }
+                if ws_tunnel.connect_attempts == 0 {
+                    return invalid(format!(
+                        "route '{}': ws_tunnel connect_attempts must be greater than 0",
+                        route.name
+                    ));
+                }
+            }
+            if let Some(mux) = &route.mux {
+                if mux.connections == 0 {
+                    return invalid(format!(
+                        "route '{}': mux connections must be greater than 0",
+                        route.name
+                    ));
+                }
+                if mux.window_size < MUX_INITIAL_WINDOW {
+                    return invalid(format!(
+                        "route '{}': mux window_size must be at least {}",
+                        route.name, MUX_INITIAL_WINDOW
+                    ));
+                }
+            }
+            if let Some(reverse) = &route.reverse_tunnel {
+                if reverse.token.is_empty() {
+                    return invalid(format!(
+                        "route '{}': reverse_tunnel token must not be empty",
+                        route.name
+                    ));
+                }
+                if reverse.public_host.parse::<IpAddr>().is_err() {
+                    return invalid(format!(
+                        "route '{}': reverse_tunnel public_host '{}' is not an IP address",
+                        route.name, reverse.public_host
+                    ));
+                }
+                if let Some([first, last]) = reverse.port_range
+                    && (first == 0 || first > last)
+                {
+                    return invalid(format!(
+                        "route '{}': reverse_tunnel port_range must be [first, last] with 0 < first <= last",
+                        route.name
+                    ));
+                }
+            }
+            if route.has_fixed_target()
+                && !is_host_port(&route.target_addr)
+                && unix_path(&route.target_addr).is_none()
+            {
+                return invalid(format!(
+                    "route '{}': target_addr '{}' must be host:port or unix:/path",
+                    route.name, route.target_addr
+                ));
+            }
+            if let Some(warm_pool) = &route.warm_pool {
+                // Only plain routes connect to `target_addr` for every client.
+                if modes.contains(&true) {
+                    return invalid(format!(
+                        "route '{}': warm_pool can't be combined with socks5, http_connect, sniff, http, ws_tunnel, mux or reverse_tunnel",
+                        route.name
+                    ));
+                }
+                if warm_pool.size == 0 {
+                    return invalid(format!(
+                        "route '{}': warm_pool size must be greater than 0",
+                        route.name
+                    ));
+                }
+                if warm_pool.max_idle_secs == 0 {
+                    return invalid(format!(
+                        "route '{}': warm_pool max_idle_secs must be greater than 0",
+                        route.name
+                    ));
+                }
+            }
+            if let Some(mirror) = &route.mirror {
+                if !is_host_port(&mirror.target_addr) && unix_path(&mirror.target_addr).is_none() {
+                    return invalid(format!(
+                        "route '{}': mirror target_addr '{}' must be host:port or unix:/path",
+                        route.name, mirror.target_addr
+                    ));
+                }
+                if mirror.buffer_size == 0 {
+                    return invalid(format!(
+                        "route '{}': mirror buffer_size must be greater than 0",
+                        route.name
+                    ));
+                }
+            }
+        }
+
+        if self.resolver.refresh_interval_secs == 0 {
+            return invalid("resolver: refresh_interval_secs must be greater than 0".to_string());
+        }
+
+        if self.sockets.listen_backlog == 0 {
+            return invalid("sockets: listen_backlog must be greater than 0".to_string());
+        }
+        if self.sockets.client.fastopen == Some(true) && self.sockets.fastopen_queue == 0 {
+            return invalid("sockets: fastopen_queue must be greater than 0".to_string());
+        }
+        for (side, options) in [
+            ("client", &self.sockets.client),
+            ("upstream", &self.sockets.upstream),
+        ] {
+            if options.recv_buffer_size == Some(0) || options.send_buffer_size == Some(0) {
+                return invalid(format!(
+                    "sockets.{}: buffer sizes must be greater than 0",
+                    side
+                ));
+            }
+            if options.keepalive.as_ref().is_some_and(|keepalive| {
+                keepalive.idle_secs == 0 || keepalive.interval_secs == 0 || keepalive.count == 0
+            }) {
+                return invalid(format!(
+                    "sockets.{}: keepalive idle_secs, interval_secs and count must be greater than 0",
+                    side
+                ));
+            }
+        }
+        if self.unix_socket.mode.is_some_and(|mode| mode > 0o7777) {
+            return invalid("unix_socket: mode must be at most 0o7777".to_string());
+        }
+
+        let mut udp_names = HashSet::new();
+        for route in &self.udp_routes {
+            if route.name.is_empty() || route.name.contains('/') {
+                return invalid(format!(
+                    "udp route name '{}' must be non-empty and must not contain '/'",
+                    route.name
+                ));
+            }
+            if !udp_names.insert(route.name.as_str()) {
+                return invalid(format!("duplicate udp route name '{}'", route.name));
+            }
+            if route.listen_addr.parse::<SocketAddr>().is_err() {
+                return invalid(format!(
+                    "udp route '{}': listen_addr '{}' is not a socket address",
+                    route.name, route.listen_addr
+                ));
+            }
+            if !is_host_port(&route.target_addr) {
+                return invalid(format!(
+                    "udp route '{}': target_addr '{}' must be host:port",
+                    route.name, route.target_addr
+                ));
+            }
+            if route.idle_timeout_secs == 0 {
+                return invalid(format!(
+                    "udp route '{}': idle_timeout_secs must be greater than 0",
+                    route.name
+                ));
+            }
+        }
+
+        let mut agent_names = HashSet::new();
+        for agent in &self.reverse_agents {
+            // Sent on the registration line, which is split on whitespace.
+            if agent.name.is_empty() || agent.name.contains(char::is_whitespace) {
+                return invalid(format!(
+                    "reverse agent name '{}' must be non-empty and must not contain whitespace",
+                    agent.name
+                ));
+            }
+            if !agent_names.insert(agent.name.as_str()) {
+                return invalid(format!("duplicate reverse agent name '{}'", agent.name));
+            }
+            if agent.token.is_empty() || agent.token.contains(['\r', '\n']) {
+                return invalid(format!(
+                    "reverse agent '{}': token must be non-empty and a single line",
+                    agent.name
+                ));
+            }
+            for addr in [&agent.relay_addr, &agent.target_addr] {
+                if !is_host_port(addr) && unix_path(addr).is_none() {
+                    return invalid(format!(
+                        "reverse agent '{}': '{}' must be host:port or unix:/path",
+                        agent.name, addr
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn is_host_port(addr: &str) -> bool {
+    addr.rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
 }
@@ -0,0 +1,160 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::Cidr;
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid destination rule '{0}'")]
+pub struct DestinationRuleParseError(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    Any,
+    Network(Cidr),
+    // `*.example.com` matches subdomains of example.com, not example.com itself.
+    Suffix(String),
+    Exact(String),
+}
+
+// One allowlist entry for destinations chosen by the client: `host[:port]`, where host is `*`,
+// an IP or CIDR range, a hostname or a `*.` wildcard, and port is a number or `*`. IPv6
+// addresses need brackets when a port follows, e.g. `[2001:db8::/32]:443`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DestinationRule {
+    host: HostPattern,
+    port: Option<u16>,
+}
+
+impl DestinationRule {
+    // Hostname patterns only match hostname requests and CIDR patterns only match IP requests,
+    // so a name never gets in through whatever it currently resolves to.
+    pub fn matches(&self, host: &str, port: u16) -> bool {
+        if self.port.is_some_and(|allowed| allowed != port) {
+            return false;
+        }
+        let ip = host.trim_matches(['[', ']']).parse::<IpAddr>();
+        match (&self.host, ip) {
+            (HostPattern::Any, _) => true,
+            (HostPattern::Network(cidr), Ok(ip)) => cidr.contains(ip),
+            (HostPattern::Suffix(suffix), Err(_)) => {
+                let host = host.to_ascii_lowercase();
+                host.strip_suffix(suffix.as_str())
+                    .is_some_and(|label| label.ends_with('.') && label.len() > 1)
+            }
+            (HostPattern::Exact(name), Err(_)) => host.eq_ignore_ascii_case(name),
+            _ => false,
+        }
+    }
+}
+
+// An empty allowlist allows every destination; `reachable_addrs` still keeps internal ones out.
+pub fn destination_allowed(rules: &[DestinationRule], host: &str, port: u16) -> bool {
+    rules.is_empty() || rules.iter().any(|rule| rule.matches(host, port))
+}
+
+// Drops the internal addresses a destination resolved to, unless a rule other than `*` matches
+// it: a CIDR range for an IP destination, or the hostname the client asked for. Checked on the
+// resolved addresses, so a hostname can't lead into the proxy's own network either.
+pub fn reachable_addrs(
+    rules: &[DestinationRule],
+    host: &str,
+    port: u16,
+    addrs: Vec<SocketAddr>,
+) -> Vec<SocketAddr> {
+    let explicit = rules
+        .iter()
+        .any(|rule| rule.host != HostPattern::Any && rule.matches(host, port));
+    if explicit {
+        return addrs;
+    }
+    addrs
+        .into_iter()
+        .filter(|addr| !is_internal(addr.ip()))
+        .collect()
+}
+
+// Loopback, private, link-local and unspecified addresses, including IPv4-mapped ones.
+pub fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_unspecified()
+            }
+        },
+    }
+}
+
+impl FromStr for DestinationRule {
+    type Err = DestinationRuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || DestinationRuleParseError(s.to_string());
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (host, rest) = rest.split_once(']').ok_or_else(err)?;
+            match rest {
+                "" => (host, None),
+                _ => (host, Some(rest.strip_prefix(':').ok_or_else(err)?)),
+            }
+        } else if s.matches(':').count() > 1 {
+            (s, None)
+        } else {
+            match s.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            }
+        };
+        let port = match port {
+            None | Some("*") => None,
+            Some(port) => Some(port.parse::<u16>().map_err(|_| err())?),
+        };
+
+        let host = if host == "*" {
+            HostPattern::Any
+        } else if let Ok(cidr) = host.parse::<Cidr>() {
+            HostPattern::Network(cidr)
+        } else {
+            let name = host.to_ascii_lowercase();
+            let (name, wildcard) = match name.strip_prefix("*.") {
+                Some(name) => (name.to_string(), true),
+                None => (name, false),
+            };
+            let valid = !name.is_empty()
+                && name
+                    .split('.')
+                    .all(|label| !label.is_empty() && label.chars().all(is_label_char));
+            if !valid {
+                return Err(err());
+            }
+            if wildcard {
+                HostPattern::Suffix(name)
+            } else {
+                HostPattern::Exact(name)
+            }
+        };
+        Ok(Self { host, port })
+    }
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+impl TryFrom<String> for DestinationRule {
+    type Error = DestinationRuleParseError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
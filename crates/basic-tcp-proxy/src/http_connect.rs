@@ -0,0 +1,180 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http_body_util::Full;
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header,
+    server::conn::http1,
+    service::service_fn,
+    upgrade::{OnUpgrade, Upgraded},
+};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+
+use crate::{HttpConnectConfig, Resolver, Stream, destination_allowed, reachable_addrs};
+
+// Clients that take longer to send a request's headers are dropped.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Tunnel = (OnUpgrade, Stream, String);
+
+// Serves HTTP/1.1 on the client connection until a CONNECT request succeeds, then returns the
+// upgraded client stream, the connected destination and its `host:port`. Failed requests get an
+// error response and the client may try again; `None` means it left without a tunnel.
+pub async fn http_connect_handshake<S>(
+    client: S,
+    config: &HttpConnectConfig,
+    resolver: &Arc<Resolver>,
+    client_addr: SocketAddr,
+) -> Result<Option<(TokioIo<Upgraded>, Stream, String)>, hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (tunnel_tx, mut tunnel_rx) = mpsc::channel::<Tunnel>(1);
+    let config = Arc::new(config.clone());
+    let resolver = Arc::clone(resolver);
+    let service = service_fn(move |req| {
+        let config = Arc::clone(&config);
+        let resolver = Arc::clone(&resolver);
+        let tunnel_tx = tunnel_tx.clone();
+        async move {
+            Ok::<_, Infallible>(connect(req, &config, &resolver, client_addr, &tunnel_tx).await)
+        }
+    });
+
+    // Returns once the client is gone or, after a 200, once hyper has handed over the socket.
+    http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(HEADER_TIMEOUT)
+        .serve_connection(TokioIo::new(client), service)
+        .with_upgrades()
+        .await?;
+
+    let Ok((on_upgrade, stream, destination)) = tunnel_rx.try_recv() else {
+        return Ok(None);
+    };
+    let upgraded = on_upgrade.await?;
+    Ok(Some((TokioIo::new(upgraded), stream, destination)))
+}
+
+async fn connect(
+    req: Request<Incoming>,
+    config: &HttpConnectConfig,
+    resolver: &Resolver,
+    client_addr: SocketAddr,
+    tunnel_tx: &mpsc::Sender<Tunnel>,
+) -> Response<Full<Bytes>> {
+    if req.method() != Method::CONNECT {
+        return response(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, "CONNECT")
+            .body(Full::new(Bytes::from("Only CONNECT is supported\n")))
+            .expect("valid response");
+    }
+    if !authorized(&req, config) {
+        return response(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+            .header(header::PROXY_AUTHENTICATE, "Basic realm=\"proxy\"")
+            .body(Full::new(Bytes::new()))
+            .expect("valid response");
+    }
+
+    let Some((host, port)) = req
+        .uri()
+        .authority()
+        .and_then(|authority| Some((authority.host(), authority.port_u16()?)))
+    else {
+        return error(StatusCode::BAD_REQUEST, "CONNECT needs a host:port target");
+    };
+    let host = host.trim_matches(['[', ']']);
+    let destination = match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{}:{}", host, port),
+    };
+
+    if !destination_allowed(&config.allow, host, port) {
+        println!(
+            "[CONNECT] {} destination {} is not allowed",
+            client_addr, destination
+        );
+        return error(StatusCode::FORBIDDEN, "Destination not allowed");
+    }
+    // Connected through the resolver directly: a destination must never name a Unix socket.
+    let addrs = match resolver.resolve(&destination).await {
+        Ok(addrs) => reachable_addrs(&config.allow, host, port, addrs),
+        Err(e) => {
+            println!(
+                "[CONNECT] {} failed to resolve {}: {}",
+                client_addr, destination, e
+            );
+            return error(StatusCode::BAD_GATEWAY, "Destination unreachable");
+        }
+    };
+    if addrs.is_empty() {
+        println!(
+            "[CONNECT] {} destination {} is internal and not allowed",
+            client_addr, destination
+        );
+        return error(StatusCode::FORBIDDEN, "Destination not allowed");
+    }
+    match resolver.connect_addrs(addrs).await {
+        Ok(stream) => {
+            let tunnel = (hyper::upgrade::on(req), Stream::Tcp(stream), destination);
+            if tunnel_tx.try_send(tunnel).is_err() {
+                return error(StatusCode::BAD_REQUEST, "A tunnel is already open");
+            }
+            response(StatusCode::OK)
+                .body(Full::new(Bytes::new()))
+                .expect("valid response")
+        }
+        Err(e) => {
+            println!(
+                "[CONNECT] {} failed to connect to {}: {}",
+                client_addr, destination, e
+            );
+            error(StatusCode::BAD_GATEWAY, "Destination unreachable")
+        }
+    }
+}
+
+fn authorized(req: &Request<Incoming>, config: &HttpConnectConfig) -> bool {
+    if config.users.is_empty() {
+        return true;
+    }
+    let credentials = req
+        .headers()
+        .get(header::PROXY_AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let Some((username, password)) = credentials
+        .as_deref()
+        .and_then(|credentials| credentials.split_once(':'))
+    else {
+        return false;
+    };
+    config
+        .users
+        .iter()
+        .any(|user| user.username == username && user.password == password)
+}
+
+fn response(status: StatusCode) -> hyper::http::response::Builder {
+    Response::builder().status(status)
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    response(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Full::new(Bytes::from(format!("{}\n", message))))
+        .expect("valid response")
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    os::fd::{AsFd, OwnedFd},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use http_body_util::{BodyExt, Full, combinators::BoxBody};
+use hyper::{
+    HeaderMap, Request, Response, StatusCode, Uri,
+    body::{Bytes, Incoming},
+    client::conn::http1::{self as client_http1, SendRequest},
+    header::{self, HeaderName, HeaderValue},
+    server::conn::http1,
+    service::service_fn,
+    upgrade::{OnUpgrade, Upgraded},
+};
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    select,
+    sync::mpsc,
+};
+
+use crate::{
+    DEFAULT_ROUTE, HttpProxyConfig, HttpRule, MetricEvent, Resolver, RouteContext, Stream,
+};
+
+pub type ProxyBody = BoxBody<Bytes, hyper::Error>;
+
+// RFC 9110 section 7.6.1: these describe one connection and are never forwarded.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpstreamError {
+    #[error("failed to connect: {0}")]
+    Connect(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] hyper::Error),
+}
+
+// A WebSocket handshake the upstream accepted, waiting for hyper to hand over the client side.
+struct PendingUpgrade {
+    client: OnUpgrade,
+    upstream: TokioIo<Upgraded>,
+    upstream_socket: OwnedFd,
+    target_addr: String,
+}
+
+// A client connection switched to WebSocket, ready to be relayed byte for byte.
+pub struct WebSocket {
+    pub client: TokioIo<Upgraded>,
+    pub upstream: TokioIo<Upgraded>,
+    // The upgrade hides the upstream socket, so keep a handle to it for resetting the connection.
+    pub upstream_socket: OwnedFd,
+    pub target_addr: String,
+}
+
+struct IdleConnection {
+    sender: SendRequest<Incoming>,
+    since: Instant,
+}
+
+// Keep-alive connections to upstreams, shared by every HTTP route and keyed by target address.
+#[derive(Default)]
+pub struct HttpPool {
+    idle: Mutex<HashMap<String, Vec<IdleConnection>>>,
+}
+
+impl HttpPool {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    async fn checkout(
+        &self,
+        target_addr: &str,
+        config: &HttpProxyConfig,
+        resolver: &Resolver,
+    ) -> Result<SendRequest<Incoming>, UpstreamError> {
+        let idle_timeout = Duration::from_secs(config.pool_idle_timeout_secs);
+        if let Some(sender) = self.take_idle(target_addr, idle_timeout) {
+            return Ok(sender);
+        }
+        let stream = Stream::connect(target_addr, resolver).await?;
+        let (sender, connection) = client_http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+        Ok(sender)
+    }
+
+    fn take_idle(
+        &self,
+        target_addr: &str,
+        idle_timeout: Duration,
+    ) -> Option<SendRequest<Incoming>> {
+        let mut idle = self.idle.lock().expect("http pool poisoned");
+        let connections = idle.get_mut(target_addr)?;
+        connections.retain(|conn| !conn.sender.is_closed() && conn.since.elapsed() < idle_timeout);
+        // A connection still streaming a response body is not ready yet and stays pooled.
+        let i = connections.iter().position(|conn| conn.sender.is_ready())?;
+        Some(connections.swap_remove(i).sender)
+    }
+
+    // Called as soon as the response head arrives; the connection is reused once its body is done.
+    fn checkin(&self, target_addr: &str, sender: SendRequest<Incoming>, max_idle: usize) {
+        let mut idle = self.idle.lock().expect("http pool poisoned");
+        let connections = idle.entry(target_addr.to_string()).or_default();
+        connections.retain(|conn| !conn.sender.is_closed());
+        if connections.len() < max_idle {
+            connections.push(IdleConnection {
+                sender,
+                since: Instant::now(),
+            });
+        }
+    }
+}
+
+// Serves HTTP/1.1 requests on a client connection, with keep-alive, until the client leaves or
+// the route drains. A WebSocket upgrade ends the HTTP exchange and is returned for relaying.
+pub async fn serve_http<S>(
+    client: S,
+    ctx: &RouteContext,
+    client_addr: SocketAddr,
+) -> Result<Option<WebSocket>, hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (upgrade_tx, mut upgrade_rx) = mpsc::channel::<PendingUpgrade>(1);
+    let service_ctx = ctx.clone();
+    let service = service_fn(move |req| {
+        let ctx = service_ctx.clone();
+        let upgrade_tx = upgrade_tx.clone();
+        async move { Ok::<_, Infallible>(proxy_request(req, &ctx, client_addr, &upgrade_tx).await) }
+    });
+    let connection = http1::Builder::new()
+        .serve_connection(TokioIo::new(client), service)
+        .with_upgrades();
+    tokio::pin!(connection);
+    select! {
+        result = connection.as_mut() => result?,
+        _ = ctx.graceful_token.cancelled() => {
+            // Finishes the request in flight, then closes instead of waiting for the next one.
+            connection.as_mut().graceful_shutdown();
+            connection.await?;
+        }
+    }
+
+    let Ok(pending) = upgrade_rx.try_recv() else {
+        return Ok(None);
+    };
+    let client = pending.client.await?;
+    Ok(Some(WebSocket {
+        client: TokioIo::new(client),
+        upstream: pending.upstream,
+        upstream_socket: pending.upstream_socket,
+        target_addr: pending.target_addr,
+    }))
+}
+
+async fn proxy_request(
+    mut req: Request<Incoming>,
+    ctx: &RouteContext,
+    client_addr: SocketAddr,
+    upgrade_tx: &mpsc::Sender<PendingUpgrade>,
+) -> Response<ProxyBody> {
+    let config = ctx.config_rx.borrow().clone();
+    let Some(route) = config.route(&ctx.route) else {
+        return error(StatusCode::SERVICE_UNAVAILABLE);
+    };
+    let http = route.http.unwrap_or_default();
+    let host = request_host(&req).unwrap_or_default();
+    let path = req.uri().path().to_string();
+    let (rule_name, target_addr) = match http
+        .rules
+        .iter()
+        .find(|rule| rule_matches(rule, &host, &path))
+    {
+        Some(rule) => (rule.name.as_str(), rule.target_addr.as_str()),
+        None => (DEFAULT_ROUTE, route.target_addr.as_str()),
+    };
+    let rule_key = format!("{}/{}", ctx.route, rule_name);
+    let method = req.method().clone();
+
+    let websocket = websocket_upgrade(req.headers());
+    prepare_request(&mut req, &host, client_addr.ip());
+    let result = match websocket {
+        Some(protocol) => {
+            restore_upgrade(req.headers_mut(), protocol);
+            forward_upgrade(req, target_addr, ctx, upgrade_tx).await
+        }
+        None => forward(req, target_addr, &http, ctx).await,
+    };
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("[HTTP] {} failed to reach {}: {}", rule_key, target_addr, e);
+            error(StatusCode::BAD_GATEWAY)
+        }
+    };
+
+    let status = response.status().as_u16();
+    println!(
+        "[HTTP] {} {} {}{} -> {} {}",
+        client_addr, method, host, path, rule_key, status
+    );
+    let _ = ctx
+        .metrics_tx
+        .send(MetricEvent::HttpResponse(client_addr, rule_key, status))
+        .await;
+    response
+}
+
+async fn forward(
+    req: Request<Incoming>,
+    target_addr: &str,
+    config: &HttpProxyConfig,
+    ctx: &RouteContext,
+) -> Result<Response<ProxyBody>, UpstreamError> {
+    let mut sender = ctx
+        .http_pool
+        .checkout(target_addr, config, &ctx.resolver)
+        .await?;
+    let mut response = sender.send_request(req).await?;
+    ctx.http_pool
+        .checkin(target_addr, sender, config.pool_max_idle);
+    strip_hop_by_hop(response.headers_mut());
+    Ok(response.map(BodyExt::boxed))
+}
+
+// Upgrades take over their upstream connection, so they get a fresh one instead of a pooled one.
+async fn forward_upgrade(
+    mut req: Request<Incoming>,
+    target_addr: &str,
+    ctx: &RouteContext,
+    upgrade_tx: &mpsc::Sender<PendingUpgrade>,
+) -> Result<Response<ProxyBody>, UpstreamError> {
+    let client = hyper::upgrade::on(&mut req);
+    let stream = Stream::connect(target_addr, &ctx.resolver).await?;
+    let upstream_socket = stream.as_fd().try_clone_to_owned()?;
+    let (mut sender, connection) = client_http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        let _ = connection.with_upgrades().await;
+    });
+
+    let mut response = sender.send_request(req).await?;
+    let protocol = response.headers().get(header::UPGRADE).cloned();
+    strip_hop_by_hop(response.headers_mut());
+    // Anything else is the upstream refusing the handshake, passed on like any other response.
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS
+        && let Some(protocol) = protocol
+    {
+        let upstream = hyper::upgrade::on(&mut response).await?;
+        restore_upgrade(response.headers_mut(), protocol);
+        let _ = upgrade_tx.try_send(PendingUpgrade {
+            client,
+            upstream: TokioIo::new(upstream),
+            upstream_socket,
+            target_addr: target_addr.to_string(),
+        });
+    }
+    Ok(response.map(BodyExt::boxed))
+}
+
+// The `Upgrade` value of a WebSocket handshake; other upgrades are stripped like any hop-by-hop
+// header.
+fn websocket_upgrade(headers: &HeaderMap) -> Option<HeaderValue> {
+    let connection_upgrade = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"));
+    let upgrade = headers.get(header::UPGRADE)?;
+    let websocket = upgrade
+        .to_str()
+        .ok()?
+        .split(',')
+        .any(|protocol| protocol.trim().eq_ignore_ascii_case("websocket"));
+    (connection_upgrade && websocket).then(|| upgrade.clone())
+}
+
+fn restore_upgrade(headers: &mut HeaderMap, protocol: HeaderValue) {
+    headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+    headers.insert(header::UPGRADE, protocol);
+}
+
+fn request_host(req: &Request<Incoming>) -> Option<String> {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            req.uri()
+                .authority()
+                .map(|authority| authority.as_str().to_string())
+        })?;
+    Some(host.to_ascii_lowercase())
+}
+
+fn host_without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    }
+}
+
+fn rule_matches(rule: &HttpRule, host: &str, path: &str) -> bool {
+    let name = host_without_port(host);
+    let host_matches = rule.host.as_deref().is_none_or(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => name
+                .strip_suffix(suffix)
+                .is_some_and(|label| label.ends_with('.') && label.len() > 1),
+            None => name == pattern,
+        }
+    });
+    // `/api` matches `/api` and `/api/users` but not `/apis`.
+    let path_matches = rule.path_prefix.as_deref().is_none_or(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'))
+    });
+    host_matches && path_matches
+}
+
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+}
+
+// Requests go upstream in origin form, with the client recorded in both the de facto
+// X-Forwarded-* headers and RFC 7239 Forwarded.
+fn prepare_request(req: &mut Request<Incoming>, host: &str, client_ip: IpAddr) {
+    let origin_form = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str())
+        .parse::<Uri>()
+        .unwrap_or_else(|_| Uri::from_static("/"));
+    *req.uri_mut() = origin_form;
+
+    let headers = req.headers_mut();
+    strip_hop_by_hop(headers);
+
+    let client_ip = client_ip.to_canonical();
+    append(headers, "x-forwarded-for", &client_ip.to_string());
+    if !headers.contains_key("x-forwarded-proto") {
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("http"));
+    }
+    if !host.is_empty()
+        && !headers.contains_key("x-forwarded-host")
+        && let Ok(value) = HeaderValue::from_str(host)
+    {
+        headers.insert("x-forwarded-host", value);
+    }
+
+    let node = match client_ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    };
+    let mut forwarded = format!("for={}", node);
+    if !host.is_empty() {
+        let _ = write!(forwarded, ";host=\"{}\"", host);
+    }
+    forwarded.push_str(";proto=http");
+    append(headers, "forwarded", &forwarded);
+}
+
+// Adds to a list-valued header, keeping what earlier proxies wrote.
+fn append(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    let combined = match headers
+        .get(name)
+        .and_then(|existing| existing.to_str().ok())
+    {
+        Some(existing) => format!("{}, {}", existing, value),
+        None => value.to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&combined) {
+        headers.insert(name, value);
+    }
+}
+
+fn error(status: StatusCode) -> Response<ProxyBody> {
+    let reason = status.canonical_reason().unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(
+            Full::new(Bytes::from(format!("{}\n", reason)))
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .expect("valid response")
+}
@@ -1,10 +1,19 @@
-use http_body_util::Full;
+use http_body_util::{BodyExt, Full};
 use hyper::{Request, Response, StatusCode, body::Bytes, server::conn::http1, service::service_fn};
 use hyper_util::rt::TokioIo;
-use tokio::{net::TcpListener, select, sync::watch};
+use tokio::{
+    net::TcpListener,
+    select,
+    sync::{mpsc, watch},
+};
 use tokio_util::sync::CancellationToken;
 
-use crate::{AppError, MetricsSnapshot};
+use std::sync::Arc;
+
+use crate::{
+    AdminRequest, AppError, CaptureRegistry, Cidr, MetricsSnapshot, ToxicRegistry,
+    handle_toxiproxy_request, is_toxiproxy_path,
+};
 
 fn parse_format_param(uri: &hyper::Uri) -> &str {
     uri.query()
@@ -17,10 +26,98 @@ fn parse_format_param(uri: &hyper::Uri) -> &str {
         .unwrap_or("text")
 }
 
-fn handle_http_request(
-    req: &Request<hyper::body::Incoming>,
+fn json_response<T: serde::Serialize>(value: &T) -> Result<Response<Full<Bytes>>, AppError> {
+    let response = Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(serde_json::to_string(value)?)))?;
+    Ok(response)
+}
+
+fn not_found() -> Result<Response<Full<Bytes>>, AppError> {
+    let response = Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Full::new(Bytes::from("Not found")))?;
+    Ok(response)
+}
+
+// POST /toxics/{route}/{name}/enable and /disable
+fn toggle_toxic(path: &str, toxics: &ToxicRegistry) -> Result<Response<Full<Bytes>>, AppError> {
+    let mut parts = path.trim_start_matches("/toxics/").split('/');
+    let (Some(route), Some(name), Some(action), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return not_found();
+    };
+    let enabled = match action {
+        "enable" => true,
+        "disable" => false,
+        _ => return not_found(),
+    };
+    match toxics.set_enabled(route, name, enabled) {
+        Some(toxic) => {
+            println!(
+                "[TOXICS] {} toxic '{}' on route '{}'",
+                if enabled { "Enabled" } else { "Disabled" },
+                name,
+                route
+            );
+            json_response(&toxic)
+        }
+        None => not_found(),
+    }
+}
+
+// POST /capture/routes/{route}/enable and /capture/clients/{cidr}/enable, or /disable
+fn toggle_capture(
+    path: &str,
+    capture: &CaptureRegistry,
+) -> Result<Response<Full<Bytes>>, AppError> {
+    let Some((target, action)) = path.trim_start_matches("/capture/").rsplit_once('/') else {
+        return not_found();
+    };
+    let enabled = match action {
+        "enable" => true,
+        "disable" => false,
+        _ => return not_found(),
+    };
+    let filter = if let Some(route) = target.strip_prefix("routes/") {
+        if route.is_empty() || route.contains('/') {
+            return not_found();
+        }
+        capture.set_route(route, enabled)
+    } else if let Some(cidr) = target.strip_prefix("clients/") {
+        let Ok(cidr) = cidr.parse::<Cidr>() else {
+            let response = Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Full::new(Bytes::from(format!("invalid CIDR '{}'", cidr))))?;
+            return Ok(response);
+        };
+        capture.set_client(cidr, enabled)
+    } else {
+        return not_found();
+    };
+    println!(
+        "[CAPTURE] {} capture for {}",
+        if enabled { "Enabled" } else { "Disabled" },
+        target
+    );
+    json_response(&filter)
+}
+
+async fn handle_http_request(
+    req: Request<hyper::body::Incoming>,
     metrics_rx: &watch::Receiver<MetricsSnapshot>,
+    toxics: &ToxicRegistry,
+    capture: &CaptureRegistry,
+    admin_tx: &mpsc::Sender<AdminRequest>,
 ) -> Result<Response<Full<Bytes>>, AppError> {
+    if is_toxiproxy_path(req.uri().path()) {
+        let (parts, body) = req.into_parts();
+        let body = body.collect().await?.to_bytes();
+        return handle_toxiproxy_request(&parts.method, parts.uri.path(), &body, toxics, admin_tx)
+            .await;
+    }
+
     match (req.method(), req.uri().path()) {
         (&hyper::Method::GET, "/") => {
             let response = Response::builder()
@@ -46,18 +143,22 @@ fn handle_http_request(
 
             Ok(res)
         }
-        _ => {
-            let response = Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Full::new(Bytes::from("Not found")))?;
-            Ok(response)
+        (&hyper::Method::GET, "/toxics") => json_response(&*toxics.snapshot()),
+        (&hyper::Method::POST, path) if path.starts_with("/toxics/") => toggle_toxic(path, toxics),
+        (&hyper::Method::GET, "/capture") => json_response(&capture.filter()),
+        (&hyper::Method::POST, path) if path.starts_with("/capture/") => {
+            toggle_capture(path, capture)
         }
+        _ => not_found(),
     }
 }
 
 pub async fn http_server(
     listener: TcpListener,
     metrics_rx: watch::Receiver<MetricsSnapshot>,
+    toxics: Arc<ToxicRegistry>,
+    capture: Arc<CaptureRegistry>,
+    admin_tx: mpsc::Sender<AdminRequest>,
     graceful_token: CancellationToken,
 ) -> Result<(), AppError> {
     loop {
@@ -67,10 +168,18 @@ pub async fn http_server(
                 let io = TokioIo::new(stream);
 
                 let metrics_rx = metrics_rx.clone();
+                let toxics = Arc::clone(&toxics);
+                let capture = Arc::clone(&capture);
+                let admin_tx = admin_tx.clone();
 
                 let service = service_fn(move |req| {
                     let metrics_rx = metrics_rx.clone();
-                    async move { handle_http_request(&req, &metrics_rx) }
+                    let toxics = Arc::clone(&toxics);
+                    let capture = Arc::clone(&capture);
+                    let admin_tx = admin_tx.clone();
+                    async move {
+                        handle_http_request(req, &metrics_rx, &toxics, &capture, &admin_tx).await
+                    }
                 });
 
                 tokio::spawn(async move {
@@ -1,11 +1,65 @@
+pub mod access;
+pub mod capture;
+pub mod cidr;
 pub mod config;
+pub mod destination;
+pub mod http_connect;
+pub mod http_proxy;
 pub mod http_server;
+pub mod limits;
 pub mod metrics;
+pub mod mirror;
+pub mod mux;
 pub mod proxy;
+pub mod recording;
 pub mod relay;
+pub mod reload;
+pub mod resolver;
+pub mod reverse_tunnel;
+pub mod shaping;
+pub mod sniff;
+pub mod socket_options;
+pub mod socks5;
+pub mod stream;
+pub mod systemd;
+pub mod tap;
+pub mod toxics;
+pub mod toxiproxy;
+pub mod udp;
+pub mod upgrade;
+pub mod warm_pool;
+pub mod websocket;
+pub mod ws_tunnel;
 
+pub use access::*;
+pub use capture::*;
+pub use cidr::*;
 pub use config::*;
+pub use destination::*;
+pub use http_connect::*;
+pub use http_proxy::*;
 pub use http_server::*;
+pub use limits::*;
 pub use metrics::*;
+pub use mirror::*;
+pub use mux::*;
 pub use proxy::*;
+pub use recording::*;
 pub use relay::*;
+pub use reload::*;
+pub use resolver::*;
+pub use reverse_tunnel::*;
+pub use shaping::*;
+pub use sniff::*;
+pub use socket_options::*;
+pub use socks5::*;
+pub use stream::*;
+pub use systemd::*;
+pub use tap::*;
+pub use toxics::*;
+pub use toxiproxy::*;
+pub use udp::*;
+pub use upgrade::*;
+pub use warm_pool::*;
+pub use websocket::*;
+pub use ws_tunnel::*;
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{select, sync::Notify, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{Cidr, LimitAction, LimitsConfig};
+
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const SWEEP_EVERY: u64 = 1024;
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, capacity: f64) -> Self {
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn matches(&self, rate: f64, capacity: f64) -> bool {
+        self.rate.total_cmp(&rate).is_eq() && self.capacity.total_cmp(&capacity).is_eq()
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    pub fn try_take(&mut self, amount: f64) -> bool {
+        self.refill();
+        if self.tokens >= amount {
+            self.tokens -= amount;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn wait_time(&mut self, amount: f64) -> Duration {
+        self.refill();
+        let missing = amount.min(self.capacity) - self.tokens;
+        if missing <= 0.0 || self.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.rate)
+        }
+    }
+
+    // Takes the tokens even if that leaves the bucket in debt and returns how long the caller
+    // should wait for the debt to be paid off.
+    pub fn reserve(&mut self, amount: f64) -> Duration {
+        self.refill();
+        self.tokens -= amount;
+        if self.tokens >= 0.0 || self.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    pub fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    MaxConnections,
+    PerIp,
+    PerPrefix,
+    RateLimit,
+    QueueFull,
+    QueueTimeout,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::MaxConnections => "max_connections",
+            Self::PerIp => "per_ip",
+            Self::PerPrefix => "per_prefix",
+            Self::RateLimit => "rate_limit",
+            Self::QueueFull => "queue_full",
+            Self::QueueTimeout => "queue_timeout",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Default)]
+struct ClientState {
+    active: usize,
+    bucket: Option<TokenBucket>,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    active: usize,
+    queued: usize,
+    clients: HashMap<IpAddr, ClientState>,
+    prefixes: HashMap<Cidr, usize>,
+    acquisitions: u64,
+}
+
+impl LimiterState {
+    fn sweep(&mut self) {
+        self.clients.retain(|_, client| {
+            client.active > 0 || client.bucket.as_mut().is_some_and(|b| !b.is_full())
+        });
+        self.prefixes.retain(|_, active| *active > 0);
+    }
+}
+
+#[derive(Default)]
+pub struct ConnectionLimiter {
+    state: Mutex<LimiterState>,
+    released: Notify,
+}
+
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+    prefix: Cidr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip, self.prefix);
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub async fn admit(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        limits: &LimitsConfig,
+        cancel: &CancellationToken,
+    ) -> Result<ConnectionPermit, RejectReason> {
+        let ip = ip.to_canonical();
+        match self.try_acquire(ip, limits) {
+            Ok(permit) => return Ok(permit),
+            Err((reason, _)) if limits.on_limit == LimitAction::Close => return Err(reason),
+            Err(_) => {}
+        }
+
+        {
+            let mut state = self.state.lock().expect("limiter state poisoned");
+            if state.queued >= limits.queue_size {
+                return Err(RejectReason::QueueFull);
+            }
+            state.queued += 1;
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(limits.queue_timeout_secs);
+        let result = loop {
+            match self.try_acquire(ip, limits) {
+                Ok(permit) => break Ok(permit),
+                Err((reason, retry_after)) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        break Err(RejectReason::QueueTimeout);
+                    }
+                    // Polling covers wakeups missed between try_acquire and notified().
+                    let wait = retry_after
+                        .unwrap_or(QUEUE_POLL_INTERVAL)
+                        .min(QUEUE_POLL_INTERVAL)
+                        .min(remaining);
+                    select! {
+                        _ = self.released.notified() => {}
+                        _ = sleep(wait) => {}
+                        _ = cancel.cancelled() => break Err(reason),
+                    }
+                }
+            }
+        };
+
+        self.state.lock().expect("limiter state poisoned").queued -= 1;
+        result
+    }
+
+    fn try_acquire(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        limits: &LimitsConfig,
+    ) -> Result<ConnectionPermit, (RejectReason, Option<Duration>)> {
+        let prefix_len = if ip.is_ipv4() {
+            limits.ipv4_prefix_len
+        } else {
+            limits.ipv6_prefix_len
+        };
+        let prefix = Cidr::truncate(ip, prefix_len);
+
+        let mut guard = self.state.lock().expect("limiter state poisoned");
+        let state = &mut *guard;
+        state.acquisitions += 1;
+        if state.acquisitions.is_multiple_of(SWEEP_EVERY) {
+            state.sweep();
+        }
+
+        if limits
+            .max_connections
+            .is_some_and(|max| state.active >= max)
+        {
+            return Err((RejectReason::MaxConnections, None));
+        }
+        let client = state.clients.entry(ip).or_default();
+        if limits
+            .max_connections_per_ip
+            .is_some_and(|max| client.active >= max)
+        {
+            return Err((RejectReason::PerIp, None));
+        }
+        let prefix_active = state.prefixes.get(&prefix).copied().unwrap_or(0);
+        if limits
+            .max_connections_per_prefix
+            .is_some_and(|max| prefix_active >= max)
+        {
+            return Err((RejectReason::PerPrefix, None));
+        }
+        match limits.connections_per_sec_per_ip {
+            Some(rate) => {
+                let burst = f64::from(limits.connection_burst_per_ip.unwrap_or(1).max(1));
+                let bucket = match &mut client.bucket {
+                    Some(bucket) if bucket.matches(rate, burst) => bucket,
+                    slot => slot.insert(TokenBucket::new(rate, burst)),
+                };
+                if !bucket.try_take(1.0) {
+                    return Err((RejectReason::RateLimit, Some(bucket.wait_time(1.0))));
+                }
+            }
+            None => client.bucket = None,
+        }
+
+        client.active += 1;
+        *state.prefixes.entry(prefix).or_default() += 1;
+        state.active += 1;
+
+        Ok(ConnectionPermit {
+            limiter: Arc::clone(self),
+            ip,
+            prefix,
+        })
+    }
+
+    fn release(&self, ip: IpAddr, prefix: Cidr) {
+        {
+            let mut state = self.state.lock().expect("limiter state poisoned");
+            state.active = state.active.saturating_sub(1);
+            if let Some(active) = state.prefixes.get_mut(&prefix) {
+                *active = active.saturating_sub(1);
+            }
+            if let Some(client) = state.clients.get_mut(&ip) {
+                client.active = client.active.saturating_sub(1);
+            }
+        }
+        self.released.notify_waiters();
+    }
+}
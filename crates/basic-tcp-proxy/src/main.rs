@@ -2,7 +2,10 @@ use basic_tcp_proxy::{Config, Proxy};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = Config::from_file("proxy.toml").unwrap_or_default();
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "proxy.toml".to_string());
+    let config = Config::from_file(path).unwrap_or_default();
 
     let (mut proxy, _) = Proxy::new(config).await?;
     proxy.run().await?;
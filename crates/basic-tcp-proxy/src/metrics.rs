@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{collections::BTreeMap, fmt::Write, net::SocketAddr, time::Duration};
 
 use tokio::{
     select,
@@ -6,12 +6,36 @@ use tokio::{
     time::interval,
 };
 
-#[derive(Debug, Clone, Copy)]
+use crate::{Cidr, RejectReason};
+
+#[derive(Debug, Clone)]
 pub enum MetricEvent {
     ConnectionOpened(SocketAddr),
     ConnectionClosed(SocketAddr),
+    ConnectionRejected(SocketAddr, RejectReason),
+    ConnectionDenied(SocketAddr, Option<Cidr>),
     BytesUpstream(SocketAddr, u64),
     BytesDownstream(SocketAddr, u64),
+    BytesMirrored(SocketAddr, u64),
+    BytesMirrorDropped(SocketAddr, u64),
+    // An HTTP request answered through the `route/rule` key, with its status code.
+    HttpResponse(SocketAddr, String, u16),
+    // Physical connections carrying multiplexed streams, and the logical streams inside them.
+    MuxSessionOpened,
+    MuxSessionClosed,
+    MuxStreamOpened(SocketAddr),
+    MuxStreamClosed(SocketAddr),
+    // Whether a client found a pre-established connection to its target waiting.
+    WarmPoolHit(SocketAddr),
+    WarmPoolMiss(SocketAddr),
+    ConfigReloaded,
+    ConfigRejected,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct HttpRuleStats {
+    pub requests: u64,
+    pub statuses: BTreeMap<u16, u64>,
 }
 
 #[derive(Debug, Clone, Default, serde::Serialize)]
@@ -20,17 +44,65 @@ pub struct MetricsSnapshot {
     pub total_connections: u64,
     pub bytes_upstream: u64,
     pub bytes_downstream: u64,
+    pub bytes_mirrored: u64,
+    pub bytes_mirror_dropped: u64,
+    pub config_reloads: u64,
+    pub config_reload_errors: u64,
+    pub rejected_connections: BTreeMap<String, u64>,
+    pub denied_connections: BTreeMap<String, u64>,
+    pub http_requests: BTreeMap<String, HttpRuleStats>,
+    pub mux_sessions_active: u64,
+    pub mux_sessions_total: u64,
+    pub mux_streams_active: u64,
+    pub mux_streams_total: u64,
+    pub warm_pool_hits: u64,
+    pub warm_pool_misses: u64,
 }
 
 impl MetricsSnapshot {
     pub fn to_plain_text(&self) -> String {
-        format!(
-            "connections_active {}\nconnections_total {}\nbytes_upstream {}\nbytes_downstream {}",
+        let mut text = format!(
+            "connections_active {}\nconnections_total {}\nbytes_upstream {}\nbytes_downstream {}\nbytes_mirrored {}\nbytes_mirror_dropped {}\nconfig_reloads {}\nconfig_reload_errors {}\nmux_sessions_active {}\nmux_sessions_total {}\nmux_streams_active {}\nmux_streams_total {}\nwarm_pool_hits {}\nwarm_pool_misses {}",
             self.active_connections,
             self.total_connections,
             self.bytes_upstream,
-            self.bytes_downstream
-        )
+            self.bytes_downstream,
+            self.bytes_mirrored,
+            self.bytes_mirror_dropped,
+            self.config_reloads,
+            self.config_reload_errors,
+            self.mux_sessions_active,
+            self.mux_sessions_total,
+            self.mux_streams_active,
+            self.mux_streams_total,
+            self.warm_pool_hits,
+            self.warm_pool_misses
+        );
+        for (reason, count) in &self.rejected_connections {
+            let _ = write!(
+                text,
+                "\nconnections_rejected{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+        for (rule, count) in &self.denied_connections {
+            let _ = write!(text, "\nconnections_denied{{rule=\"{}\"}} {}", rule, count);
+        }
+        for (rule, stats) in &self.http_requests {
+            let _ = write!(
+                text,
+                "\nhttp_requests{{rule=\"{}\"}} {}",
+                rule, stats.requests
+            );
+            for (status, count) in &stats.statuses {
+                let _ = write!(
+                    text,
+                    "\nhttp_responses{{rule=\"{}\",status=\"{}\"}} {}",
+                    rule, status, count
+                );
+            }
+        }
+        text
     }
 
     #[allow(clippy::cast_precision_loss)]
@@ -120,6 +192,31 @@ impl MetricsCollector {
                     addr, self.state.active_connections
                 );
             }
+            MetricEvent::ConnectionRejected(addr, reason) => {
+                let count = self
+                    .state
+                    .rejected_connections
+                    .entry(reason.to_string())
+                    .or_default();
+                *count += 1;
+                println!(
+                    "[METRICS] ConnectionRejected {} ({}) | rejected: {}",
+                    addr, reason, count
+                );
+            }
+            MetricEvent::ConnectionDenied(addr, rule) => {
+                let rule = rule.map_or_else(|| "default".to_string(), |cidr| cidr.to_string());
+                let count = self
+                    .state
+                    .denied_connections
+                    .entry(rule.clone())
+                    .or_default();
+                *count += 1;
+                println!(
+                    "[METRICS] ConnectionDenied {} ({}) | denied: {}",
+                    addr, rule, count
+                );
+            }
             MetricEvent::BytesUpstream(addr, n) => {
                 self.state.bytes_upstream += n;
                 println!(
@@ -138,6 +235,91 @@ impl MetricsCollector {
                     MetricsSnapshot::format_bytes(self.state.bytes_downstream)
                 );
             }
+            MetricEvent::BytesMirrored(addr, n) => {
+                self.state.bytes_mirrored += n;
+                println!(
+                    "[METRICS] {} ⇉ {} | total mirrored: {}",
+                    addr,
+                    n,
+                    MetricsSnapshot::format_bytes(self.state.bytes_mirrored)
+                );
+            }
+            MetricEvent::BytesMirrorDropped(addr, n) => {
+                self.state.bytes_mirror_dropped += n;
+                println!(
+                    "[METRICS] {} ⇉ dropped {} | total mirror dropped: {}",
+                    addr,
+                    n,
+                    MetricsSnapshot::format_bytes(self.state.bytes_mirror_dropped)
+                );
+            }
+            MetricEvent::HttpResponse(addr, rule, status) => {
+                let rule_stats = self.state.http_requests.entry(rule.clone()).or_default();
+                rule_stats.requests += 1;
+                *rule_stats.statuses.entry(status).or_default() += 1;
+                println!(
+                    "[METRICS] HttpResponse {} {} {} | requests: {}",
+                    addr, rule, status, rule_stats.requests
+                );
+            }
+            MetricEvent::MuxSessionOpened => {
+                self.state.mux_sessions_active += 1;
+                self.state.mux_sessions_total += 1;
+                println!(
+                    "[METRICS] MuxSessionOpened | active: {}, total: {}",
+                    self.state.mux_sessions_active, self.state.mux_sessions_total
+                );
+            }
+            MetricEvent::MuxSessionClosed => {
+                self.state.mux_sessions_active = self.state.mux_sessions_active.saturating_sub(1);
+                println!(
+                    "[METRICS] MuxSessionClosed | active: {}",
+                    self.state.mux_sessions_active
+                );
+            }
+            MetricEvent::MuxStreamOpened(addr) => {
+                self.state.mux_streams_active += 1;
+                self.state.mux_streams_total += 1;
+                println!(
+                    "[METRICS] MuxStreamOpened {} | active: {}, total: {}",
+                    addr, self.state.mux_streams_active, self.state.mux_streams_total
+                );
+            }
+            MetricEvent::MuxStreamClosed(addr) => {
+                self.state.mux_streams_active = self.state.mux_streams_active.saturating_sub(1);
+                println!(
+                    "[METRICS] MuxStreamClosed {} | active: {}",
+                    addr, self.state.mux_streams_active
+                );
+            }
+            MetricEvent::WarmPoolHit(addr) => {
+                self.state.warm_pool_hits += 1;
+                println!(
+                    "[METRICS] WarmPoolHit {} | hits: {}",
+                    addr, self.state.warm_pool_hits
+                );
+            }
+            MetricEvent::WarmPoolMiss(addr) => {
+                self.state.warm_pool_misses += 1;
+                println!(
+                    "[METRICS] WarmPoolMiss {} | misses: {}",
+                    addr, self.state.warm_pool_misses
+                );
+            }
+            MetricEvent::ConfigReloaded => {
+                self.state.config_reloads += 1;
+                println!(
+                    "[METRICS] ConfigReloaded | reloads: {}",
+                    self.state.config_reloads
+                );
+            }
+            MetricEvent::ConfigRejected => {
+                self.state.config_reload_errors += 1;
+                println!(
+                    "[METRICS] ConfigRejected | errors: {}",
+                    self.state.config_reload_errors
+                );
+            }
         }
         let _ = self.watch_tx.send(self.state.clone());
     }
@@ -0,0 +1,147 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, split},
+    select,
+    sync::{OwnedSemaphorePermit, Semaphore, mpsc},
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{MetricEvent, MirrorConfig, Resolver, Stream};
+
+const SHADOW_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// A shadow that doesn't take any data for this long is abandoned for the rest of the connection.
+const SHADOW_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Chunk = (Vec<u8>, OwnedSemaphorePermit);
+
+// The sending side of one connection's shadow. Queued bytes hold permits of `budget`, so the
+// queue never grows beyond the configured buffer size.
+pub struct Mirror {
+    tx: mpsc::UnboundedSender<Chunk>,
+    budget: Arc<Semaphore>,
+}
+
+impl Mirror {
+    pub fn spawn(
+        config: &MirrorConfig,
+        client_addr: SocketAddr,
+        resolver: Arc<Resolver>,
+        metrics_tx: mpsc::Sender<MetricEvent>,
+        graceful_token: CancellationToken,
+    ) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_shadow(
+            config.target_addr.clone(),
+            resolver,
+            rx,
+            client_addr,
+            metrics_tx,
+            graceful_token,
+        ));
+        Self {
+            tx,
+            budget: Arc::new(Semaphore::new(config.buffer_size)),
+        }
+    }
+
+    // Never waits: returns false when the chunk was dropped.
+    pub fn send(&self, data: &[u8]) -> bool {
+        let Ok(size) = u32::try_from(data.len()) else {
+            return false;
+        };
+        match Arc::clone(&self.budget).try_acquire_many_owned(size) {
+            Ok(permit) => self.tx.send((data.to_vec(), permit)).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+async fn connect_shadow(
+    target_addr: &str,
+    resolver: &Resolver,
+    client_addr: SocketAddr,
+) -> Option<Stream> {
+    match timeout(
+        SHADOW_CONNECT_TIMEOUT,
+        Stream::connect(target_addr, resolver),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => Some(stream),
+        Ok(Err(e)) => {
+            eprintln!(
+                "[MIRROR] Failed to connect to shadow {} for {}: {}",
+                target_addr, client_addr, e
+            );
+            None
+        }
+        Err(_) => {
+            eprintln!(
+                "[MIRROR] Timed out connecting to shadow {} for {}",
+                target_addr, client_addr
+            );
+            None
+        }
+    }
+}
+
+async fn run_shadow(
+    target_addr: String,
+    resolver: Arc<Resolver>,
+    mut rx: mpsc::UnboundedReceiver<Chunk>,
+    client_addr: SocketAddr,
+    metrics_tx: mpsc::Sender<MetricEvent>,
+    graceful_token: CancellationToken,
+) {
+    let mut dropped = 0u64;
+    let stream = select! {
+        stream = connect_shadow(&target_addr, &resolver, client_addr) => stream,
+        _ = graceful_token.cancelled() => None,
+    };
+
+    if let Some(stream) = stream {
+        let (mut shadow_read, mut shadow_write) = split(stream);
+        // The shadow's responses are thrown away, but still read so it never blocks on writing them.
+        let discard = tokio::spawn(async move {
+            let mut buf = [0u8; 4096];
+            while matches!(shadow_read.read(&mut buf).await, Ok(n) if n > 0) {}
+        });
+
+        loop {
+            let chunk = select! {
+                chunk = rx.recv() => chunk,
+                _ = graceful_token.cancelled() => None,
+            };
+            let Some((data, _permit)) = chunk else {
+                break;
+            };
+            if let Ok(Ok(())) = timeout(SHADOW_WRITE_TIMEOUT, shadow_write.write_all(&data)).await {
+                let _ = metrics_tx
+                    .send(MetricEvent::BytesMirrored(client_addr, data.len() as u64))
+                    .await;
+            } else {
+                eprintln!(
+                    "[MIRROR] Shadow {} stalled or failed, stop mirroring {}",
+                    target_addr, client_addr
+                );
+                dropped += data.len() as u64;
+                break;
+            }
+        }
+
+        let _ = shadow_write.shutdown().await;
+        discard.abort();
+    }
+
+    rx.close();
+    while let Ok((data, _)) = rx.try_recv() {
+        dropped += data.len() as u64;
+    }
+    if dropped > 0 {
+        let _ = metrics_tx
+            .send(MetricEvent::BytesMirrorDropped(client_addr, dropped))
+            .await;
+    }
+}
@@ -0,0 +1,545 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf, split},
+    select,
+    sync::{Mutex as AsyncMutex, mpsc},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{MetricEvent, MuxConfig, Resolver, Stream};
+
+// Framing follows yamux: a 12-byte header of version, type, flags, stream id and length, all
+// big-endian. Data frames carry `length` payload bytes; window updates grow the peer's send
+// window by `length`.
+const VERSION: u8 = 0;
+const HEADER_LEN: usize = 12;
+
+const TYPE_DATA: u8 = 0;
+const TYPE_WINDOW_UPDATE: u8 = 1;
+const TYPE_PING: u8 = 2;
+const TYPE_GO_AWAY: u8 = 3;
+
+const FLAG_SYN: u16 = 1;
+const FLAG_ACK: u16 = 2;
+const FLAG_FIN: u16 = 4;
+const FLAG_RST: u16 = 8;
+
+// Every stream starts with this window in both directions; a larger one is announced with the
+// SYN or ACK.
+pub const MUX_INITIAL_WINDOW: u32 = 256 * 1024;
+
+// Writes are split so one busy stream can't hold the connection for long.
+const MAX_FRAME_PAYLOAD: usize = 16 * 1024;
+
+// New streams waiting for the server side to pick them up.
+const ACCEPT_BACKLOG: usize = 64;
+
+fn frame(kind: u8, flags: u16, stream_id: u32, length: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(VERSION);
+    frame.push(kind);
+    frame.extend_from_slice(&flags.to_be_bytes());
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[derive(Default)]
+struct StreamState {
+    // Received bytes the stream's reader hasn't taken yet.
+    recv: VecDeque<u8>,
+    // Read since the last window update was sent.
+    consumed: u32,
+    recv_closed: bool,
+    send_window: u32,
+    send_closed: bool,
+    reset: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl StreamState {
+    fn wake(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct Streams {
+    open: HashMap<u32, Arc<Mutex<StreamState>>>,
+    next_id: u32,
+    // Cleared once the peer sent GO_AWAY.
+    accepting: bool,
+}
+
+struct Shared {
+    streams: Mutex<Streams>,
+    frames_tx: mpsc::UnboundedSender<Vec<u8>>,
+    window_size: u32,
+    closed: CancellationToken,
+}
+
+impl Shared {
+    fn send(&self, frame: Vec<u8>) -> io::Result<()> {
+        self.frames_tx
+            .send(frame)
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))
+    }
+
+    fn stream(&self, id: u32) -> Option<Arc<Mutex<StreamState>>> {
+        let streams = self.streams.lock().expect("mux streams poisoned");
+        streams.open.get(&id).cloned()
+    }
+
+    fn insert(&self, id: u32) -> Arc<Mutex<StreamState>> {
+        let state = Arc::new(Mutex::new(StreamState {
+            send_window: MUX_INITIAL_WINDOW,
+            ..StreamState::default()
+        }));
+        let mut streams = self.streams.lock().expect("mux streams poisoned");
+        streams.open.insert(id, Arc::clone(&state));
+        state
+    }
+
+    // The window beyond the initial one, announced when a stream is opened or accepted.
+    fn window_delta(&self) -> u32 {
+        self.window_size - MUX_INITIAL_WINDOW
+    }
+}
+
+// One physical connection carrying many streams. Clients open odd stream ids and servers even
+// ones, so both ends can open streams without asking.
+#[derive(Clone)]
+pub struct MuxSession {
+    shared: Arc<Shared>,
+}
+
+impl MuxSession {
+    pub fn client<S>(io: S, window_size: u32, metrics_tx: mpsc::Sender<MetricEvent>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::start(io, window_size, metrics_tx, 1, None)
+    }
+
+    // The receiver yields the streams the client opens.
+    pub fn server<S>(
+        io: S,
+        window_size: u32,
+        metrics_tx: mpsc::Sender<MetricEvent>,
+    ) -> (Self, mpsc::Receiver<MuxStream>)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (accept_tx, accept_rx) = mpsc::channel(ACCEPT_BACKLOG);
+        let session = Self::start(io, window_size, metrics_tx, 2, Some(accept_tx));
+        (session, accept_rx)
+    }
+
+    fn start<S>(
+        io: S,
+        window_size: u32,
+        metrics_tx: mpsc::Sender<MetricEvent>,
+        first_id: u32,
+        accept_tx: Option<mpsc::Sender<MuxStream>>,
+    ) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let shared = Arc::new(Shared {
+            streams: Mutex::new(Streams {
+                open: HashMap::new(),
+                next_id: first_id,
+                accepting: true,
+            }),
+            frames_tx,
+            window_size,
+            closed: CancellationToken::new(),
+        });
+        let (mut reader, mut writer) = split(io);
+
+        let closed = shared.closed.clone();
+        tokio::spawn(async move {
+            let mut writable = true;
+            loop {
+                select! {
+                    frame = frames_rx.recv() => match frame {
+                        Some(frame) => {
+                            if writer.write_all(&frame).await.is_err() {
+                                writable = false;
+                                break;
+                            }
+                        }
+                        None => break,
+                    },
+                    _ = closed.cancelled() => break,
+                }
+            }
+            closed.cancel();
+            // Frames queued before the close, such as the last data and FIN of a stream that
+            // just finished, still go out so the peer sees the streams end cleanly.
+            while writable && let Ok(frame) = frames_rx.try_recv() {
+                writable = writer.write_all(&frame).await.is_ok();
+            }
+            let _ = writer.shutdown().await;
+        });
+
+        let session = Self {
+            shared: Arc::clone(&shared),
+        };
+        tokio::spawn(async move {
+            let _ = metrics_tx.send(MetricEvent::MuxSessionOpened).await;
+            let closed = shared.closed.clone();
+            select! {
+                result = read_frames(&mut reader, &shared, accept_tx) => {
+                    if let Err(e) = result {
+                        println!("[MUX] Session closed: {}", e);
+                    }
+                }
+                _ = closed.cancelled() => {}
+            }
+            closed.cancel();
+            // Streams still open can't get any more data.
+            for state in shared
+                .streams
+                .lock()
+                .expect("mux streams poisoned")
+                .open
+                .values()
+            {
+                let mut state = state.lock().expect("mux stream poisoned");
+                state.reset = true;
+                state.wake();
+            }
+            let _ = metrics_tx.send(MetricEvent::MuxSessionClosed).await;
+        });
+        session
+    }
+
+    pub fn open(&self) -> io::Result<MuxStream> {
+        let id = {
+            let mut streams = self.shared.streams.lock().expect("mux streams poisoned");
+            if !streams.accepting || self.is_closed() {
+                return Err(io::Error::from(io::ErrorKind::NotConnected));
+            }
+            let id = streams.next_id;
+            streams.next_id = id
+                .checked_add(2)
+                .ok_or_else(|| io::Error::other("mux stream ids exhausted"))?;
+            id
+        };
+        let state = self.shared.insert(id);
+        // Data may follow right away; the peer learns about the stream from this SYN.
+        self.shared.send(frame(
+            TYPE_WINDOW_UPDATE,
+            FLAG_SYN,
+            id,
+            self.shared.window_delta(),
+            &[],
+        ))?;
+        Ok(MuxStream {
+            id,
+            state,
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.is_cancelled()
+    }
+
+    // Resolves once the connection under the session is gone.
+    pub async fn closed(&self) {
+        self.shared.closed.cancelled().await;
+    }
+
+    pub fn stream_count(&self) -> usize {
+        let streams = self.shared.streams.lock().expect("mux streams poisoned");
+        streams.open.len()
+    }
+
+    // Tells the peer to open no more streams; the ones already open carry on.
+    pub fn go_away(&self) {
+        let _ = self.shared.send(frame(TYPE_GO_AWAY, 0, 0, 0, &[]));
+    }
+
+    pub fn close(&self) {
+        self.shared.closed.cancel();
+    }
+}
+
+async fn read_frames<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    shared: &Arc<Shared>,
+    accept_tx: Option<mpsc::Sender<MuxStream>>,
+) -> io::Result<()> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    loop {
+        let mut header = [0u8; HEADER_LEN];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if header[0] != VERSION {
+            return Err(invalid("unsupported mux version"));
+        }
+        let kind = header[1];
+        let flags = u16::from_be_bytes([header[2], header[3]]);
+        let id = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let length = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+
+        match kind {
+            TYPE_DATA | TYPE_WINDOW_UPDATE => {
+                let payload = if kind == TYPE_DATA {
+                    if length > shared.window_size {
+                        return Err(invalid("data frame larger than the window"));
+                    }
+                    let mut payload = vec![0u8; length as usize];
+                    reader.read_exact(&mut payload).await?;
+                    payload
+                } else {
+                    Vec::new()
+                };
+
+                if flags & FLAG_SYN != 0 {
+                    accept(shared, accept_tx.as_ref(), id, kind, length).await?;
+                }
+                let Some(state) = shared.stream(id) else {
+                    // Closed on this side already; whatever was in flight is dropped.
+                    continue;
+                };
+                let mut state = state.lock().expect("mux stream poisoned");
+                if kind == TYPE_DATA {
+                    if state.recv.len() + payload.len() > shared.window_size as usize {
+                        // The peer ignored the window: give up on the stream, not the session.
+                        state.reset = true;
+                        shared.send(frame(TYPE_WINDOW_UPDATE, FLAG_RST, id, 0, &[]))?;
+                    } else {
+                        state.recv.extend(payload);
+                    }
+                } else if flags & FLAG_SYN == 0 {
+                    state.send_window = state.send_window.saturating_add(length);
+                }
+                if flags & FLAG_FIN != 0 {
+                    state.recv_closed = true;
+                }
+                if flags & FLAG_RST != 0 {
+                    state.reset = true;
+                }
+                state.wake();
+            }
+            TYPE_PING => {
+                if flags & FLAG_SYN != 0 {
+                    shared.send(frame(TYPE_PING, FLAG_ACK, 0, length, &[]))?;
+                }
+            }
+            TYPE_GO_AWAY => {
+                let mut streams = shared.streams.lock().expect("mux streams poisoned");
+                streams.accepting = false;
+            }
+            _ => return Err(invalid("unknown mux frame type")),
+        }
+    }
+}
+
+// Registers a stream the peer opened and acknowledges it, or refuses it with a reset.
+async fn accept(
+    shared: &Arc<Shared>,
+    accept_tx: Option<&mpsc::Sender<MuxStream>>,
+    id: u32,
+    kind: u8,
+    length: u32,
+) -> io::Result<()> {
+    let refuse = || shared.send(frame(TYPE_WINDOW_UPDATE, FLAG_RST, id, 0, &[]));
+    let Some(accept_tx) = accept_tx else {
+        return refuse();
+    };
+    // Clients open odd ids, so a server never sees an even one from its peer.
+    if id.is_multiple_of(2) || shared.stream(id).is_some() {
+        return refuse();
+    }
+    let state = shared.insert(id);
+    if kind == TYPE_WINDOW_UPDATE {
+        let mut state = state.lock().expect("mux stream poisoned");
+        state.send_window = state.send_window.saturating_add(length);
+    }
+    shared.send(frame(
+        TYPE_WINDOW_UPDATE,
+        FLAG_ACK,
+        id,
+        shared.window_delta(),
+        &[],
+    ))?;
+    let stream = MuxStream {
+        id,
+        state,
+        shared: Arc::clone(shared),
+    };
+    // A server that stopped accepting drops the stream, which closes it.
+    let _ = accept_tx.send(stream).await;
+    Ok(())
+}
+
+// A logical connection inside a session, with its own flow control. Dropping it closes it.
+pub struct MuxStream {
+    id: u32,
+    state: Arc<Mutex<StreamState>>,
+    shared: Arc<Shared>,
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut state = this.state.lock().expect("mux stream poisoned");
+        if !state.recv.is_empty() {
+            let n = buf.remaining().min(state.recv.len());
+            let (front, back) = state.recv.as_slices();
+            let from_front = n.min(front.len());
+            buf.put_slice(&front[..from_front]);
+            buf.put_slice(&back[..n - from_front]);
+            state.recv.drain(..n);
+
+            // Hand the window back in batches rather than after every read.
+            state.consumed += u32::try_from(n).expect("reads are bounded by the window");
+            if state.consumed >= this.shared.window_size / 2 && !state.recv_closed {
+                let update = frame(TYPE_WINDOW_UPDATE, 0, this.id, state.consumed, &[]);
+                state.consumed = 0;
+                let _ = this.shared.send(update);
+            }
+            return Poll::Ready(Ok(()));
+        }
+        if state.recv_closed {
+            return Poll::Ready(Ok(()));
+        }
+        if state.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        state.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let mut state = this.state.lock().expect("mux stream poisoned");
+        if state.reset {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+        if state.send_closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if state.send_window == 0 {
+            state.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let n = buf
+            .len()
+            .min(MAX_FRAME_PAYLOAD)
+            .min(state.send_window as usize);
+        let length = u32::try_from(n).expect("frames are at most MAX_FRAME_PAYLOAD");
+        state.send_window -= length;
+        Poll::Ready(
+            this.shared
+                .send(frame(TYPE_DATA, 0, this.id, length, &buf[..n]))
+                .map(|()| n),
+        )
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut state = this.state.lock().expect("mux stream poisoned");
+        if !state.send_closed && !state.reset {
+            state.send_closed = true;
+            let _ = this
+                .shared
+                .send(frame(TYPE_WINDOW_UPDATE, FLAG_FIN, this.id, 0, &[]));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().expect("mux stream poisoned");
+        if !state.send_closed && !state.reset {
+            state.send_closed = true;
+            let _ = self
+                .shared
+                .send(frame(TYPE_WINDOW_UPDATE, FLAG_FIN, self.id, 0, &[]));
+        }
+        drop(state);
+        let mut streams = self.shared.streams.lock().expect("mux streams poisoned");
+        streams.open.remove(&self.id);
+    }
+}
+
+type MuxSessions = Arc<AsyncMutex<Vec<MuxSession>>>;
+
+// Long-lived multiplexed connections to server sides, shared by every route and keyed by target
+// address and window size, as a session's window is fixed when it starts. Streams go to the
+// least busy connection, and new connections are opened until `connections` of them are up.
+#[derive(Default)]
+pub struct MuxPool {
+    targets: Mutex<HashMap<(String, u32), MuxSessions>>,
+}
+
+impl MuxPool {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub async fn open(
+        &self,
+        target_addr: &str,
+        config: &MuxConfig,
+        resolver: &Resolver,
+        metrics_tx: &mpsc::Sender<MetricEvent>,
+    ) -> io::Result<MuxStream> {
+        let sessions = {
+            let mut targets = self.targets.lock().expect("mux pool poisoned");
+            let key = (target_addr.to_string(), config.window_size);
+            Arc::clone(targets.entry(key).or_default())
+        };
+        // Held while connecting, so clients arriving together share the new connection rather
+        // than each opening one.
+        let mut sessions = sessions.lock().await;
+        sessions.retain(|session| !session.is_closed());
+        if sessions.len() >= config.connections
+            && let Some(session) = sessions.iter().min_by_key(|session| session.stream_count())
+        {
+            return session.open();
+        }
+        let stream = Stream::connect(target_addr, resolver).await?;
+        let session = MuxSession::client(stream, config.window_size, metrics_tx.clone());
+        println!("[MUX] Connected to {}", target_addr);
+        sessions.push(session.clone());
+        session.open()
+    }
+}
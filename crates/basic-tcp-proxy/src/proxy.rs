@@ -1,15 +1,32 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    os::fd::{AsFd, OwnedFd},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
 use tokio::{
-    net::TcpListener,
+    join,
+    net::{TcpListener, UdpSocket, UnixListener, UnixStream},
     select,
+    signal::unix::{SignalKind, signal},
     sync::{mpsc, watch},
     task::{JoinHandle, JoinSet},
-    time::sleep,
+    time::{sleep, timeout},
 };
 use tokio_util::sync::CancellationToken;
 
-use crate::{Config, MetricEvent, MetricsCollector, MetricsSnapshot, http_server, run_server};
+use crate::{
+    AdminOp, AdminRequest, ApiError, BandwidthShaper, CaptureRegistry, Config, ConfigError,
+    ConnectionLimiter, DEFAULT_ROUTE, HttpPool, InheritedListeners, Listener, ListenerAddr,
+    MetricEvent, MetricsCollector, MetricsSnapshot, MuxPool, ProxyInfo, ProxyPatch, ReloadTrigger,
+    Resolver, ReverseAgentConfig, ReverseAgentContext, ReverseServices, RouteContext, SocketConfig,
+    ToxicRegistry, UdpContext, UdpRouteConfig, WarmPool, bind_tcp_listener, dup_listener,
+    http_server, listeners_from_env, notify, receive_listeners, run_reverse_agent, run_server,
+    run_udp_server, send_listeners, spawn_reload_watcher, unix_path,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
@@ -31,17 +48,70 @@ pub enum AppError {
     #[error("Failed to parse address: {0}")]
     Parse(#[from] std::net::AddrParseError),
 
+    #[error("Config error: {0}")]
+    Config(#[from] ConfigError),
+
     #[error("Unexpected error: {0}")]
     Unexpected(String),
 }
 
+struct RouteHandle {
+    listen_addr: String,
+    local_addr: ListenerAddr,
+    // Cancelling the route token closes its connections; the accept token only stops accepting.
+    route_token: CancellationToken,
+    accept_token: CancellationToken,
+    listener_fd: Option<OwnedFd>,
+}
+
+impl RouteHandle {
+    fn new(
+        listen_addr: String,
+        listener: &Listener,
+        parent: &CancellationToken,
+    ) -> std::io::Result<Self> {
+        let route_token = parent.child_token();
+        Ok(Self {
+            listen_addr,
+            local_addr: listener.local_addr()?,
+            accept_token: route_token.child_token(),
+            route_token,
+            listener_fd: listener.as_tcp().map(dup_listener).transpose()?,
+        })
+    }
+}
+
+// UDP routes are bound at startup and never handed to a newer process.
+struct UdpRouteHandle {
+    local_addr: SocketAddr,
+    token: CancellationToken,
+}
+
 pub struct Proxy {
-    config: Config,
-    src_listener: TcpListener,
+    config_tx: watch::Sender<Arc<Config>>,
+    routes: HashMap<String, RouteHandle>,
+    pending_listeners: Vec<(String, Listener)>,
+    udp_routes: HashMap<String, UdpRouteHandle>,
+    pending_udp_sockets: Vec<(UdpRouteConfig, UdpSocket)>,
+    // Cancelled to disconnect reverse tunnel agents from their relays.
+    agents_token: CancellationToken,
+    route_tasks: JoinSet<()>,
     local_addr: SocketAddr,
     metrics_listener: Option<TcpListener>,
     metrics_addr: SocketAddr,
+    metrics_fd: OwnedFd,
     shutdown_token: CancellationToken,
+    limiter: Arc<ConnectionLimiter>,
+    shaper: Arc<BandwidthShaper>,
+    toxics: Arc<ToxicRegistry>,
+    capture: Arc<CaptureRegistry>,
+    resolver: Arc<Resolver>,
+    http_pool: Arc<HttpPool>,
+    mux_pool: Arc<MuxPool>,
+    reverse_services: Arc<ReverseServices>,
+    warm_pool: Arc<WarmPool>,
+    admin_tx: mpsc::Sender<AdminRequest>,
+    admin_rx: Option<mpsc::Receiver<AdminRequest>>,
     metrics_tx: Option<mpsc::Sender<MetricEvent>>,
     metrics_rx: watch::Receiver<MetricsSnapshot>,
     collector: Option<MetricsCollector>,
@@ -49,25 +119,102 @@ pub struct Proxy {
 
 impl Proxy {
     pub async fn new(config: Config) -> Result<(Self, SocketAddr), AppError> {
-        let src_listener = TcpListener::bind(config.listen_addr.parse::<SocketAddr>()?).await?;
-        let local_addr = src_listener.local_addr()?;
+        config.validate()?;
+
+        let route_names: Vec<String> = config.all_routes().into_iter().map(|r| r.name).collect();
+        let mut activated = listeners_from_env(&route_names)?;
+        let mut inherited = match &activated {
+            Some(activated) => {
+                println!(
+                    "[SYSTEMD] Adopted {} socket-activated listener(s)",
+                    activated.routes.len() + usize::from(activated.metrics.is_some())
+                );
+                None
+            }
+            None => Self::request_handoff(&config).await,
+        };
+
+        let shutdown_token = CancellationToken::new();
+        let mut routes = HashMap::new();
+        let mut pending_listeners = Vec::new();
+        for route in config.enabled_routes() {
+            let activated_listener = activated
+                .as_mut()
+                .and_then(|activated| activated.routes.remove(&route.name));
+            let listener = if let Some(listener) = activated_listener {
+                Listener::Tcp(listener)
+            } else {
+                let previous = inherited
+                    .as_mut()
+                    .and_then(|inherited| inherited.routes.remove(&route.name));
+                Self::bind_route_listener(&route.listen_addr, previous, &config)?
+            };
+            let handle = RouteHandle::new(route.listen_addr, &listener, &shutdown_token)?;
+            routes.insert(route.name.clone(), handle);
+            pending_listeners.push((route.name, listener));
+        }
+        let ListenerAddr::Tcp(local_addr) = routes[DEFAULT_ROUTE].local_addr else {
+            return Err(AppError::Unexpected(
+                "the default route must listen on TCP".to_string(),
+            ));
+        };
+
+        let mut udp_routes = HashMap::new();
+        let mut pending_udp_sockets = Vec::new();
+        for route in &config.udp_routes {
+            let socket = UdpSocket::bind(route.listen_addr.parse::<SocketAddr>()?).await?;
+            let handle = UdpRouteHandle {
+                local_addr: socket.local_addr()?,
+                token: shutdown_token.child_token(),
+            };
+            udp_routes.insert(route.name.clone(), handle);
+            pending_udp_sockets.push((route.clone(), socket));
+        }
 
-        let metrics_listener =
-            TcpListener::bind(config.metrics_addr.parse::<SocketAddr>()?).await?;
+        let metrics_listener = if let Some(listener) = activated.and_then(|a| a.metrics) {
+            listener
+        } else {
+            let previous = inherited.and_then(|inherited| inherited.metrics);
+            Self::bind_listener(&config.metrics_addr, previous, &config.sockets)?
+        };
         let metrics_addr = metrics_listener.local_addr()?;
+        let metrics_fd = dup_listener(&metrics_listener)?;
 
         let (collector, metrics_tx, metrics_rx) = MetricsCollector::new(
             config.channel_buffer_size,
             Duration::from_secs(config.metrics_log_interval_secs),
         );
 
+        let toxics = ToxicRegistry::new(&config);
+        let capture = CaptureRegistry::new(&config);
+        let resolver = Resolver::new(&config);
+        let (config_tx, _) = watch::channel(Arc::new(config));
+        let (admin_tx, admin_rx) = mpsc::channel(16);
+
         let proxy = Self {
-            config,
-            src_listener,
+            config_tx,
+            routes,
+            pending_listeners,
+            udp_routes,
+            pending_udp_sockets,
+            agents_token: shutdown_token.child_token(),
+            route_tasks: JoinSet::new(),
             local_addr,
             metrics_listener: Some(metrics_listener),
             metrics_addr,
-            shutdown_token: CancellationToken::new(),
+            metrics_fd,
+            shutdown_token,
+            limiter: ConnectionLimiter::new(),
+            shaper: BandwidthShaper::new(),
+            toxics,
+            capture,
+            resolver,
+            http_pool: HttpPool::new(),
+            mux_pool: MuxPool::new(),
+            reverse_services: ReverseServices::new(),
+            warm_pool: WarmPool::new(),
+            admin_tx,
+            admin_rx: Some(admin_rx),
             metrics_tx: Some(metrics_tx),
             metrics_rx,
             collector: Some(collector),
@@ -77,17 +224,40 @@ impl Proxy {
     }
 
     pub async fn run(&mut self) -> Result<(), AppError> {
+        let config = self.config();
+
         println!("========================================");
         println!("       basic-tcp-proxy starting");
         println!("========================================");
         println!("Proxy listening on:    {}", self.local_addr);
-        println!("Forwarding to:         {}", self.config.target_addr);
+        println!("Forwarding to:         {}", config.target_addr);
+        for route in &config.routes {
+            let Some(running) = self.routes.get(&route.name) else {
+                println!("Route {:<16} disabled", format!("'{}':", route.name));
+                continue;
+            };
+            println!(
+                "Route {:<16} {} -> {}",
+                format!("'{}':", route.name),
+                running.local_addr,
+                route.target_addr
+            );
+        }
+        for route in &config.udp_routes {
+            println!(
+                "UDP route {:<12} {} -> {}",
+                format!("'{}':", route.name),
+                self.udp_routes[&route.name].local_addr,
+                route.target_addr
+            );
+        }
         println!(
             "Metrics endpoint:      http://{}/metrics",
             self.metrics_addr
         );
         println!("----------------------------------------");
         println!("Press Ctrl+C to shutdown gracefully");
+        println!("Send SIGHUP to reload {}", Self::config_source(&config));
         println!("========================================\n");
 
         let collector = self.collector.take().expect("collector already started");
@@ -100,30 +270,86 @@ impl Proxy {
         let http_server = tokio::spawn(http_server(
             metrics_listener,
             self.metrics_rx.clone(),
+            Arc::clone(&self.toxics),
+            Arc::clone(&self.capture),
+            self.admin_tx.clone(),
             self.shutdown_token.clone(),
         ));
+        let mut admin_rx = self.admin_rx.take().expect("admin_rx already taken");
 
-        let mut tasks_set = JoinSet::new();
-        let metrics_tx = self.metrics_tx.clone().expect("metrics_tx already taken");
+        let watch_interval = config
+            .watch_config
+            .then(|| Duration::from_secs(config.watch_interval_secs));
+        let mut reload_rx = spawn_reload_watcher(
+            self.config_tx.subscribe(),
+            watch_interval,
+            self.shutdown_token.clone(),
+        )?;
 
-        select! {
-            _ = run_server(
-                &self.src_listener,
-                &self.config.target_addr,
-                &self.shutdown_token,
-                &mut tasks_set,
-                metrics_tx,
-            ) => {}
-            _ = self.shutdown_token.cancelled() => {
-                println!("\n[SHUTDOWN] Received shutdown signal, starting graceful shutdown...");
-            }
-            _ = tokio::signal::ctrl_c() => {
-                println!("\n[SHUTDOWN] Received Ctrl+C, starting graceful shutdown...");
-                self.shutdown();
+        self.resolver
+            .spawn_refresh(self.config_tx.subscribe(), self.shutdown_token.clone());
+
+        for (name, listener) in std::mem::take(&mut self.pending_listeners) {
+            self.spawn_route(name, listener);
+        }
+        for (route, socket) in std::mem::take(&mut self.pending_udp_sockets) {
+            self.spawn_udp_route(route, socket);
+        }
+        for agent in &config.reverse_agents {
+            self.spawn_reverse_agent(agent.clone());
+        }
+
+        let upgrade_listener = config
+            .upgrade_socket
+            .as_deref()
+            .and_then(Self::bind_upgrade_socket);
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        notify(&format!(
+            "READY=1\nSTATUS=Serving {} route(s)",
+            self.routes.len()
+        ));
+
+        loop {
+            select! {
+                Some(trigger) = reload_rx.recv() => {
+                    self.reload(trigger).await;
+                }
+                Some(request) = admin_rx.recv() => {
+                    let result = self.handle_admin(request.op);
+                    let _ = request.reply.send(result);
+                }
+                Some(stream) = Self::accept_upgrade(upgrade_listener.as_ref()) => {
+                    match self.hand_off(stream).await {
+                        Ok(()) => {
+                            println!("\n[UPGRADE] Listeners handed off to new process");
+                            notify("STOPPING=1\nSTATUS=Draining after handing off listeners");
+                            self.drain().await;
+                            break;
+                        }
+                        Err(e) => eprintln!("[UPGRADE] Handoff failed, keep serving: {}", e),
+                    }
+                }
+                _ = self.shutdown_token.cancelled() => {
+                    println!("\n[SHUTDOWN] Received shutdown signal, starting graceful shutdown...");
+                    break;
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    println!("\n[SHUTDOWN] Received Ctrl+C, starting graceful shutdown...");
+                    self.shutdown();
+                    break;
+                }
+                _ = terminate.recv() => {
+                    println!("\n[SHUTDOWN] Received SIGTERM, starting graceful shutdown...");
+                    self.shutdown();
+                    break;
+                }
             }
         }
 
-        self.graceful_shutdown(tasks_set, http_server, collector_handle)
+        notify("STOPPING=1\nSTATUS=Shutting down");
+
+        self.graceful_shutdown(http_server, collector_handle)
             .await?;
 
         Ok(())
@@ -137,19 +363,509 @@ impl Proxy {
         self.metrics_rx.clone()
     }
 
+    pub fn config(&self) -> Arc<Config> {
+        self.config_tx.borrow().clone()
+    }
+
+    pub fn metrics_addr(&self) -> SocketAddr {
+        self.metrics_addr
+    }
+
+    // The TCP address of a running route; `None` for Unix socket routes.
+    pub fn route_addr(&self, name: &str) -> Option<SocketAddr> {
+        match self.routes.get(name)?.local_addr {
+            ListenerAddr::Tcp(addr) => Some(addr),
+            ListenerAddr::Unix(_) => None,
+        }
+    }
+
+    pub fn udp_route_addr(&self, name: &str) -> Option<SocketAddr> {
+        self.udp_routes.get(name).map(|route| route.local_addr)
+    }
+
+    // The public address of a service registered by a reverse tunnel agent.
+    pub fn reverse_service_addr(&self, name: &str) -> Option<SocketAddr> {
+        self.reverse_services.get(name)
+    }
+
+    async fn request_handoff(config: &Config) -> Option<InheritedListeners> {
+        let path = config.upgrade_socket.as_ref()?;
+        match receive_listeners(path).await {
+            Ok(Some(inherited)) => {
+                println!(
+                    "[UPGRADE] Took over {} route listener(s) from {}",
+                    inherited.routes.len(),
+                    path.display()
+                );
+                Some(inherited)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!(
+                    "[UPGRADE] Handoff from {} failed, binding fresh listeners: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn bind_route_listener(
+        listen_addr: &str,
+        inherited: Option<TcpListener>,
+        config: &Config,
+    ) -> Result<Listener, AppError> {
+        if unix_path(listen_addr).is_some() {
+            return Ok(Listener::bind(listen_addr, config)?);
+        }
+        Ok(Listener::Tcp(Self::bind_listener(
+            listen_addr,
+            inherited,
+            &config.sockets,
+        )?))
+    }
+
+    fn bind_listener(
+        listen_addr: &str,
+        inherited: Option<TcpListener>,
+        sockets: &SocketConfig,
+    ) -> Result<TcpListener, AppError> {
+        let addr = listen_addr.parse::<SocketAddr>()?;
+        if let Some(listener) = inherited {
+            let local_addr = listener.local_addr()?;
+            let matches = local_addr == addr || (addr.port() == 0 && addr.ip() == local_addr.ip());
+            if matches {
+                return Ok(listener);
+            }
+        }
+        Ok(bind_tcp_listener(addr, sockets)?)
+    }
+
+    fn bind_upgrade_socket(path: &Path) -> Option<UnixListener> {
+        // The previous process may still hold this path; it keeps its unlinked socket until exit.
+        let _ = std::fs::remove_file(path);
+        match UnixListener::bind(path) {
+            Ok(listener) => {
+                println!("[UPGRADE] Accepting handoff requests on {}", path.display());
+                Some(listener)
+            }
+            Err(e) => {
+                eprintln!("[UPGRADE] Failed to bind {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    async fn accept_upgrade(listener: Option<&UnixListener>) -> Option<UnixStream> {
+        match listener {
+            Some(listener) => listener.accept().await.ok().map(|(stream, _)| stream),
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn hand_off(&self, mut stream: UnixStream) -> Result<(), AppError> {
+        let routes: Vec<_> = self
+            .routes
+            .iter()
+            .filter_map(|(name, route)| Some((name.as_str(), route.listener_fd.as_ref()?.as_fd())))
+            .collect();
+        send_listeners(&mut stream, &routes, Some(self.metrics_fd.as_fd())).await
+    }
+
+    async fn drain(&mut self) {
+        for route in self.routes.values() {
+            route.accept_token.cancel();
+        }
+        // Datagrams can't be drained; the new process takes over once the sockets are closed.
+        for route in self.udp_routes.values() {
+            route.token.cancel();
+        }
+        // The new process registers the agents' services again once the relay sees them go.
+        self.agents_token.cancel();
+
+        let grace_period = Duration::from_secs(self.config().grace_period_secs);
+        let active = self.metrics_rx.borrow().active_connections;
+        println!(
+            "[UPGRADE] Stopped accepting, draining {} connection(s) for up to {}s...",
+            active,
+            grace_period.as_secs()
+        );
+
+        let mut route_tasks = std::mem::take(&mut self.route_tasks);
+        let drained = timeout(grace_period, async {
+            while route_tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            println!("[UPGRADE] Drain timed out, closing remaining connections");
+        }
+        self.route_tasks = route_tasks;
+        self.shutdown();
+    }
+
+    fn config_source(config: &Config) -> String {
+        config.path.as_ref().map_or_else(
+            || "(no config file)".to_string(),
+            |path| path.display().to_string(),
+        )
+    }
+
+    fn spawn_route(&mut self, name: String, listener: Listener) {
+        let accept_token = self.routes[&name].accept_token.clone();
+        let ctx = RouteContext {
+            graceful_token: self.routes[&name].route_token.clone(),
+            route: name,
+            config_rx: self.config_tx.subscribe(),
+            metrics_tx: self.metrics_tx.clone().expect("metrics_tx already taken"),
+            limiter: Arc::clone(&self.limiter),
+            shaper: Arc::clone(&self.shaper),
+            toxics: Arc::clone(&self.toxics),
+            capture: Arc::clone(&self.capture),
+            resolver: Arc::clone(&self.resolver),
+            http_pool: Arc::clone(&self.http_pool),
+            mux_pool: Arc::clone(&self.mux_pool),
+            reverse_services: Arc::clone(&self.reverse_services),
+            warm_pool: Arc::clone(&self.warm_pool),
+        };
+
+        self.route_tasks.spawn(async move {
+            let mut tasks_set = JoinSet::new();
+            let _ = join!(
+                run_server(&listener, &accept_token, &mut tasks_set, &ctx),
+                ctx.warm_pool.refill(&ctx, &accept_token)
+            );
+            drop(listener);
+            tasks_set.join_all().await;
+        });
+    }
+
+    fn spawn_udp_route(&mut self, route: UdpRouteConfig, socket: UdpSocket) {
+        let ctx = UdpContext {
+            graceful_token: self.udp_routes[&route.name].token.clone(),
+            route,
+            config_rx: self.config_tx.subscribe(),
+            metrics_tx: self.metrics_tx.clone().expect("metrics_tx already taken"),
+            resolver: Arc::clone(&self.resolver),
+        };
+        self.route_tasks.spawn(run_udp_server(socket, ctx));
+    }
+
+    fn spawn_reverse_agent(&mut self, agent: ReverseAgentConfig) {
+        let ctx = ReverseAgentContext {
+            agent,
+            graceful_token: self.agents_token.clone(),
+            metrics_tx: self.metrics_tx.clone().expect("metrics_tx already taken"),
+            resolver: Arc::clone(&self.resolver),
+        };
+        self.route_tasks.spawn(run_reverse_agent(ctx));
+    }
+
+    async fn reload(&mut self, trigger: ReloadTrigger) {
+        let Some(path) = self.config().path.clone() else {
+            println!(
+                "[RELOAD] Ignoring {}: proxy was not started from a config file",
+                trigger
+            );
+            return;
+        };
+        println!(
+            "[RELOAD] {} received, reloading {}",
+            trigger,
+            path.display()
+        );
+
+        let result = match Config::from_file(&path) {
+            Ok(config) => self.apply_config(config),
+            Err(e) => Err(e),
+        };
+
+        let event = match result {
+            Ok(()) => {
+                println!("[RELOAD] New config applied");
+                notify("READY=1\nSTATUS=Config reloaded");
+                MetricEvent::ConfigReloaded
+            }
+            Err(e) => {
+                eprintln!("[RELOAD] Rejected config, keeping current one: {}", e);
+                notify(&format!("READY=1\nSTATUS=Config rejected: {}", e));
+                MetricEvent::ConfigRejected
+            }
+        };
+        if let Some(metrics_tx) = &self.metrics_tx {
+            let _ = metrics_tx.send(event).await;
+        }
+    }
+
+    fn apply_config(&mut self, mut config: Config) -> Result<(), ConfigError> {
+        config.validate()?;
+        let current = self.config();
+
+        if config.metrics_addr != current.metrics_addr {
+            println!(
+                "[RELOAD] metrics_addr requires a restart, keeping {}",
+                current.metrics_addr
+            );
+            config.metrics_addr.clone_from(&current.metrics_addr);
+        }
+        if config.channel_buffer_size != current.channel_buffer_size {
+            println!(
+                "[RELOAD] channel_buffer_size requires a restart, keeping {}",
+                current.channel_buffer_size
+            );
+            config.channel_buffer_size = current.channel_buffer_size;
+        }
+        if config.metrics_log_interval_secs != current.metrics_log_interval_secs {
+            println!(
+                "[RELOAD] metrics_log_interval_secs requires a restart, keeping {}",
+                current.metrics_log_interval_secs
+            );
+            config.metrics_log_interval_secs = current.metrics_log_interval_secs;
+        }
+        if (config.watch_config, config.watch_interval_secs)
+            != (current.watch_config, current.watch_interval_secs)
+        {
+            println!("[RELOAD] watch_config/watch_interval_secs require a restart, keeping them");
+            config.watch_config = current.watch_config;
+            config.watch_interval_secs = current.watch_interval_secs;
+        }
+        if config.upgrade_socket != current.upgrade_socket {
+            println!("[RELOAD] upgrade_socket requires a restart, keeping it");
+            config.upgrade_socket.clone_from(&current.upgrade_socket);
+        }
+
+        if (&config.capture.dir, config.capture.max_file_size)
+            != (&current.capture.dir, current.capture.max_file_size)
+        {
+            println!("[RELOAD] capture dir/max_file_size require a restart, keeping them");
+            config.capture.dir.clone_from(&current.capture.dir);
+            config.capture.max_file_size = current.capture.max_file_size;
+        }
+
+        if config.udp_routes != current.udp_routes {
+            println!("[RELOAD] udp_routes require a restart, keeping them");
+            config.udp_routes.clone_from(&current.udp_routes);
+        }
+        if config.reverse_agents != current.reverse_agents {
+            println!("[RELOAD] reverse_agents require a restart, keeping them");
+            config.reverse_agents.clone_from(&current.reverse_agents);
+        }
+
+        let routes = config.enabled_routes();
+
+        // Bind every new listener up front so a bad address rejects the whole reload.
+        let mut bound = Vec::new();
+        for route in &routes {
+            let unchanged = self
+                .routes
+                .get(&route.name)
+                .is_some_and(|running| running.listen_addr == route.listen_addr);
+            if unchanged {
+                continue;
+            }
+            let listener = Listener::bind(&route.listen_addr, &config).map_err(|e| {
+                ConfigError::Invalid(format!(
+                    "route '{}': failed to bind {}: {}",
+                    route.name, route.listen_addr, e
+                ))
+            })?;
+            bound.push((route.clone(), listener));
+        }
+
+        let stale: Vec<String> = self
+            .routes
+            .iter()
+            .filter(|(name, running)| {
+                !routes
+                    .iter()
+                    .any(|route| route.name == **name && route.listen_addr == running.listen_addr)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in stale {
+            if let Some(running) = self.routes.remove(&name) {
+                running.accept_token.cancel();
+                println!(
+                    "[RELOAD] Route '{}' stopped listening on {}",
+                    name, running.local_addr
+                );
+            }
+        }
+
+        self.toxics.sync_config(&config);
+        self.capture.sync_config(&config);
+        self.resolver.sync_config(&config);
+        self.config_tx.send_replace(Arc::new(config));
+
+        for (route, listener) in bound {
+            let local_addr = listener.local_addr()?;
+            println!(
+                "[RELOAD] Route '{}' listening on {} -> {}",
+                route.name, local_addr, route.target_addr
+            );
+            let handle = RouteHandle::new(route.listen_addr, &listener, &self.shutdown_token)?;
+            self.routes.insert(route.name.clone(), handle);
+            self.spawn_route(route.name, listener);
+        }
+
+        Ok(())
+    }
+
+    fn proxy_infos(&self, names: Option<&[String]>) -> Vec<ProxyInfo> {
+        self.config()
+            .all_routes()
+            .into_iter()
+            .filter(|route| names.is_none_or(|names| names.contains(&route.name)))
+            .map(|route| ProxyInfo {
+                listen: self.routes.get(&route.name).map_or_else(
+                    || route.listen_addr.clone(),
+                    |running| running.local_addr.to_string(),
+                ),
+                name: route.name,
+                upstream: route.target_addr,
+                enabled: route.enabled,
+            })
+            .collect()
+    }
+
+    fn patch_route(config: &mut Config, name: &str, patch: ProxyPatch) -> Result<(), ApiError> {
+        if name == DEFAULT_ROUTE {
+            if patch.enabled == Some(false) {
+                return Err(ApiError::Conflict(
+                    "the default proxy cannot be disabled".to_string(),
+                ));
+            }
+            if let Some(listen) = patch.listen {
+                config.listen_addr = listen;
+            }
+            if let Some(upstream) = patch.upstream {
+                config.target_addr = upstream;
+            }
+            return Ok(());
+        }
+        let route = config
+            .routes
+            .iter_mut()
+            .find(|route| route.name == name)
+            .ok_or_else(|| ApiError::NotFound("proxy not found".to_string()))?;
+        if let Some(listen) = patch.listen {
+            route.listen_addr = listen;
+        }
+        if let Some(upstream) = patch.upstream {
+            route.target_addr = upstream;
+        }
+        if let Some(enabled) = patch.enabled {
+            route.enabled = enabled;
+        }
+        Ok(())
+    }
+
+    // Proxies created or changed over the toxiproxy API live in the running config only; a
+    // reload from the config file drops them.
+    fn handle_admin(&mut self, op: AdminOp) -> Result<Vec<ProxyInfo>, ApiError> {
+        let mut config = (*self.config()).clone();
+        let names = match op {
+            AdminOp::List => return Ok(self.proxy_infos(None)),
+            AdminOp::Create(spec) => {
+                if config
+                    .all_routes()
+                    .iter()
+                    .any(|route| route.name == spec.name)
+                {
+                    return Err(ApiError::Conflict("proxy already exists".to_string()));
+                }
+                let name = spec.name.clone();
+                config.routes.push(spec.into_route());
+                vec![name]
+            }
+            AdminOp::Update(name, patch) => {
+                Self::patch_route(&mut config, &name, patch)?;
+                vec![name]
+            }
+            AdminOp::Delete(name) => {
+                if name == DEFAULT_ROUTE {
+                    return Err(ApiError::Conflict(
+                        "the default proxy cannot be deleted".to_string(),
+                    ));
+                }
+                let before = config.routes.len();
+                config.routes.retain(|route| route.name != name);
+                if config.routes.len() == before {
+                    return Err(ApiError::NotFound("proxy not found".to_string()));
+                }
+                Vec::new()
+            }
+            AdminOp::Populate(specs) => {
+                let mut names = Vec::new();
+                for spec in specs {
+                    if config
+                        .all_routes()
+                        .iter()
+                        .any(|route| route.name == spec.name)
+                    {
+                        let patch = ProxyPatch {
+                            listen: Some(spec.listen),
+                            upstream: Some(spec.upstream),
+                            enabled: Some(spec.enabled),
+                        };
+                        Self::patch_route(&mut config, &spec.name, patch)?;
+                    } else {
+                        config.routes.push(spec.clone().into_route());
+                    }
+                    names.push(spec.name);
+                }
+                names
+            }
+            AdminOp::EnableAll => {
+                for route in &mut config.routes {
+                    route.enabled = true;
+                }
+                return self
+                    .apply_admin_config(config)
+                    .map(|()| self.proxy_infos(None));
+            }
+        };
+        self.apply_admin_config(config)?;
+        Ok(self.proxy_infos(Some(&names)))
+    }
+
+    fn apply_admin_config(&mut self, config: Config) -> Result<(), ApiError> {
+        let running: Vec<(String, CancellationToken)> = self
+            .routes
+            .iter()
+            .map(|(name, route)| (name.clone(), route.route_token.clone()))
+            .collect();
+        self.apply_config(config)
+            .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+        // Like toxiproxy, disabling or deleting a proxy also closes its open connections.
+        let enabled: Vec<String> = self
+            .config()
+            .enabled_routes()
+            .into_iter()
+            .map(|route| route.name)
+            .collect();
+        for (name, route_token) in running {
+            if !enabled.contains(&name) {
+                route_token.cancel();
+                println!("[ADMIN] Closed connections of proxy '{}'", name);
+            }
+        }
+        Ok(())
+    }
+
     async fn graceful_shutdown(
         &mut self,
-        tasks_set: JoinSet<()>,
         http_server: JoinHandle<Result<(), AppError>>,
         collector_handle: JoinHandle<()>,
     ) -> Result<(), AppError> {
         let active = self.metrics_rx.borrow().active_connections;
         println!("[SHUTDOWN] Waiting for {} active connection(s)...", active);
 
-        let grace_period = Duration::from_secs(self.config.grace_period_secs);
+        let grace_period = Duration::from_secs(self.config().grace_period_secs);
         let force_handle = Self::start_force_timeout_task(grace_period);
 
-        tasks_set.join_all().await;
+        std::mem::take(&mut self.route_tasks).join_all().await;
         http_server.await??;
         drop(self.metrics_tx.take());
         collector_handle.await?;
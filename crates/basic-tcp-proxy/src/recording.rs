@@ -0,0 +1,240 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+};
+
+use crate::Direction;
+
+// File layout: MAGIC, then the header fields, then one record per relayed chunk until EOF.
+// A record is a direction byte, the microseconds since the previous record and the data length
+// as LEB128 varints, then the data itself.
+const MAGIC: &[u8; 8] = b"TCPREC\x00\x01";
+const RECORDER_QUEUE_SIZE: usize = 256;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecordingError {
+    #[error("Failed to read recording: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Not a session recording")]
+    BadMagic,
+
+    #[error("Recording is truncated or corrupt")]
+    Corrupt,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionHeader {
+    pub started_at: SystemTime,
+    pub route: String,
+    pub client_addr: String,
+    pub target_addr: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionChunk {
+    pub direction: Direction,
+    // Time since the session started.
+    pub offset: Duration,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub header: SessionHeader,
+    pub chunks: Vec<SessionChunk>,
+}
+
+impl Recording {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, RecordingError> {
+        Self::decode(&fs::read(path)?)
+    }
+
+    // Everything the client sent, or everything the target answered, in order.
+    pub fn stream(&self, direction: Direction) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.direction == direction)
+            .flat_map(|chunk| chunk.data.iter().copied())
+            .collect()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, RecordingError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(RecordingError::BadMagic);
+        }
+        let started_at = UNIX_EPOCH + Duration::from_micros(reader.varint()?);
+        let header = SessionHeader {
+            started_at,
+            route: reader.string()?,
+            client_addr: reader.string()?,
+            target_addr: reader.string()?,
+        };
+
+        let mut chunks = Vec::new();
+        let mut offset = Duration::ZERO;
+        while !reader.bytes.is_empty() {
+            let direction = match reader.take(1)?[0] {
+                0 => Direction::Upstream,
+                1 => Direction::Downstream,
+                _ => return Err(RecordingError::Corrupt),
+            };
+            offset += Duration::from_micros(reader.varint()?);
+            let len = usize::try_from(reader.varint()?).map_err(|_| RecordingError::Corrupt)?;
+            chunks.push(SessionChunk {
+                direction,
+                offset,
+                data: reader.take(len)?.to_vec(),
+            });
+        }
+        Ok(Self { header, chunks })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RecordingError> {
+        if self.bytes.len() < len {
+            return Err(RecordingError::Corrupt);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn varint(&mut self) -> Result<u64, RecordingError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(RecordingError::Corrupt)
+    }
+
+    fn string(&mut self) -> Result<String, RecordingError> {
+        let len = usize::try_from(self.varint()?).map_err(|_| RecordingError::Corrupt)?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| RecordingError::Corrupt)
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn put_string(out: &mut Vec<u8>, value: &str) {
+    put_varint(out, value.len() as u64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+fn encode_header(header: &SessionHeader) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    put_varint(
+        &mut out,
+        micros(
+            header
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        ),
+    );
+    put_string(&mut out, &header.route);
+    put_string(&mut out, &header.client_addr);
+    put_string(&mut out, &header.target_addr);
+    out
+}
+
+// Records one connection into its own file. Chunks are written by a background task; unlike
+// capturing, recording waits for the writer so a replay never misses bytes.
+#[derive(Clone)]
+pub struct SessionRecorder {
+    tx: mpsc::Sender<(Direction, Instant, Vec<u8>)>,
+}
+
+impl SessionRecorder {
+    pub fn start(dir: PathBuf, header: SessionHeader) -> Self {
+        let (tx, rx) = mpsc::channel(RECORDER_QUEUE_SIZE);
+        let started = Instant::now();
+        tokio::spawn(async move {
+            if let Err(e) = write_session(&dir, &header, started, rx).await {
+                eprintln!(
+                    "[RECORD] Failed to record session of {}: {}",
+                    header.client_addr, e
+                );
+            }
+        });
+        Self { tx }
+    }
+
+    pub async fn record(&self, direction: Direction, at: Instant, data: &[u8]) {
+        let _ = self.tx.send((direction, at, data.to_vec())).await;
+    }
+}
+
+async fn write_session(
+    dir: &Path,
+    header: &SessionHeader,
+    started: Instant,
+    mut rx: mpsc::Receiver<(Direction, Instant, Vec<u8>)>,
+) -> io::Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    let started_micros = micros(
+        header
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default(),
+    );
+    let client_port = header.client_addr.rsplit(':').next().unwrap_or_default();
+    let path = dir.join(format!(
+        "{}-{}-{}.rec",
+        header.route, started_micros, client_port
+    ));
+    let mut file = BufWriter::new(File::create(&path).await?);
+    file.write_all(&encode_header(header)).await?;
+
+    let mut previous = started;
+    let mut record = Vec::new();
+    while let Some((direction, at, data)) = rx.recv().await {
+        // Directions are read concurrently, so a chunk may carry a slightly older timestamp.
+        let at = at.max(previous);
+        record.clear();
+        record.push(match direction {
+            Direction::Upstream => 0,
+            Direction::Downstream => 1,
+        });
+        put_varint(&mut record, micros(at - previous));
+        put_varint(&mut record, data.len() as u64);
+        record.extend_from_slice(&data);
+        file.write_all(&record).await?;
+        previous = at;
+    }
+    file.flush().await?;
+    println!("[RECORD] Wrote {}", path.display());
+    Ok(())
+}
@@ -1,128 +1,780 @@
+use std::{
+    net::SocketAddr,
+    os::fd::{AsFd, OwnedFd},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use rustix::net::sockopt;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, split},
+    join,
+    net::TcpListener,
     select,
-    sync::mpsc,
+    sync::{mpsc, watch},
     task::JoinSet,
+    time::sleep_until,
 };
 use tokio_util::sync::CancellationToken;
 
-use crate::{AppError, MetricEvent};
-
-async fn accept_connection(
-    src_listener: &TcpListener,
-    output_ip: &str,
-    graceful_token: &CancellationToken,
-    tasks_set: &mut JoinSet<()>,
-    metrics_tx: mpsc::Sender<MetricEvent>,
-) -> Result<(), AppError> {
-    let (stream_a, client_addr) = src_listener.accept().await?;
-    let stream_b = TcpStream::connect(output_ip).await?;
-
-    let _ = metrics_tx
-        .send(MetricEvent::ConnectionOpened(client_addr))
-        .await;
+use crate::{
+    AppError, BandwidthShaper, CaptureRegistry, CaptureTap, Config, ConnectionLimiter, DataTap,
+    Direction, HttpPool, Listener, MUX_INITIAL_WINDOW, MetricEvent, Mirror, MuxPool, MuxSession,
+    MuxStream, Resolver, ReverseServices, RouteConfig, ServiceRegistration, SessionHeader,
+    SessionRecorder, Shaper, Stream, ToxicPipeline, ToxicRegistry, TunnelSide, Verdict, WarmPool,
+    apply_socket_options, http_connect_handshake, reverse_tunnel_accept, serve_http, sniff,
+    socks5_handshake, ws_tunnel_accept, ws_tunnel_connect,
+};
 
-    let (mut a_read, mut a_write) = stream_a.into_split();
-    let (mut b_read, mut b_write) = stream_b.into_split();
+#[derive(Clone)]
+pub struct RouteContext {
+    pub route: String,
+    pub config_rx: watch::Receiver<Arc<Config>>,
+    pub graceful_token: CancellationToken,
+    pub metrics_tx: mpsc::Sender<MetricEvent>,
+    pub limiter: Arc<ConnectionLimiter>,
+    pub shaper: Arc<BandwidthShaper>,
+    pub toxics: Arc<ToxicRegistry>,
+    pub capture: Arc<CaptureRegistry>,
+    pub resolver: Arc<Resolver>,
+    pub http_pool: Arc<HttpPool>,
+    pub mux_pool: Arc<MuxPool>,
+    pub reverse_services: Arc<ReverseServices>,
+    pub warm_pool: Arc<WarmPool>,
+}
 
-    let conn_token = CancellationToken::new();
-    let conn_token_clone = conn_token.clone();
+struct Leg {
+    direction: Direction,
+    shaper: Shaper,
+    toxics: ToxicPipeline,
+    mirror: Option<Mirror>,
+    capture: Option<CaptureTap>,
+    recorder: Option<SessionRecorder>,
+    tap: Option<DataTap>,
+}
 
-    let upstream_graceful = graceful_token.clone();
-    let downstream_graceful = graceful_token.clone();
+fn bytes_event(direction: Direction, client_addr: SocketAddr, n: usize) -> MetricEvent {
+    match direction {
+        Direction::Upstream => MetricEvent::BytesUpstream(client_addr, n as u64),
+        Direction::Downstream => MetricEvent::BytesDownstream(client_addr, n as u64),
+    }
+}
 
-    let upstream_tx = metrics_tx.clone();
-    let downstream_tx = metrics_tx;
+// SO_LINGER with a zero timeout makes close() send RST instead of FIN. Unix sockets have no RST
+// and simply close.
+fn reset(sockets: &[OwnedFd]) {
+    for socket in sockets {
+        let _ = sockopt::set_socket_linger(socket, Some(Duration::ZERO));
+    }
+}
 
-    tasks_set.spawn(async move {
-        loop {
-            let mut buf = [0u8; 1024];
-            select! {
-                result = a_read.read(&mut buf) => {
-                    match result {
-                        Ok(0) | Err(_) => {
+async fn pipe<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    reader: &mut R,
+    writer: &mut W,
+    mut leg: Leg,
+    client_addr: SocketAddr,
+    ctx: &RouteContext,
+    conn_token: &CancellationToken,
+) -> Verdict {
+    let mut toxic_changes = leg.toxics.changes();
+    loop {
+        let deadline = leg.toxics.deadline();
+        let mut buf = [0u8; 1024];
+        let verdict = select! {
+            result = reader.read(&mut buf) => {
+                let n = match result {
+                    Ok(0) | Err(_) => {
+                        conn_token.cancel();
+                        break Verdict::Close;
+                    }
+                    Ok(n) => n,
+                };
+                let read_at = Instant::now();
+                let _ = ctx.metrics_tx.send(bytes_event(leg.direction, client_addr, n)).await;
+                if let Some(capture) = &leg.capture {
+                    capture.record(leg.direction, &buf[..n]);
+                }
+                if let Some(recorder) = &leg.recorder {
+                    recorder.record(leg.direction, read_at, &buf[..n]).await;
+                }
+                if let Some(tap) = &mut leg.tap {
+                    tap.log(leg.direction, &buf[..n]);
+                }
+                if let Some(mirror) = &leg.mirror
+                    && !mirror.send(&buf[..n])
+                {
+                    let _ = ctx
+                        .metrics_tx
+                        .send(MetricEvent::BytesMirrorDropped(client_addr, n as u64))
+                        .await;
+                }
+                if !leg.shaper.is_unlimited() {
+                    select! {
+                        () = leg.shaper.consume(n) => {}
+                        _ = ctx.graceful_token.cancelled() => {
                             conn_token.cancel();
-                            break;
+                            break Verdict::Close;
                         }
-                        Ok(n) => {
-                            let _ = upstream_tx.send(MetricEvent::BytesUpstream(client_addr, n as u64)).await;
-                            if b_write.write_all(&buf[..n]).await.is_err() {
-                                conn_token.cancel();
-                                break;
-                            }
+                        _ = conn_token.cancelled() => {
+                            break Verdict::Close;
                         }
                     }
                 }
-                _ = upstream_graceful.cancelled() => {
-                    conn_token.cancel();
-                    break;
-                }
-                _ = conn_token.cancelled() => {
-                    break;
-                }
+                let result = if leg.toxics.is_idle() {
+                    writer.write_all(&buf[..n]).await.map(|()| Verdict::Continue)
+                } else {
+                    select! {
+                        result = leg.toxics.forward(&mut buf[..n], read_at, writer) => result,
+                        _ = conn_token.cancelled() => {
+                            break Verdict::Close;
+                        }
+                    }
+                };
+                result.unwrap_or(Verdict::Close)
+            }
+            () = sleep_until(deadline.map_or_else(Instant::now, |(at, _)| at).into()), if deadline.is_some() => {
+                deadline.map_or(Verdict::Continue, |(_, verdict)| verdict)
+            }
+            Ok(()) = toxic_changes.changed() => Verdict::Continue,
+            _ = ctx.graceful_token.cancelled() => Verdict::Close,
+            _ = conn_token.cancelled() => {
+                break Verdict::Close;
+            }
+        };
+
+        match verdict {
+            Verdict::Continue => {}
+            Verdict::Close => {
+                conn_token.cancel();
+                break Verdict::Close;
+            }
+            Verdict::Reset => {
+                conn_token.cancel();
+                break Verdict::Reset;
             }
         }
-        let _ = upstream_tx.send(MetricEvent::ConnectionClosed(client_addr)).await;
-    });
+    }
+}
 
-    tasks_set.spawn(async move {
-        loop {
-            let mut buf = [0u8; 1024];
-            select! {
-                result = b_read.read(&mut buf) => {
-                    match result {
-                        Ok(0) | Err(_) => {
-                            conn_token_clone.cancel();
-                            break;
-                        }
-                        Ok(n) => {
-                            let _ = downstream_tx.send(MetricEvent::BytesDownstream(client_addr, n as u64)).await;
-                            if a_write.write_all(&buf[..n]).await.is_err() {
-                                conn_token_clone.cancel();
-                                break;
-                            }
-                        }
-                    }
+async fn handle_connection(
+    ctx: RouteContext,
+    mut stream_a: Stream,
+    client_addr: SocketAddr,
+) -> Result<(), AppError> {
+    let config = ctx.config_rx.borrow().clone();
+    let Some(route_config) = config.route(&ctx.route) else {
+        return Err(AppError::Unexpected(format!(
+            "route '{}' is no longer configured",
+            ctx.route
+        )));
+    };
+
+    if let Err(rule) = config.access.check(client_addr.ip()) {
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::ConnectionDenied(client_addr, rule))
+            .await;
+        return Ok(());
+    }
+
+    let permit = match ctx
+        .limiter
+        .admit(client_addr.ip(), &config.limits, &ctx.graceful_token)
+        .await
+    {
+        Ok(permit) => permit,
+        Err(reason) => {
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::ConnectionRejected(client_addr, reason))
+                .await;
+            return Ok(());
+        }
+    };
+
+    // Captures and resets need the client socket, which the CONNECT and sniffing wrappers hide.
+    let local_addr = stream_a.local_addr()?;
+    let client_socket = stream_a.as_fd().try_clone_to_owned()?;
+    if let Stream::Tcp(stream) = &stream_a {
+        apply_socket_options(stream, &config.sockets.client, local_addr.is_ipv6())?;
+    }
+
+    if route_config.http.is_some() {
+        // Requests are routed one by one, so there is no single target to relay bytes to until a
+        // WebSocket upgrade hands the connection over to one.
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::ConnectionOpened(client_addr))
+            .await;
+        match serve_http(stream_a, &ctx, client_addr).await {
+            Ok(Some(websocket)) => {
+                println!(
+                    "[HTTP] {} WebSocket -> {}",
+                    client_addr, websocket.target_addr
+                );
+                let connection = Connection {
+                    client_addr,
+                    local_addr,
+                    target_addr: websocket.target_addr,
+                    sockets: vec![client_socket, websocket.upstream_socket],
+                };
+                relay_opened(
+                    &ctx,
+                    &config,
+                    &route_config,
+                    websocket.client,
+                    websocket.upstream,
+                    connection,
+                )
+                .await;
+            }
+            Ok(None) => {}
+            Err(e) => println!("[HTTP] {} {}", client_addr, e),
+        }
+    } else if let Some(http_connect) = &route_config.http_connect {
+        let result = select! {
+            result = http_connect_handshake(stream_a, http_connect, &ctx.resolver, client_addr) => result,
+            _ = ctx.graceful_token.cancelled() => return Ok(()),
+        };
+        let (client, stream_b, destination) = match result {
+            Ok(Some(tunnel)) => tunnel,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                println!("[CONNECT] {} {}", client_addr, e);
+                return Ok(());
+            }
+        };
+        println!("[CONNECT] {} -> {}", client_addr, destination);
+        let connection = Connection {
+            client_addr,
+            local_addr,
+            target_addr: destination,
+            sockets: vec![client_socket, stream_b.as_fd().try_clone_to_owned()?],
+        };
+        relay(&ctx, &config, &route_config, client, stream_b, connection).await;
+    } else if let Some(ws_tunnel) = &route_config.ws_tunnel
+        && ws_tunnel.side == TunnelSide::Client
+    {
+        let target_addr = route_config.target_addr.clone();
+        let result = select! {
+            result = ws_tunnel_connect(&target_addr, ws_tunnel, &ctx.resolver) => result,
+            _ = ctx.graceful_token.cancelled() => return Ok(()),
+        };
+        let (tunnel, tunnel_socket) = match result {
+            Ok(tunnel) => tunnel,
+            Err(e) => {
+                println!(
+                    "[WS] {} tunnel to {} failed: {}",
+                    client_addr, target_addr, e
+                );
+                return Ok(());
+            }
+        };
+        println!("[WS] {} -> tunnel {}", client_addr, target_addr);
+        let connection = Connection {
+            client_addr,
+            local_addr,
+            target_addr,
+            sockets: vec![client_socket, tunnel_socket],
+        };
+        relay(&ctx, &config, &route_config, stream_a, tunnel, connection).await;
+    } else if let Some(ws_tunnel) = &route_config.ws_tunnel {
+        let result = select! {
+            result = ws_tunnel_accept(stream_a, ws_tunnel, &route_config.target_addr, &ctx.resolver, client_addr) => result,
+            _ = ctx.graceful_token.cancelled() => return Ok(()),
+        };
+        let (client, stream_b) = match result {
+            Ok(Some(tunnel)) => tunnel,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                println!("[WS] {} {}", client_addr, e);
+                return Ok(());
+            }
+        };
+        println!(
+            "[WS] tunnel {} -> {}",
+            client_addr, route_config.target_addr
+        );
+        let connection = Connection {
+            client_addr,
+            local_addr,
+            target_addr: route_config.target_addr.clone(),
+            sockets: vec![client_socket, stream_b.as_fd().try_clone_to_owned()?],
+        };
+        relay(&ctx, &config, &route_config, client, stream_b, connection).await;
+    } else if let Some(mux) = &route_config.mux
+        && mux.side == TunnelSide::Client
+    {
+        let target_addr = route_config.target_addr.clone();
+        let result = select! {
+            result = ctx.mux_pool.open(&target_addr, mux, &ctx.resolver, &ctx.metrics_tx) => result,
+            _ = ctx.graceful_token.cancelled() => return Ok(()),
+        };
+        let stream_b = match result {
+            Ok(stream_b) => stream_b,
+            Err(e) => {
+                println!(
+                    "[MUX] {} stream to {} failed: {}",
+                    client_addr, target_addr, e
+                );
+                return Ok(());
+            }
+        };
+        println!("[MUX] {} -> stream {}", client_addr, target_addr);
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::MuxStreamOpened(client_addr))
+            .await;
+        // The stream shares its socket with others, so only the client can be reset.
+        let connection = Connection {
+            client_addr,
+            local_addr,
+            target_addr,
+            sockets: vec![client_socket],
+        };
+        relay(&ctx, &config, &route_config, stream_a, stream_b, connection).await;
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::MuxStreamClosed(client_addr))
+            .await;
+    } else if let Some(mux) = &route_config.mux {
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::ConnectionOpened(client_addr))
+            .await;
+        println!("[MUX] {} session opened", client_addr);
+        let (session, streams) =
+            MuxSession::server(stream_a, mux.window_size, ctx.metrics_tx.clone());
+        serve_mux(&ctx, &config, &route_config, &session, streams, client_addr).await;
+        println!("[MUX] {} session closed", client_addr);
+    } else if let Some(reverse) = &route_config.reverse_tunnel {
+        let result = select! {
+            result = reverse_tunnel_accept(
+                &mut stream_a,
+                reverse,
+                &config.sockets,
+                &ctx.reverse_services,
+                client_addr,
+            ) => result,
+            _ = ctx.graceful_token.cancelled() => return Ok(()),
+        };
+        let (listener, registration) = match result {
+            Ok(Some(registered)) => registered,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                println!("[REVERSE] {} {}", client_addr, e);
+                return Ok(());
+            }
+        };
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::ConnectionOpened(client_addr))
+            .await;
+        println!(
+            "[REVERSE] '{}' registered by {} on {}",
+            registration.name,
+            client_addr,
+            listener.local_addr()?
+        );
+        let session = MuxSession::client(stream_a, MUX_INITIAL_WINDOW, ctx.metrics_tx.clone());
+        serve_reverse_tunnel(
+            &ctx,
+            &config,
+            &route_config,
+            &session,
+            listener,
+            &registration,
+            client_addr,
+        )
+        .await;
+        println!("[REVERSE] '{}' unregistered", registration.name);
+    } else if let Some(sniff_config) = &route_config.sniff {
+        let (client, target_addr) = select! {
+            result = sniff(stream_a, sniff_config, &route_config.target_addr) => result?,
+            _ = ctx.graceful_token.cancelled() => return Ok(()),
+        };
+        println!("[SNIFF] {} -> {}", client_addr, target_addr);
+        let stream_b = select! {
+            result = Stream::connect(&target_addr, &ctx.resolver) => result?,
+            _ = ctx.graceful_token.cancelled() => return Ok(()),
+        };
+        let connection = Connection {
+            client_addr,
+            local_addr,
+            target_addr,
+            sockets: vec![client_socket, stream_b.as_fd().try_clone_to_owned()?],
+        };
+        relay(&ctx, &config, &route_config, client, stream_b, connection).await;
+    } else {
+        let (stream_b, target_addr) = if let Some(socks5) = &route_config.socks5 {
+            let result = select! {
+                result = socks5_handshake(&mut stream_a, socks5, &ctx.resolver) => result,
+                _ = ctx.graceful_token.cancelled() => return Ok(()),
+            };
+            match result {
+                Ok((stream_b, destination)) => {
+                    println!("[SOCKS] {} -> {}", client_addr, destination);
+                    (stream_b, destination)
                 }
-                _ = downstream_graceful.cancelled() => {
-                    conn_token_clone.cancel();
-                    break;
+                Err(e) => {
+                    println!("[SOCKS] {} {}", client_addr, e);
+                    return Ok(());
                 }
-                _ = conn_token_clone.cancelled() => {
-                    break;
+            }
+        } else {
+            let warm = route_config.warm_pool.as_ref().map(|warm_pool| {
+                ctx.warm_pool.checkout(
+                    &ctx.route,
+                    &route_config.target_addr,
+                    Duration::from_secs(warm_pool.max_idle_secs),
+                )
+            });
+            let event = match &warm {
+                Some(Some(_)) => Some(MetricEvent::WarmPoolHit(client_addr)),
+                Some(None) => Some(MetricEvent::WarmPoolMiss(client_addr)),
+                None => None,
+            };
+            if let Some(event) = event {
+                let _ = ctx.metrics_tx.send(event).await;
+            }
+            let stream_b = if let Some(Some(stream_b)) = warm {
+                stream_b
+            } else {
+                select! {
+                    result = Stream::connect(&route_config.target_addr, &ctx.resolver) => result?,
+                    _ = ctx.graceful_token.cancelled() => return Ok(()),
                 }
+            };
+            (stream_b, route_config.target_addr.clone())
+        };
+        let connection = Connection {
+            client_addr,
+            local_addr,
+            target_addr,
+            sockets: vec![client_socket, stream_b.as_fd().try_clone_to_owned()?],
+        };
+        relay(&ctx, &config, &route_config, stream_a, stream_b, connection).await;
+    }
+
+    drop(permit);
+    let _ = ctx
+        .metrics_tx
+        .send(MetricEvent::ConnectionClosed(client_addr))
+        .await;
+
+    Ok(())
+}
+
+// Relays every stream the edge proxy opens on `session` to the route's target, each as if it
+// were a connection of its own. On shutdown the edge is told to open no more streams, and the
+// session closes once the open ones are done.
+async fn serve_mux(
+    ctx: &RouteContext,
+    config: &Arc<Config>,
+    route_config: &RouteConfig,
+    session: &MuxSession,
+    mut streams: mpsc::Receiver<MuxStream>,
+    client_addr: SocketAddr,
+) {
+    let mut relays = JoinSet::new();
+    loop {
+        let stream_a = select! {
+            stream = streams.recv() => match stream {
+                Some(stream) => stream,
+                None => break,
+            },
+            _ = ctx.graceful_token.cancelled() => {
+                session.go_away();
+                break;
             }
+        };
+        while relays.try_join_next().is_some() {}
+
+        let ctx = ctx.clone();
+        let config = Arc::clone(config);
+        let route_config = route_config.clone();
+        relays.spawn(async move {
+            let target_addr = route_config.target_addr.clone();
+            let stream_b = match Stream::connect(&target_addr, &ctx.resolver).await {
+                Ok(stream_b) => stream_b,
+                Err(e) => {
+                    println!(
+                        "[MUX] {} stream to {} failed: {}",
+                        client_addr, target_addr, e
+                    );
+                    return;
+                }
+            };
+            let Ok(target_socket) = stream_b.as_fd().try_clone_to_owned() else {
+                return;
+            };
+            let Ok(local_addr) = stream_b.local_addr() else {
+                return;
+            };
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::MuxStreamOpened(client_addr))
+                .await;
+            let connection = Connection {
+                client_addr,
+                local_addr,
+                target_addr,
+                sockets: vec![target_socket],
+            };
+            relay_opened(&ctx, &config, &route_config, stream_a, stream_b, connection).await;
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::MuxStreamClosed(client_addr))
+                .await;
+        });
+    }
+    relays.join_all().await;
+    session.close();
+}
+
+// Accepts public connections on a registered service's port and carries each one to the agent
+// as a stream, until the agent goes away or the route shuts down.
+async fn serve_reverse_tunnel(
+    ctx: &RouteContext,
+    config: &Arc<Config>,
+    route_config: &RouteConfig,
+    session: &MuxSession,
+    listener: TcpListener,
+    registration: &ServiceRegistration,
+    agent_addr: SocketAddr,
+) {
+    let mut relays = JoinSet::new();
+    loop {
+        let (stream_a, client_addr) = select! {
+            result = listener.accept() => match result {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("[REVERSE] Failed to accept on '{}': {}", registration.name, e);
+                    continue;
+                }
+            },
+            () = session.closed() => break,
+            _ = ctx.graceful_token.cancelled() => break,
+        };
+        while relays.try_join_next().is_some() {}
+        if let Err(rule) = config.access.check(client_addr.ip()) {
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::ConnectionDenied(client_addr, rule))
+                .await;
+            continue;
         }
+
+        let ctx = ctx.clone();
+        let config = Arc::clone(config);
+        let route_config = route_config.clone();
+        let session = session.clone();
+        let target_addr = format!("{} via {}", registration.name, agent_addr);
+        relays.spawn(async move {
+            let permit = match ctx
+                .limiter
+                .admit(client_addr.ip(), &config.limits, &ctx.graceful_token)
+                .await
+            {
+                Ok(permit) => permit,
+                Err(reason) => {
+                    let _ = ctx
+                        .metrics_tx
+                        .send(MetricEvent::ConnectionRejected(client_addr, reason))
+                        .await;
+                    return;
+                }
+            };
+            let (Ok(local_addr), Ok(client_socket)) =
+                (stream_a.local_addr(), stream_a.as_fd().try_clone_to_owned())
+            else {
+                return;
+            };
+            if let Err(e) =
+                apply_socket_options(&stream_a, &config.sockets.client, local_addr.is_ipv6())
+            {
+                eprintln!(
+                    "[REVERSE] Failed to set socket options for {}: {}",
+                    client_addr, e
+                );
+                return;
+            }
+            let stream_b = match session.open() {
+                Ok(stream_b) => stream_b,
+                Err(e) => {
+                    println!(
+                        "[REVERSE] {} stream to {} failed: {}",
+                        client_addr, target_addr, e
+                    );
+                    return;
+                }
+            };
+            println!("[REVERSE] {} -> {}", client_addr, target_addr);
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::MuxStreamOpened(client_addr))
+                .await;
+            let connection = Connection {
+                client_addr,
+                local_addr,
+                target_addr,
+                sockets: vec![client_socket],
+            };
+            relay(&ctx, &config, &route_config, stream_a, stream_b, connection).await;
+            drop(permit);
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::MuxStreamClosed(client_addr))
+                .await;
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::ConnectionClosed(client_addr))
+                .await;
+        });
+    }
+    // The port closes now; connections already carried finish first.
+    drop(listener);
+    relays.join_all().await;
+    session.close();
+}
+
+// A client connected to its target, ready to be relayed.
+struct Connection {
+    client_addr: SocketAddr,
+    local_addr: SocketAddr,
+    target_addr: String,
+    // The halves hide the sockets, so keep a handle to them for resetting the connection.
+    sockets: Vec<OwnedFd>,
+}
+
+async fn relay<C: AsyncRead + AsyncWrite, B: AsyncRead + AsyncWrite>(
+    ctx: &RouteContext,
+    config: &Config,
+    route_config: &RouteConfig,
+    stream_a: C,
+    stream_b: B,
+    connection: Connection,
+) {
+    let _ = ctx
+        .metrics_tx
+        .send(MetricEvent::ConnectionOpened(connection.client_addr))
+        .await;
+    relay_opened(ctx, config, route_config, stream_a, stream_b, connection).await;
+}
+
+// Relays a connection that was already counted as opened, such as an upgraded HTTP request.
+async fn relay_opened<C: AsyncRead + AsyncWrite, B: AsyncRead + AsyncWrite>(
+    ctx: &RouteContext,
+    config: &Config,
+    route_config: &RouteConfig,
+    stream_a: C,
+    stream_b: B,
+    connection: Connection,
+) {
+    let client_addr = connection.client_addr;
+    let capture = ctx
+        .capture
+        .tap(&ctx.route, client_addr, connection.local_addr);
+    let recorder = config.record.routes.contains(&ctx.route).then(|| {
+        SessionRecorder::start(
+            config.record_dir(),
+            SessionHeader {
+                started_at: SystemTime::now(),
+                route: ctx.route.clone(),
+                client_addr: client_addr.to_string(),
+                target_addr: connection.target_addr,
+            },
+        )
     });
+    let tap = DataTap::new(&config.tap, client_addr);
+    let (mut a_read, mut a_write) = split(stream_a);
+    let (mut b_read, mut b_write) = split(stream_b);
 
-    Ok(())
+    let leg = |direction| Leg {
+        direction,
+        shaper: ctx
+            .shaper
+            .shaper(route_config, client_addr.ip(), direction, &config.bandwidth),
+        toxics: ToxicPipeline::new(&ctx.toxics, &ctx.route, direction),
+        mirror: None,
+        capture: capture.clone(),
+        recorder: recorder.clone(),
+        tap: tap.clone(),
+    };
+    let mut upstream_leg = leg(Direction::Upstream);
+    let downstream_leg = leg(Direction::Downstream);
+    // Only what the client sends is mirrored; the shadow's responses are discarded.
+    upstream_leg.mirror = route_config.mirror.as_ref().map(|mirror| {
+        Mirror::spawn(
+            mirror,
+            client_addr,
+            Arc::clone(&ctx.resolver),
+            ctx.metrics_tx.clone(),
+            ctx.graceful_token.clone(),
+        )
+    });
+
+    let conn_token = CancellationToken::new();
+    let upstream = pipe(
+        &mut a_read,
+        &mut b_write,
+        upstream_leg,
+        client_addr,
+        ctx,
+        &conn_token,
+    );
+    let downstream = pipe(
+        &mut b_read,
+        &mut a_write,
+        downstream_leg,
+        client_addr,
+        ctx,
+        &conn_token,
+    );
+
+    let verdicts = join!(upstream, downstream);
+    if verdicts.0 == Verdict::Reset || verdicts.1 == Verdict::Reset {
+        reset(&connection.sockets);
+    }
+    if let Some(capture) = capture {
+        capture.close();
+    }
 }
 
 pub async fn run_server(
-    src_listener: &TcpListener,
-    output_ip: &str,
-    graceful_token: &CancellationToken,
+    src_listener: &Listener,
+    accept_token: &CancellationToken,
     tasks_set: &mut JoinSet<()>,
-    metrics_tx: mpsc::Sender<MetricEvent>,
+    ctx: &RouteContext,
 ) -> Result<(), AppError> {
     loop {
-        select! {
-            result = accept_connection(
-                src_listener,
-                output_ip,
-                graceful_token,
-                tasks_set,
-                metrics_tx.clone(),
-            ) => {
-                if let Err(e) = result {
+        // Only the accept itself is cancellable: a client that was already accepted is
+        // handed to its own task, otherwise a drain would drop it on the floor.
+        let (stream, client_addr) = select! {
+            result = src_listener.accept() => match result {
+                Ok(accepted) => accepted,
+                Err(e) => {
                     eprintln!("[ERROR] Failed to accept connection: {}", e);
+                    continue;
                 }
-            }
-            _ = graceful_token.cancelled() => {
-                println!("Stopped accepting connections");
+            },
+            _ = accept_token.cancelled() => {
+                println!("Stopped accepting connections on route '{}'", ctx.route);
                 return Ok(());
             }
-        }
+        };
+
+        // Reap finished connections so long-running routes don't accumulate join handles.
+        while tasks_set.try_join_next().is_some() {}
+
+        let ctx = ctx.clone();
+        tasks_set.spawn(async move {
+            if let Err(e) = handle_connection(ctx, stream, client_addr).await {
+                eprintln!(
+                    "[ERROR] Failed to relay connection from {}: {}",
+                    client_addr, e
+                );
+            }
+        });
     }
 }
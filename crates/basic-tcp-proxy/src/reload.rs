@@ -0,0 +1,84 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    select,
+    signal::unix::{SignalKind, signal},
+    sync::{mpsc, watch},
+    time::interval,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::Config;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadTrigger {
+    Signal,
+    FileChanged,
+}
+
+impl fmt::Display for ReloadTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Signal => write!(f, "SIGHUP"),
+            Self::FileChanged => write!(f, "config file change"),
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// The watched set comes from the live config, so files added by a reload are picked up.
+fn snapshot(config_rx: &watch::Receiver<Arc<Config>>) -> Vec<(PathBuf, Option<SystemTime>)> {
+    config_rx
+        .borrow()
+        .watched_files()
+        .into_iter()
+        .map(|path| {
+            let modified = modified(&path);
+            (path, modified)
+        })
+        .collect()
+}
+
+pub fn spawn_reload_watcher(
+    config_rx: watch::Receiver<Arc<Config>>,
+    watch_interval: Option<Duration>,
+    graceful_token: CancellationToken,
+) -> Result<mpsc::Receiver<ReloadTrigger>, std::io::Error> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    let (tx, rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        let mut last_modified = snapshot(&config_rx);
+        let mut poll_timer = interval(watch_interval.unwrap_or(Duration::from_secs(1)));
+        poll_timer.tick().await;
+
+        loop {
+            let trigger = select! {
+                _ = hangup.recv() => ReloadTrigger::Signal,
+                _ = poll_timer.tick(), if watch_interval.is_some() => {
+                    let current = snapshot(&config_rx);
+                    if current == last_modified {
+                        continue;
+                    }
+                    last_modified = current;
+                    ReloadTrigger::FileChanged
+                }
+                _ = graceful_token.cancelled() => return,
+            };
+
+            if tx.send(trigger).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(rx)
+}
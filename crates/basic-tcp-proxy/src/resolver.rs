@@ -0,0 +1,338 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    net::{TcpStream, lookup_host},
+    select,
+    sync::watch,
+    task::JoinSet,
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    Config, IpPreference, ResolverConfig, RouteConfig, SocketOptions, connect_with_options,
+    unix_path,
+};
+
+type Hosts = HashMap<String, Vec<IpAddr>>;
+
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+}
+
+struct ResolverState {
+    config: ResolverConfig,
+    hosts: Hosts,
+    cache: HashMap<String, CacheEntry>,
+    upstream_options: SocketOptions,
+}
+
+// Resolves `host:port` targets with a TTL cache and connects to them RFC 8305 style: addresses
+// of both families are interleaved and tried one after another, each getting a head start of
+// `attempt_delay_ms` before the next one starts, until one connects.
+pub struct Resolver {
+    state: Mutex<ResolverState>,
+}
+
+impl Resolver {
+    pub fn new(config: &Config) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(ResolverState {
+                config: config.resolver.clone(),
+                hosts: load_hosts(config.hosts_file_path().as_deref()),
+                cache: HashMap::new(),
+                upstream_options: config.sockets.upstream.clone(),
+            }),
+        })
+    }
+
+    pub fn sync_config(&self, config: &Config) {
+        let hosts = load_hosts(config.hosts_file_path().as_deref());
+        let mut state = self.state.lock().expect("resolver poisoned");
+        if state.config != config.resolver || state.hosts != hosts {
+            state.cache.clear();
+        }
+        state.config = config.resolver.clone();
+        state.hosts = hosts;
+        state.upstream_options = config.sockets.upstream.clone();
+    }
+
+    pub async fn resolve(&self, target_addr: &str) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(addr) = target_addr.parse::<SocketAddr>() {
+            return Ok(vec![addr]);
+        }
+        let (host, port) = split_host_port(target_addr)?;
+        let host = host.to_ascii_lowercase();
+        let host = host.as_str();
+        let cached = {
+            let state = self.state.lock().expect("resolver poisoned");
+            state.hosts.get(host).cloned().or_else(|| {
+                state
+                    .cache
+                    .get(host)
+                    .filter(|entry| entry.expires > Instant::now())
+                    .map(|entry| entry.addrs.clone())
+            })
+        };
+        let ips = match cached {
+            Some(ips) => ips,
+            None => self.lookup(host, port).await?,
+        };
+        let preference = self
+            .state
+            .lock()
+            .expect("resolver poisoned")
+            .config
+            .ip_preference;
+        let addrs: Vec<SocketAddr> = order_addrs(&ips, preference)
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no usable address for {}", host),
+            ));
+        }
+        Ok(addrs)
+    }
+
+    async fn lookup(&self, host: &str, port: u16) -> io::Result<Vec<IpAddr>> {
+        let ips: Vec<IpAddr> = lookup_host((host, port))
+            .await?
+            .map(|addr| addr.ip())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let mut state = self.state.lock().expect("resolver poisoned");
+        let expires = Instant::now() + Duration::from_secs(state.config.ttl_secs);
+        let changed = state.cache.get(host).is_none_or(|entry| entry.addrs != ips);
+        if changed {
+            println!("[DNS] {} -> {:?}", host, ips);
+        }
+        state.cache.insert(
+            host.to_string(),
+            CacheEntry {
+                addrs: ips.clone(),
+                expires,
+            },
+        );
+        Ok(ips)
+    }
+
+    pub async fn connect(&self, target_addr: &str) -> io::Result<TcpStream> {
+        let addrs = self.resolve(target_addr).await?;
+        self.connect_with(addrs, true).await
+    }
+
+    // Never uses fast open, so the handshake is done when this returns. For connections that
+    // may sit idle before their first write, like warm pool ones.
+    pub async fn connect_handshaken(&self, target_addr: &str) -> io::Result<TcpStream> {
+        let addrs = self.resolve(target_addr).await?;
+        self.connect_with(addrs, false).await
+    }
+
+    // Connects to addresses that were already resolved, e.g. after filtering them. SOCKS5 and
+    // CONNECT tell the client whether this worked, so it never uses fast open either.
+    pub async fn connect_addrs(&self, addrs: Vec<SocketAddr>) -> io::Result<TcpStream> {
+        self.connect_with(addrs, false).await
+    }
+
+    async fn connect_with(&self, addrs: Vec<SocketAddr>, fastopen: bool) -> io::Result<TcpStream> {
+        let (delay, mut options) = {
+            let state = self.state.lock().expect("resolver poisoned");
+            (
+                Duration::from_millis(state.config.attempt_delay_ms),
+                state.upstream_options.clone(),
+            )
+        };
+        // A fast open connect returns before the SYN is sent, so the first address would always
+        // win the race; only a lone address can use it.
+        if !fastopen || addrs.len() > 1 {
+            options.fastopen = None;
+        }
+        happy_eyeballs(addrs, delay, Arc::new(options)).await
+    }
+
+    // Re-resolves every hostname target, so the cache follows DNS changes and connections
+    // rarely wait for a lookup. A failed lookup keeps the previous addresses.
+    pub fn spawn_refresh(
+        self: &Arc<Self>,
+        config_rx: watch::Receiver<Arc<Config>>,
+        graceful_token: CancellationToken,
+    ) {
+        let resolver = Arc::clone(self);
+        tokio::spawn(async move {
+            loop {
+                let period = config_rx.borrow().resolver.refresh_interval_secs;
+                select! {
+                    () = sleep(Duration::from_secs(period)) => {}
+                    _ = graceful_token.cancelled() => return,
+                }
+
+                let config = config_rx.borrow().clone();
+                resolver.reload_hosts(&config);
+                for target in hostname_targets(&config) {
+                    let Ok((host, port)) = split_host_port(&target) else {
+                        continue;
+                    };
+                    let host = host.to_ascii_lowercase();
+                    let host = host.as_str();
+                    if resolver.is_pinned(host) {
+                        continue;
+                    }
+                    if let Err(e) = resolver.lookup(host, port).await {
+                        eprintln!("[DNS] Failed to re-resolve {}: {}", host, e);
+                        resolver.extend(host);
+                    }
+                }
+            }
+        });
+    }
+
+    fn reload_hosts(&self, config: &Config) {
+        let hosts = load_hosts(config.hosts_file_path().as_deref());
+        let mut state = self.state.lock().expect("resolver poisoned");
+        if state.hosts != hosts {
+            println!("[DNS] Hosts file changed, {} host(s)", hosts.len());
+            state.hosts = hosts;
+        }
+    }
+
+    fn is_pinned(&self, host: &str) -> bool {
+        let state = self.state.lock().expect("resolver poisoned");
+        state.hosts.contains_key(host)
+    }
+
+    fn extend(&self, host: &str) {
+        let mut state = self.state.lock().expect("resolver poisoned");
+        let ttl = Duration::from_secs(state.config.ttl_secs);
+        if let Some(entry) = state.cache.get_mut(host) {
+            entry.expires = Instant::now() + ttl;
+        }
+    }
+}
+
+fn split_host_port(target_addr: &str) -> io::Result<(&str, u16)> {
+    target_addr
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host.trim_matches(['[', ']']), port.parse().ok()?)))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid target address '{}'", target_addr),
+            )
+        })
+}
+
+fn hostname_targets(config: &Config) -> BTreeSet<String> {
+    let tcp = config
+        .all_routes()
+        .iter()
+        .flat_map(RouteConfig::targets)
+        .collect::<Vec<_>>();
+    let udp = config
+        .udp_routes
+        .iter()
+        .map(|route| route.target_addr.clone());
+    tcp.into_iter()
+        .chain(udp)
+        .filter(|target| unix_path(target).is_none() && target.parse::<SocketAddr>().is_err())
+        .collect()
+}
+
+fn load_hosts(path: Option<&Path>) -> Hosts {
+    let Some(path) = path else {
+        return Hosts::new();
+    };
+    match std::fs::read_to_string(path) {
+        Ok(content) => parse_hosts(&content),
+        Err(e) => {
+            eprintln!("[DNS] Failed to read {}: {}", path.display(), e);
+            Hosts::new()
+        }
+    }
+}
+
+fn parse_hosts(content: &str) -> Hosts {
+    let mut hosts = Hosts::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut fields = line.split_whitespace();
+        let Some(Ok(ip)) = fields.next().map(str::parse::<IpAddr>) else {
+            continue;
+        };
+        for name in fields {
+            let addrs = hosts.entry(name.to_ascii_lowercase()).or_default();
+            if !addrs.contains(&ip) {
+                addrs.push(ip);
+            }
+        }
+    }
+    hosts
+}
+
+// RFC 8305 section 4: alternate between families, starting with the preferred one.
+fn order_addrs(ips: &[IpAddr], preference: IpPreference) -> Vec<IpAddr> {
+    let (v6, v4): (Vec<IpAddr>, Vec<IpAddr>) = ips.iter().partition(|ip| ip.is_ipv6());
+    let (first, second) = match preference {
+        IpPreference::Ipv6First => (v6, v4),
+        IpPreference::Ipv4First => (v4, v6),
+        IpPreference::Ipv6Only => (v6, Vec::new()),
+        IpPreference::Ipv4Only => (v4, Vec::new()),
+    };
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+async fn happy_eyeballs(
+    addrs: Vec<SocketAddr>,
+    delay: Duration,
+    options: Arc<SocketOptions>,
+) -> io::Result<TcpStream> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    let mut start_next = true;
+
+    loop {
+        if start_next && let Some(addr) = pending.next() {
+            let options = Arc::clone(&options);
+            attempts.spawn(async move { connect_with_options(addr, &options).await });
+        }
+        if attempts.is_empty() {
+            return Err(last_error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "no address to connect to")
+            }));
+        }
+        // Dropping `attempts` aborts the connections that lost the race.
+        start_next = select! {
+            Some(result) = attempts.join_next() => match result {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => {
+                    last_error = Some(e);
+                    true
+                }
+                Err(e) => {
+                    last_error = Some(io::Error::other(e));
+                    true
+                }
+            },
+            () = sleep(delay), if pending.len() > 0 => true,
+        };
+    }
+}
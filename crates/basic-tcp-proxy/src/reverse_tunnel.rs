@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, copy_bidirectional},
+    net::TcpListener,
+    select,
+    sync::mpsc,
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    MUX_INITIAL_WINDOW, MetricEvent, MuxSession, MuxStream, Resolver, ReverseAgentConfig,
+    ReverseTunnelConfig, SocketConfig, Stream, bind_tcp_listener,
+};
+
+// Registration is a single line each way before the connection switches to mux framing:
+// `REGISTER <name> <token>` from the agent, `OK <public addr>` or `ERR <reason>` from the relay.
+const MAX_LINE_LEN: usize = 1024;
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_mins(1);
+
+// Reads one `\n`-terminated line a byte at a time, so nothing after it is consumed.
+async fn read_line<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    let mut line = Vec::new();
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+        if line.len() == MAX_LINE_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+        }
+        line.push(byte);
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8"))
+}
+
+// Services currently registered with this relay, by name, with their public address.
+#[derive(Default)]
+pub struct ReverseServices {
+    services: Mutex<HashMap<String, SocketAddr>>,
+}
+
+impl ReverseServices {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn get(&self, name: &str) -> Option<SocketAddr> {
+        let services = self.services.lock().expect("reverse services poisoned");
+        services.get(name).copied()
+    }
+
+    // `None` if the name is taken.
+    fn register(self: &Arc<Self>, name: &str, addr: SocketAddr) -> Option<ServiceRegistration> {
+        let mut services = self.services.lock().expect("reverse services poisoned");
+        if services.contains_key(name) {
+            return None;
+        }
+        services.insert(name.to_string(), addr);
+        Some(ServiceRegistration {
+            registry: Arc::clone(self),
+            name: name.to_string(),
+        })
+    }
+}
+
+// Frees the service name when dropped.
+pub struct ServiceRegistration {
+    registry: Arc<ReverseServices>,
+    pub name: String,
+}
+
+impl Drop for ServiceRegistration {
+    fn drop(&mut self) {
+        let mut services = self
+            .registry
+            .services
+            .lock()
+            .expect("reverse services poisoned");
+        services.remove(&self.name);
+    }
+}
+
+// Opens the public port from the route's range, or any port without one.
+fn bind_public(config: &ReverseTunnelConfig, sockets: &SocketConfig) -> io::Result<TcpListener> {
+    let host: IpAddr = config
+        .public_host
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid public_host"))?;
+    let Some([first, last]) = config.port_range else {
+        return bind_tcp_listener(SocketAddr::new(host, 0), sockets);
+    };
+    for port in first..=last {
+        if let Ok(listener) = bind_tcp_listener(SocketAddr::new(host, port), sockets) {
+            return Ok(listener);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        "no free port in port_range",
+    ))
+}
+
+// Reads an agent's registration and answers it. On success the service's public port is open
+// and registered, and the connection is ready for mux framing; `None` means the agent was
+// refused.
+pub async fn reverse_tunnel_accept<S: AsyncRead + AsyncWrite + Unpin>(
+    agent: &mut S,
+    config: &ReverseTunnelConfig,
+    sockets: &SocketConfig,
+    services: &Arc<ReverseServices>,
+    agent_addr: SocketAddr,
+) -> io::Result<Option<(TcpListener, ServiceRegistration)>> {
+    let line = timeout(REGISTER_TIMEOUT, read_line(agent))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    let mut parts = line.splitn(3, ' ');
+    let (Some("REGISTER"), Some(name), Some(token)) = (parts.next(), parts.next(), parts.next())
+    else {
+        agent.write_all(b"ERR bad registration\n").await?;
+        return Ok(None);
+    };
+    // A wrong guess takes as long however many of its bytes match.
+    if !bool::from(token.as_bytes().ct_eq(config.token.as_bytes())) {
+        println!("[REVERSE] {} presented a wrong token", agent_addr);
+        agent.write_all(b"ERR unauthorized\n").await?;
+        return Ok(None);
+    }
+    if services.get(name).is_some() {
+        println!(
+            "[REVERSE] {} asked for '{}', already registered",
+            agent_addr, name
+        );
+        agent.write_all(b"ERR name taken\n").await?;
+        return Ok(None);
+    }
+    let listener = match bind_public(config, sockets) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("[REVERSE] No public port for '{}': {}", name, e);
+            agent.write_all(b"ERR no free port\n").await?;
+            return Ok(None);
+        }
+    };
+    let public_addr = listener.local_addr()?;
+    // Another agent may have taken the name while the port was being opened.
+    let Some(registration) = services.register(name, public_addr) else {
+        agent.write_all(b"ERR name taken\n").await?;
+        return Ok(None);
+    };
+    agent
+        .write_all(format!("OK {}\n", public_addr).as_bytes())
+        .await?;
+    Ok(Some((listener, registration)))
+}
+
+#[derive(Debug, thiserror::Error)]
+enum RegisterError {
+    #[error("failed to connect: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("relay refused: {0}")]
+    Refused(String),
+
+    #[error("registration timed out")]
+    Timeout,
+}
+
+async fn register(
+    agent: &ReverseAgentConfig,
+    resolver: &Resolver,
+) -> Result<(Stream, String), RegisterError> {
+    let mut stream = Stream::connect(&agent.relay_addr, resolver).await?;
+    stream
+        .write_all(format!("REGISTER {} {}\n", agent.name, agent.token).as_bytes())
+        .await?;
+    let line = read_line(&mut stream).await?;
+    match line.strip_prefix("OK ") {
+        Some(public_addr) => Ok((stream, public_addr.to_string())),
+        None => Err(RegisterError::Refused(
+            line.strip_prefix("ERR ").unwrap_or(&line).to_string(),
+        )),
+    }
+}
+
+#[derive(Clone)]
+pub struct ReverseAgentContext {
+    pub agent: ReverseAgentConfig,
+    pub graceful_token: CancellationToken,
+    pub metrics_tx: mpsc::Sender<MetricEvent>,
+    pub resolver: Arc<Resolver>,
+}
+
+// Keeps the agent registered with its relay until the graceful token is cancelled, reconnecting
+// with a doubling backoff whenever the relay can't be reached or drops the connection.
+pub async fn run_reverse_agent(ctx: ReverseAgentContext) {
+    let agent = &ctx.agent;
+    let initial_backoff = Duration::from_millis(agent.retry_backoff_ms);
+    let mut backoff = initial_backoff;
+    loop {
+        let registered = select! {
+            result = timeout(REGISTER_TIMEOUT, register(agent, &ctx.resolver)) => {
+                result.unwrap_or(Err(RegisterError::Timeout))
+            }
+            _ = ctx.graceful_token.cancelled() => return,
+        };
+        match registered {
+            Ok((stream, public_addr)) => {
+                println!(
+                    "[REVERSE] '{}' registered: {} -> {}",
+                    agent.name, public_addr, agent.target_addr
+                );
+                backoff = initial_backoff;
+                serve_agent(&ctx, stream).await;
+                if ctx.graceful_token.is_cancelled() {
+                    return;
+                }
+                println!(
+                    "[REVERSE] '{}' lost the relay {}",
+                    agent.name, agent.relay_addr
+                );
+            }
+            Err(e) => println!(
+                "[REVERSE] '{}' failed to register with {}: {}; retrying in {:?}",
+                agent.name, agent.relay_addr, e, backoff
+            ),
+        }
+        select! {
+            () = sleep(backoff) => {}
+            _ = ctx.graceful_token.cancelled() => return,
+        }
+        backoff = backoff.saturating_mul(2).min(MAX_RETRY_BACKOFF);
+    }
+}
+
+// Connects every stream the relay opens to the agent's target until the relay goes away or the
+// agent shuts down.
+async fn serve_agent(ctx: &ReverseAgentContext, stream: Stream) {
+    let relay_addr = stream
+        .peer_addr()
+        .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 0)));
+    let (session, mut streams) =
+        MuxSession::server(stream, MUX_INITIAL_WINDOW, ctx.metrics_tx.clone());
+    let mut relays = JoinSet::new();
+    loop {
+        let stream = select! {
+            stream = streams.recv() => match stream {
+                Some(stream) => stream,
+                None => break,
+            },
+            () = session.closed() => break,
+            _ = ctx.graceful_token.cancelled() => {
+                session.go_away();
+                break;
+            }
+        };
+        while relays.try_join_next().is_some() {}
+        relays.spawn(relay_stream(ctx.clone(), stream, relay_addr));
+    }
+    relays.join_all().await;
+    session.close();
+}
+
+async fn relay_stream(ctx: ReverseAgentContext, mut stream: MuxStream, relay_addr: SocketAddr) {
+    let target_addr = &ctx.agent.target_addr;
+    let mut target = match Stream::connect(target_addr, &ctx.resolver).await {
+        Ok(target) => target,
+        Err(e) => {
+            println!(
+                "[REVERSE] '{}' failed to connect to {}: {}",
+                ctx.agent.name, target_addr, e
+            );
+            return;
+        }
+    };
+    let _ = ctx
+        .metrics_tx
+        .send(MetricEvent::MuxStreamOpened(relay_addr))
+        .await;
+    let copied = select! {
+        result = copy_bidirectional(&mut stream, &mut target) => result.ok(),
+        _ = ctx.graceful_token.cancelled() => None,
+    };
+    // Counted once the stream is done, as the relay server counts it live.
+    if let Some((up, down)) = copied {
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::BytesUpstream(relay_addr, up))
+            .await;
+        let _ = ctx
+            .metrics_tx
+            .send(MetricEvent::BytesDownstream(relay_addr, down))
+            .await;
+    }
+    let _ = ctx
+        .metrics_tx
+        .send(MetricEvent::MuxStreamClosed(relay_addr))
+        .await;
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+
+use crate::{BandwidthConfig, RateLimit, RouteConfig, TokenBucket};
+
+// Fraction of a second worth of bytes a fresh bucket may send without waiting.
+const BURST_SECS: f64 = 0.1;
+
+type SharedBucket = Arc<Mutex<TokenBucket>>;
+type WeakBucket = Weak<Mutex<TokenBucket>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Upstream,
+    Downstream,
+}
+
+impl Direction {
+    fn rate(self, limit: &RateLimit) -> Option<u64> {
+        match self {
+            Self::Upstream => limit.upstream,
+            Self::Downstream => limit.downstream,
+        }
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn bucket_params(rate: u64) -> (f64, f64) {
+    let rate = rate as f64;
+    (rate, (rate * BURST_SECS).max(1.0))
+}
+
+fn new_bucket(rate: u64) -> SharedBucket {
+    let (rate, capacity) = bucket_params(rate);
+    Arc::new(Mutex::new(TokenBucket::new(rate, capacity)))
+}
+
+fn bucket_matches(bucket: &SharedBucket, rate: u64) -> bool {
+    let (rate, capacity) = bucket_params(rate);
+    bucket
+        .lock()
+        .expect("bandwidth bucket poisoned")
+        .matches(rate, capacity)
+}
+
+// The buckets that one direction of one connection draws from.
+pub struct Shaper {
+    buckets: Vec<SharedBucket>,
+}
+
+impl Shaper {
+    pub fn is_unlimited(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub async fn consume(&self, bytes: usize) {
+        let wait = self
+            .buckets
+            .iter()
+            .map(|bucket| {
+                bucket
+                    .lock()
+                    .expect("bandwidth bucket poisoned")
+                    .reserve(bytes as f64)
+            })
+            .max()
+            .unwrap_or(Duration::ZERO);
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+// Client buckets are held weakly so they go away with the client's last connection; route
+// buckets live as long as the route keeps its limit.
+#[derive(Default)]
+pub struct BandwidthShaper {
+    clients: Mutex<HashMap<(IpAddr, Direction), WeakBucket>>,
+    routes: Mutex<HashMap<(String, Direction), SharedBucket>>,
+}
+
+impl BandwidthShaper {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn shaper(
+        &self,
+        route: &RouteConfig,
+        client: IpAddr,
+        direction: Direction,
+        config: &BandwidthConfig,
+    ) -> Shaper {
+        let mut buckets = Vec::new();
+        if let Some(rate) = direction.rate(&config.per_connection) {
+            buckets.push(new_bucket(rate));
+        }
+        if let Some(rate) = direction.rate(&config.per_client) {
+            buckets.push(self.client_bucket(client.to_canonical(), direction, rate));
+        }
+        let route_limit = route.bandwidth.unwrap_or(config.per_route);
+        if let Some(bucket) =
+            self.route_bucket(&route.name, direction, direction.rate(&route_limit))
+        {
+            buckets.push(bucket);
+        }
+        Shaper { buckets }
+    }
+
+    fn client_bucket(&self, client: IpAddr, direction: Direction, rate: u64) -> SharedBucket {
+        let mut clients = self.clients.lock().expect("bandwidth clients poisoned");
+        if let Some(bucket) = clients.get(&(client, direction)).and_then(Weak::upgrade)
+            && bucket_matches(&bucket, rate)
+        {
+            return bucket;
+        }
+
+        clients.retain(|_, bucket| bucket.strong_count() > 0);
+        let bucket = new_bucket(rate);
+        clients.insert((client, direction), Arc::downgrade(&bucket));
+        bucket
+    }
+
+    fn route_bucket(
+        &self,
+        route: &str,
+        direction: Direction,
+        rate: Option<u64>,
+    ) -> Option<SharedBucket> {
+        let mut routes = self.routes.lock().expect("bandwidth routes poisoned");
+        let key = (route.to_string(), direction);
+        let Some(rate) = rate else {
+            routes.remove(&key);
+            return None;
+        };
+        let bucket = routes
+            .entry(key)
+            .and_modify(|bucket| {
+                if !bucket_matches(bucket, rate) {
+                    *bucket = new_bucket(rate);
+                }
+            })
+            .or_insert_with(|| new_bucket(rate));
+        Some(Arc::clone(bucket))
+    }
+}
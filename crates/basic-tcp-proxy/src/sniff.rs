@@ -0,0 +1,217 @@
+use std::{
+    io,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use regex::bytes::Regex;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    time::{Instant, timeout_at},
+};
+
+use crate::{SniffConfig, SniffPattern, SniffProtocol};
+
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"HEAD ",
+    b"POST ",
+    b"PUT ",
+    b"DELETE ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+const PROXY_V1: &[u8] = b"PROXY ";
+const PROXY_V2: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SniffRegex(Regex);
+
+impl PartialEq for SniffRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for SniffRegex {}
+
+impl FromStr for SniffRegex {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s).map(Self)
+    }
+}
+
+impl TryFrom<String> for SniffRegex {
+    type Error = regex::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Detection {
+    Match,
+    NoMatch,
+    // The bytes so far could still go either way.
+    NeedMore,
+}
+
+fn starts_with(buf: &[u8], expected: &[u8]) -> Detection {
+    if buf.starts_with(expected) {
+        Detection::Match
+    } else if expected.starts_with(buf) {
+        Detection::NeedMore
+    } else {
+        Detection::NoMatch
+    }
+}
+
+fn starts_with_any(buf: &[u8], candidates: &[&[u8]]) -> Detection {
+    let detections: Vec<Detection> = candidates
+        .iter()
+        .map(|expected| starts_with(buf, expected))
+        .collect();
+    if detections.contains(&Detection::Match) {
+        Detection::Match
+    } else if detections.contains(&Detection::NeedMore) {
+        Detection::NeedMore
+    } else {
+        Detection::NoMatch
+    }
+}
+
+// A handshake record (0x16) of TLS 1.0 to 1.3 whose first message is a ClientHello (0x01), after
+// the two length bytes.
+fn tls_client_hello(buf: &[u8]) -> Detection {
+    let expected = |i: usize, valid: fn(u8) -> bool| buf.get(i).is_none_or(|&b| valid(b));
+    let plausible = expected(0, |b| b == 0x16)
+        && expected(1, |b| b == 0x03)
+        && expected(2, |b| b <= 0x04)
+        && expected(5, |b| b == 0x01);
+    match (plausible, buf.len() >= 6) {
+        (false, _) => Detection::NoMatch,
+        (true, true) => Detection::Match,
+        (true, false) => Detection::NeedMore,
+    }
+}
+
+fn detect(pattern: &SniffPattern, buf: &[u8]) -> Detection {
+    match pattern {
+        SniffPattern::Protocol(SniffProtocol::Tls) => tls_client_hello(buf),
+        SniffPattern::Protocol(SniffProtocol::Http) => starts_with_any(buf, &HTTP_METHODS),
+        SniffPattern::Protocol(SniffProtocol::Ssh) => starts_with(buf, b"SSH-"),
+        SniffPattern::Protocol(SniffProtocol::Proxy) => starts_with_any(buf, &[PROXY_V1, PROXY_V2]),
+        SniffPattern::Prefix(prefix) => starts_with(buf, prefix.as_bytes()),
+        // More bytes can always make a regex match, so it never rules itself out early.
+        SniffPattern::Regex(regex) if regex.0.is_match(buf) => Detection::Match,
+        SniffPattern::Regex(_) => Detection::NeedMore,
+    }
+}
+
+enum Decision {
+    Rule(usize),
+    Default,
+    Undecided,
+}
+
+// Rules are tried in order, so a rule only wins once every rule before it has ruled itself out.
+// Once no more bytes are coming, a rule that is still waiting counts as not matching.
+fn decide(config: &SniffConfig, buf: &[u8], exhausted: bool) -> Decision {
+    for (i, rule) in config.rules.iter().enumerate() {
+        match detect(&rule.pattern, buf) {
+            Detection::Match => return Decision::Rule(i),
+            Detection::NeedMore if !exhausted => return Decision::Undecided,
+            Detection::NeedMore | Detection::NoMatch => {}
+        }
+    }
+    Decision::Default
+}
+
+// Reads the start of a connection until the rules agree on a target, `max_peek_bytes` have
+// arrived, the client stops sending or the peek timeout expires. The bytes read are replayed to
+// whichever target is picked.
+pub async fn sniff<S: AsyncRead + Unpin>(
+    mut stream: S,
+    config: &SniffConfig,
+    default_target: &str,
+) -> io::Result<(Sniffed<S>, String)> {
+    let deadline = Instant::now() + Duration::from_millis(config.peek_timeout_ms);
+    let mut peeked = Vec::new();
+    let mut exhausted = false;
+    let target = loop {
+        match decide(config, &peeked, exhausted) {
+            Decision::Rule(i) => break config.rules[i].target_addr.as_str(),
+            Decision::Default => break default_target,
+            Decision::Undecided => {}
+        }
+        let mut chunk = vec![0u8; config.max_peek_bytes - peeked.len()];
+        let n = match timeout_at(deadline, stream.read(&mut chunk)).await {
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => return Err(e),
+            Err(_) => 0,
+        };
+        peeked.extend_from_slice(&chunk[..n]);
+        // Out of time, out of room or the client finished sending.
+        exhausted = n == 0 || peeked.len() >= config.max_peek_bytes;
+    };
+
+    Ok((
+        Sniffed {
+            inner: stream,
+            peeked,
+            pos: 0,
+        },
+        target.to_string(),
+    ))
+}
+
+// The client stream with the sniffed bytes put back in front.
+pub struct Sniffed<S> {
+    inner: S,
+    peeked: Vec<u8>,
+    pos: usize,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Sniffed<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.peeked.len() {
+            let n = (this.peeked.len() - this.pos).min(buf.remaining());
+            buf.put_slice(&this.peeked[this.pos..this.pos + n]);
+            this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Sniffed<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
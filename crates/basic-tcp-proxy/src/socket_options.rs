@@ -0,0 +1,106 @@
+use std::{io, net::SocketAddr, os::fd::AsFd, time::Duration};
+
+use nix::{
+    getsockopt_impl, setsockopt_impl, sockopt_impl,
+    sys::socket::{getsockopt, setsockopt, sockopt::TcpFastOpenConnect},
+};
+use rustix::net::sockopt;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+use crate::{SocketConfig, SocketOptions};
+
+// `TCP_ESTABLISHED` from the kernel's tcp_states.h, which libc doesn't export.
+const TCP_ESTABLISHED: u8 = 1;
+
+// Applies the configured options to a TCP socket; unset ones keep the OS defaults. `ipv6` picks
+// the traffic class option over IP_TOS.
+pub fn apply_socket_options(
+    socket: impl AsFd,
+    options: &SocketOptions,
+    ipv6: bool,
+) -> io::Result<()> {
+    let fd = socket.as_fd();
+    if let Some(nodelay) = options.nodelay {
+        sockopt::set_tcp_nodelay(fd, nodelay)?;
+    }
+    if let Some(keepalive) = &options.keepalive {
+        sockopt::set_socket_keepalive(fd, true)?;
+        sockopt::set_tcp_keepidle(fd, Duration::from_secs(keepalive.idle_secs))?;
+        sockopt::set_tcp_keepintvl(fd, Duration::from_secs(keepalive.interval_secs))?;
+        sockopt::set_tcp_keepcnt(fd, keepalive.count)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        sockopt::set_socket_recv_buffer_size(fd, size)?;
+    }
+    if let Some(size) = options.send_buffer_size {
+        sockopt::set_socket_send_buffer_size(fd, size)?;
+    }
+    if let Some(secs) = options.linger_secs {
+        sockopt::set_socket_linger(fd, Some(Duration::from_secs(secs)))?;
+    }
+    if let Some(tos) = options.tos {
+        if ipv6 {
+            sockopt::set_ipv6_tclass(fd, u32::from(tos))?;
+        } else {
+            sockopt::set_ip_tos(fd, tos)?;
+        }
+    }
+    if let Some(timeout) = options.user_timeout_ms {
+        sockopt::set_tcp_user_timeout(fd, timeout)?;
+    }
+    Ok(())
+}
+
+fn new_socket(addr: SocketAddr) -> io::Result<TcpSocket> {
+    if addr.is_ipv6() {
+        TcpSocket::new_v6()
+    } else {
+        TcpSocket::new_v4()
+    }
+}
+
+// Like `TcpListener::bind`, without awaiting and with the listen backlog under our control.
+pub fn bind_tcp_listener(addr: SocketAddr, sockets: &SocketConfig) -> io::Result<TcpListener> {
+    let socket = new_socket(addr)?;
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    if sockets.client.fastopen == Some(true) {
+        setsockopt(&socket, TcpFastOpen, &sockets.fastopen_queue)?;
+    }
+    socket.listen(sockets.listen_backlog)
+}
+
+// Connects with the options applied before the handshake, so buffer sizes shape the advertised
+// window. With fast open the handshake waits for the first write, carrying its data when the
+// target handed out a cookie before.
+pub async fn connect_with_options(
+    addr: SocketAddr,
+    options: &SocketOptions,
+) -> io::Result<TcpStream> {
+    let socket = new_socket(addr)?;
+    apply_socket_options(&socket, options, addr.is_ipv6())?;
+    if let Some(fastopen) = options.fastopen {
+        setsockopt(&socket, TcpFastOpenConnect, &fastopen)?;
+    }
+    socket.connect(addr).await
+}
+
+// The server side of TCP Fast Open, which nix has no option for: the queue length of a
+// listener.
+sockopt_impl!(
+    TcpFastOpen,
+    Both,
+    libc::IPPROTO_TCP,
+    libc::TCP_FASTOPEN,
+    u32
+);
+
+// The first byte of TCP_INFO is the connection's state, `tcpi_state`. Asking for just that byte
+// keeps the call independent of how large the kernel's `tcp_info` is.
+sockopt_impl!(TcpState, GetOnly, libc::IPPROTO_TCP, libc::TCP_INFO, u8);
+
+// False once the peer sent a FIN or RST, even with data still queued in front of it, and for
+// fast open sockets whose handshake waits for the first write.
+pub fn tcp_established(socket: &impl AsFd) -> bool {
+    getsockopt(socket, TcpState).is_ok_and(|state| state == TCP_ESTABLISHED)
+}
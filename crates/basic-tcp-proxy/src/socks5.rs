@@ -0,0 +1,234 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::timeout,
+};
+
+use crate::{Resolver, Socks5Config, Stream, destination_allowed, reachable_addrs};
+
+const VERSION: u8 = 0x05;
+const AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+// Clients that stall before naming a destination are dropped; connecting has its own timeouts.
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    Succeeded = 0x00,
+    GeneralFailure = 0x01,
+    NotAllowed = 0x02,
+    NetworkUnreachable = 0x03,
+    HostUnreachable = 0x04,
+    ConnectionRefused = 0x05,
+    CommandNotSupported = 0x07,
+    AddressTypeNotSupported = 0x08,
+}
+
+impl Reply {
+    fn for_error(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+            io::ErrorKind::NetworkUnreachable => Self::NetworkUnreachable,
+            io::ErrorKind::HostUnreachable | io::ErrorKind::TimedOut | io::ErrorKind::NotFound => {
+                Self::HostUnreachable
+            }
+            _ => Self::GeneralFailure,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Socks5Error {
+    #[error("handshake failed: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("handshake timed out")]
+    Timeout,
+
+    #[error("unsupported SOCKS version {0}")]
+    Version(u8),
+
+    #[error("no acceptable authentication method")]
+    NoAcceptableMethod,
+
+    #[error("authentication failed for user '{0}'")]
+    AuthFailed(String),
+
+    #[error("unsupported command {0:#04x}")]
+    Command(u8),
+
+    #[error("unsupported address type {0:#04x}")]
+    AddressType(u8),
+
+    #[error("destination {0} is not allowed")]
+    NotAllowed(String),
+
+    #[error("failed to connect to {0}: {1}")]
+    Connect(String, io::Error),
+}
+
+// Runs the server side of an RFC 1928 CONNECT handshake (with RFC 1929 login when users are
+// configured) and returns the connected destination along with its `host:port`.
+pub async fn socks5_handshake<S: AsyncRead + AsyncWrite + Unpin>(
+    client: &mut S,
+    config: &Socks5Config,
+    resolver: &Resolver,
+) -> Result<(Stream, String), Socks5Error> {
+    let (host, port) = timeout(NEGOTIATION_TIMEOUT, negotiate(client, config))
+        .await
+        .map_err(|_| Socks5Error::Timeout)??;
+    let destination = match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port).to_string(),
+        Err(_) => format!("{}:{}", host, port),
+    };
+
+    if !destination_allowed(&config.allow, &host, port) {
+        send_reply(client, Reply::NotAllowed, None).await?;
+        return Err(Socks5Error::NotAllowed(destination));
+    }
+
+    // Connected through the resolver directly: a destination must never name a Unix socket.
+    let addrs = match resolver.resolve(&destination).await {
+        Ok(addrs) => reachable_addrs(&config.allow, &host, port, addrs),
+        Err(e) => {
+            send_reply(client, Reply::for_error(&e), None).await?;
+            return Err(Socks5Error::Connect(destination, e));
+        }
+    };
+    if addrs.is_empty() {
+        send_reply(client, Reply::NotAllowed, None).await?;
+        return Err(Socks5Error::NotAllowed(destination));
+    }
+    match resolver.connect_addrs(addrs).await {
+        Ok(stream) => {
+            send_reply(client, Reply::Succeeded, stream.local_addr().ok()).await?;
+            Ok((Stream::Tcp(stream), destination))
+        }
+        Err(e) => {
+            send_reply(client, Reply::for_error(&e), None).await?;
+            Err(Socks5Error::Connect(destination, e))
+        }
+    }
+}
+
+async fn negotiate<S: AsyncRead + AsyncWrite + Unpin>(
+    client: &mut S,
+    config: &Socks5Config,
+) -> Result<(String, u16), Socks5Error> {
+    let [version, n_methods] = read_array(client).await?;
+    if version != VERSION {
+        return Err(Socks5Error::Version(version));
+    }
+    let mut methods = vec![0u8; usize::from(n_methods)];
+    client.read_exact(&mut methods).await?;
+
+    let method = if config.users.is_empty() {
+        METHOD_NO_AUTH
+    } else {
+        METHOD_USER_PASS
+    };
+    if !methods.contains(&method) {
+        client.write_all(&[VERSION, METHOD_NONE_ACCEPTABLE]).await?;
+        return Err(Socks5Error::NoAcceptableMethod);
+    }
+    client.write_all(&[VERSION, method]).await?;
+    if method == METHOD_USER_PASS {
+        authenticate(client, config).await?;
+    }
+
+    let [version, command, _reserved, address_type] = read_array(client).await?;
+    if version != VERSION {
+        return Err(Socks5Error::Version(version));
+    }
+    let host = match address_type {
+        ATYP_IPV4 => Ipv4Addr::from(read_array::<_, 4>(client).await?).to_string(),
+        ATYP_IPV6 => Ipv6Addr::from(read_array::<_, 16>(client).await?).to_string(),
+        ATYP_DOMAIN => {
+            let [len] = read_array(client).await?;
+            let mut domain = vec![0u8; usize::from(len)];
+            client.read_exact(&mut domain).await?;
+            String::from_utf8_lossy(&domain).into_owned()
+        }
+        other => {
+            send_reply(client, Reply::AddressTypeNotSupported, None).await?;
+            return Err(Socks5Error::AddressType(other));
+        }
+    };
+    let port = u16::from_be_bytes(read_array(client).await?);
+    if command != CMD_CONNECT {
+        send_reply(client, Reply::CommandNotSupported, None).await?;
+        return Err(Socks5Error::Command(command));
+    }
+    Ok((host, port))
+}
+
+async fn authenticate<S: AsyncRead + AsyncWrite + Unpin>(
+    client: &mut S,
+    config: &Socks5Config,
+) -> Result<(), Socks5Error> {
+    let [version, username_len] = read_array(client).await?;
+    if version != AUTH_VERSION {
+        return Err(Socks5Error::Version(version));
+    }
+    let mut username = vec![0u8; usize::from(username_len)];
+    client.read_exact(&mut username).await?;
+    let [password_len] = read_array(client).await?;
+    let mut password = vec![0u8; usize::from(password_len)];
+    client.read_exact(&mut password).await?;
+
+    let valid = config.users.iter().any(|user| {
+        user.username.as_bytes() == username.as_slice()
+            && user.password.as_bytes() == password.as_slice()
+    });
+    // Any non-zero status is a failure, after which the client must close.
+    client.write_all(&[AUTH_VERSION, u8::from(!valid)]).await?;
+    if valid {
+        Ok(())
+    } else {
+        Err(Socks5Error::AuthFailed(
+            String::from_utf8_lossy(&username).into_owned(),
+        ))
+    }
+}
+
+async fn send_reply<S: AsyncWrite + Unpin>(
+    client: &mut S,
+    reply: Reply,
+    bound: Option<SocketAddr>,
+) -> io::Result<()> {
+    let bound = bound.unwrap_or_else(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
+    let mut message = vec![VERSION, reply as u8, 0x00];
+    match bound.ip() {
+        IpAddr::V4(ip) => {
+            message.push(ATYP_IPV4);
+            message.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            message.push(ATYP_IPV6);
+            message.extend_from_slice(&ip.octets());
+        }
+    }
+    message.extend_from_slice(&bound.port().to_be_bytes());
+    client.write_all(&message).await
+}
+
+async fn read_array<S: AsyncRead + Unpin, const N: usize>(client: &mut S) -> io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    client.read_exact(&mut buf).await?;
+    Ok(buf)
+}
@@ -0,0 +1,240 @@
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::{
+        fd::{AsFd, BorrowedFd},
+        unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::atomic::{AtomicU16, Ordering},
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+
+use crate::{Config, Resolver, UnixSocketConfig, bind_tcp_listener};
+
+pub const UNIX_PREFIX: &str = "unix:";
+
+// Unix peers have no IP address. They count as loopback clients for access lists, limits and
+// metrics, with a port that tells their connections apart.
+const UNIX_CLIENT_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// `unix:/path/to.sock` addresses name a socket file; everything else is `host:port`.
+pub fn unix_path(addr: &str) -> Option<&Path> {
+    addr.strip_prefix(UNIX_PREFIX)
+        .filter(|path| !path.is_empty())
+        .map(Path::new)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ListenerAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl fmt::Display for ListenerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixSocketListener),
+}
+
+impl Listener {
+    pub fn bind(listen_addr: &str, config: &Config) -> io::Result<Self> {
+        if let Some(path) = unix_path(listen_addr) {
+            return UnixSocketListener::bind(path, &config.unix_socket).map(Self::Unix);
+        }
+        let addr = listen_addr
+            .parse::<SocketAddr>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        bind_tcp_listener(addr, &config.sockets).map(Self::Tcp)
+    }
+
+    pub fn local_addr(&self) -> io::Result<ListenerAddr> {
+        match self {
+            Self::Tcp(listener) => listener.local_addr().map(ListenerAddr::Tcp),
+            Self::Unix(listener) => Ok(ListenerAddr::Unix(listener.file.path.clone())),
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(Stream, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Stream::Tcp(stream), addr))
+            }
+            Self::Unix(listener) => {
+                let (stream, _) = listener.listener.accept().await?;
+                let port = listener.next_port.fetch_add(1, Ordering::Relaxed);
+                Ok((Stream::Unix(stream), SocketAddr::new(UNIX_CLIENT_IP, port)))
+            }
+        }
+    }
+
+    // Only TCP listeners are handed to a newer process; it binds its own Unix sockets.
+    pub fn as_tcp(&self) -> Option<&TcpListener> {
+        match self {
+            Self::Tcp(listener) => Some(listener),
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+pub struct UnixSocketListener {
+    listener: UnixListener,
+    file: SocketFile,
+    next_port: AtomicU16,
+}
+
+impl UnixSocketListener {
+    fn bind(path: &Path, options: &UnixSocketConfig) -> io::Result<Self> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ));
+            }
+            // A leftover socket from a crashed or upgraded process; nobody accepts on it anymore.
+            if options.remove_existing {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = options.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        if options.owner.is_some() || options.group.is_some() {
+            std::os::unix::fs::chown(path, options.owner, options.group)?;
+        }
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            listener,
+            file: SocketFile {
+                path: path.to_path_buf(),
+                id: (metadata.dev(), metadata.ino()),
+                remove_on_close: options.remove_on_close,
+            },
+            next_port: AtomicU16::new(1),
+        })
+    }
+}
+
+struct SocketFile {
+    path: PathBuf,
+    id: (u64, u64),
+    remove_on_close: bool,
+}
+
+impl Drop for SocketFile {
+    // After an upgrade the path belongs to the new process's socket, which must stay.
+    fn drop(&mut self) {
+        let ours = fs::symlink_metadata(&self.path)
+            .is_ok_and(|metadata| (metadata.dev(), metadata.ino()) == self.id);
+        if self.remove_on_close && ours {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+pub enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub async fn connect(target_addr: &str, resolver: &Resolver) -> io::Result<Self> {
+        match unix_path(target_addr) {
+            Some(path) => UnixStream::connect(path).await.map(Self::Unix),
+            None => resolver.connect(target_addr).await.map(Self::Tcp),
+        }
+    }
+
+    // See `Resolver::connect_handshaken`.
+    pub async fn connect_handshaken(target_addr: &str, resolver: &Resolver) -> io::Result<Self> {
+        match unix_path(target_addr) {
+            Some(path) => UnixStream::connect(path).await.map(Self::Unix),
+            None => resolver
+                .connect_handshaken(target_addr)
+                .await
+                .map(Self::Tcp),
+        }
+    }
+
+    // The address the client connected to, as seen by captures.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Tcp(stream) => stream.local_addr(),
+            Self::Unix(_) => Ok(SocketAddr::new(UNIX_CLIENT_IP, 0)),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Tcp(stream) => stream.peer_addr(),
+            Self::Unix(_) => Ok(SocketAddr::new(UNIX_CLIENT_IP, 0)),
+        }
+    }
+}
+
+impl AsFd for Stream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Self::Tcp(stream) => stream.as_fd(),
+            Self::Unix(stream) => stream.as_fd(),
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    collections::HashMap,
+    env, io,
+    os::{linux::net::SocketAddrExt, unix::net::UnixDatagram},
+};
+
+use listenfd::ListenFd;
+
+use crate::{AppError, InheritedListeners, into_listener};
+
+pub const METRICS_LISTENER: &str = "metrics";
+
+// LISTEN_FDNAMES is only trusted when every name refers to something we serve; otherwise
+// descriptors are assigned in config order: routes first, then the metrics listener.
+pub fn listeners_from_env(route_names: &[String]) -> Result<Option<InheritedListeners>, AppError> {
+    let mut listen_fds = ListenFd::from_env();
+    if listen_fds.len() == 0 {
+        return Ok(None);
+    }
+
+    let names: Vec<String> = env::var("LISTEN_FDNAMES")
+        .map(|names| names.split(':').map(str::to_string).collect())
+        .unwrap_or_default();
+    let use_names = names.len() == listen_fds.len()
+        && names
+            .iter()
+            .all(|name| name == METRICS_LISTENER || route_names.contains(name));
+    let names = if use_names {
+        names
+    } else {
+        route_names
+            .iter()
+            .cloned()
+            .chain(std::iter::once(METRICS_LISTENER.to_string()))
+            .take(listen_fds.len())
+            .collect()
+    };
+
+    let mut routes = HashMap::new();
+    let mut metrics = None;
+    for (idx, name) in names.into_iter().enumerate() {
+        let Some(listener) = listen_fds.take_tcp_listener(idx)? else {
+            continue;
+        };
+        let listener = into_listener(listener)?;
+        if name == METRICS_LISTENER {
+            metrics = Some(listener);
+        } else {
+            routes.insert(name, listener);
+        }
+    }
+
+    Ok(Some(InheritedListeners { routes, metrics }))
+}
+
+pub fn notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notify(&path, state) {
+        eprintln!("[SYSTEMD] Failed to notify {}: {}", path, e);
+    }
+}
+
+fn send_notify(path: &str, state: &str) -> Result<(), io::Error> {
+    let socket = UnixDatagram::unbound()?;
+    match path.strip_prefix('@') {
+        Some(name) => {
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use crate::{Direction, TapConfig};
+
+// Logs the first `max_bytes` of a connection, both directions combined, as a hex dump. Each leg
+// keeps its own offset into its direction's byte stream.
+#[derive(Debug, Clone)]
+pub struct DataTap {
+    client_addr: SocketAddr,
+    remaining: Arc<AtomicUsize>,
+    offset: usize,
+}
+
+impl DataTap {
+    pub fn new(config: &TapConfig, client_addr: SocketAddr) -> Option<Self> {
+        config
+            .clients
+            .iter()
+            .any(|cidr| cidr.contains(client_addr.ip()))
+            .then(|| Self {
+                client_addr,
+                remaining: Arc::new(AtomicUsize::new(config.max_bytes)),
+                offset: 0,
+            })
+    }
+
+    // Claims up to `len` bytes of the connection's budget and advances this leg's offset.
+    // Returns the stream offset of the chunk and how many of its bytes should be dumped.
+    pub fn take(&mut self, len: usize) -> (usize, usize) {
+        let claimed = self
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                Some(remaining - remaining.min(len))
+            })
+            .map_or(0, |remaining| remaining.min(len));
+        let offset = self.offset;
+        self.offset += len;
+        (offset, claimed)
+    }
+
+    pub fn log(&mut self, direction: Direction, data: &[u8]) {
+        let (offset, claimed) = self.take(data.len());
+        if claimed == 0 {
+            return;
+        }
+        let arrow = match direction {
+            Direction::Upstream => "→",
+            Direction::Downstream => "←",
+        };
+        let mut text = format!(
+            "[TAP] {} {} {:?} offset {} ({} bytes)",
+            self.client_addr,
+            arrow,
+            direction,
+            offset,
+            data.len()
+        );
+        if claimed < data.len() {
+            let _ = write!(text, ", limit reached after {} bytes", claimed);
+        }
+        text.push('\n');
+        text.push_str(&hexdump(offset, &data[..claimed]));
+        // A single println keeps the dump together when connections log concurrently.
+        println!("{}", text.trim_end());
+    }
+}
+
+// Classic `hexdump -C` layout: offset, 16 hex bytes split in two groups, printable ASCII.
+pub fn hexdump(offset: usize, data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", offset + i * 16);
+        for column in 0..16 {
+            if column == 8 {
+                out.push(' ');
+            }
+            match line.get(column) {
+                Some(byte) => {
+                    let _ = write!(out, " {:02x}", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(line.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                char::from(byte)
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+    out
+}
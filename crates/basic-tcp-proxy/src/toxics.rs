@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::watch,
+    time::{sleep, sleep_until},
+};
+
+use crate::{ApiError, Config, Direction, TokenBucket, ToxicConfig, ToxicKind};
+
+pub type ToxicMap = Arc<HashMap<String, Vec<ToxicConfig>>>;
+
+// Live toxics per route. Starts from the config and can be changed through the HTTP API;
+// a reload only resets the routes whose configured toxics changed.
+pub struct ToxicRegistry {
+    tx: watch::Sender<ToxicMap>,
+    configured: Mutex<HashMap<String, Vec<ToxicConfig>>>,
+}
+
+fn configured_toxics(config: &Config) -> HashMap<String, Vec<ToxicConfig>> {
+    config
+        .all_routes()
+        .into_iter()
+        .map(|route| (route.name, route.toxics))
+        .collect()
+}
+
+impl ToxicRegistry {
+    pub fn new(config: &Config) -> Arc<Self> {
+        let configured = configured_toxics(config);
+        let (tx, _) = watch::channel(Arc::new(configured.clone()));
+        Arc::new(Self {
+            tx,
+            configured: Mutex::new(configured),
+        })
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ToxicMap> {
+        self.tx.subscribe()
+    }
+
+    pub fn snapshot(&self) -> ToxicMap {
+        self.tx.borrow().clone()
+    }
+
+    pub fn sync_config(&self, config: &Config) {
+        let mut configured = self.configured.lock().expect("toxic registry poisoned");
+        let next = configured_toxics(config);
+        self.tx.send_modify(|live| {
+            let live = Arc::make_mut(live);
+            live.retain(|route, _| next.contains_key(route));
+            for (route, toxics) in &next {
+                if configured.get(route) != Some(toxics) || !live.contains_key(route) {
+                    live.insert(route.clone(), toxics.clone());
+                }
+            }
+        });
+        *configured = next;
+    }
+
+    pub fn set_enabled(&self, route: &str, name: &str, enabled: bool) -> Option<ToxicConfig> {
+        let mut updated = None;
+        self.tx.send_if_modified(|live| {
+            let Some(toxic) = Arc::make_mut(live)
+                .get_mut(route)
+                .and_then(|toxics| toxics.iter_mut().find(|toxic| toxic.name == name))
+            else {
+                return false;
+            };
+            toxic.enabled = enabled;
+            updated = Some(toxic.clone());
+            true
+        });
+        updated
+    }
+
+    pub fn route_toxics(&self, route: &str) -> Option<Vec<ToxicConfig>> {
+        self.tx.borrow().get(route).cloned()
+    }
+
+    // Runs `change` on the live toxics of one route and publishes the result if it succeeded.
+    fn modify<T>(
+        &self,
+        route: &str,
+        change: impl FnOnce(&mut Vec<ToxicConfig>) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let mut result = Err(ApiError::NotFound("proxy not found".to_string()));
+        self.tx.send_if_modified(|live| {
+            let Some(toxics) = live.get(route) else {
+                return false;
+            };
+            let mut toxics = toxics.clone();
+            result = change(&mut toxics);
+            if result.is_ok() {
+                Arc::make_mut(live).insert(route.to_string(), toxics);
+            }
+            result.is_ok()
+        });
+        result
+    }
+
+    pub fn add(&self, route: &str, toxic: ToxicConfig) -> Result<ToxicConfig, ApiError> {
+        toxic.validate().map_err(ApiError::BadRequest)?;
+        self.modify(route, |toxics| {
+            if toxics.iter().any(|existing| existing.name == toxic.name) {
+                return Err(ApiError::Conflict("toxic already exists".to_string()));
+            }
+            toxics.push(toxic.clone());
+            Ok(toxic)
+        })
+    }
+
+    // Merges a toxiproxy-style JSON patch, e.g. `{"attributes": {"latency": 100}}`.
+    pub fn update(&self, route: &str, name: &str, patch: &Value) -> Result<ToxicConfig, ApiError> {
+        self.modify(route, |toxics| {
+            let toxic = toxics
+                .iter_mut()
+                .find(|toxic| toxic.name == name)
+                .ok_or_else(|| ApiError::NotFound("toxic not found".to_string()))?;
+            let mut merged =
+                serde_json::to_value(&*toxic).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            merge_json(&mut merged, patch);
+            let mut updated: ToxicConfig =
+                serde_json::from_value(merged).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+            updated.name = name.to_string();
+            updated.validate().map_err(ApiError::BadRequest)?;
+            *toxic = updated.clone();
+            Ok(updated)
+        })
+    }
+
+    pub fn remove(&self, route: &str, name: &str) -> Result<(), ApiError> {
+        self.modify(route, |toxics| {
+            let before = toxics.len();
+            toxics.retain(|toxic| toxic.name != name);
+            if toxics.len() == before {
+                return Err(ApiError::NotFound("toxic not found".to_string()));
+            }
+            Ok(())
+        })
+    }
+
+    pub fn clear(&self) {
+        self.tx.send_modify(|live| {
+            for toxics in Arc::make_mut(live).values_mut() {
+                toxics.clear();
+            }
+        });
+    }
+}
+
+fn merge_json(target: &mut Value, patch: &Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge_json(target.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (target, patch) => *target = patch.clone(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Continue,
+    Close,
+    Reset,
+}
+
+struct ActiveToxic {
+    config: ToxicConfig,
+    since: Instant,
+    bucket: Option<TokenBucket>,
+}
+
+impl ActiveToxic {
+    #[allow(clippy::cast_precision_loss)]
+    fn new(config: ToxicConfig) -> Self {
+        let bucket = match config.kind {
+            ToxicKind::Bandwidth { rate } => {
+                let rate = rate as f64 * 1000.0;
+                Some(TokenBucket::new(rate, (rate / 10.0).max(1.0)))
+            }
+            _ => None,
+        };
+        Self {
+            config,
+            since: Instant::now(),
+            bucket,
+        }
+    }
+}
+
+// The toxics one direction of one connection is currently subjected to. Toxicity is rolled
+// once per connection, like toxiproxy does.
+pub struct ToxicPipeline {
+    route: String,
+    direction: Direction,
+    rx: watch::Receiver<ToxicMap>,
+    rolls: HashMap<String, bool>,
+    active: Vec<ActiveToxic>,
+}
+
+impl ToxicPipeline {
+    pub fn new(registry: &ToxicRegistry, route: &str, direction: Direction) -> Self {
+        let mut rx = registry.subscribe();
+        rx.mark_changed();
+        let mut pipeline = Self {
+            route: route.to_string(),
+            direction,
+            rx,
+            rolls: HashMap::new(),
+            active: Vec::new(),
+        };
+        pipeline.refresh();
+        pipeline
+    }
+
+    pub fn changes(&self) -> watch::Receiver<ToxicMap> {
+        self.rx.clone()
+    }
+
+    fn refresh(&mut self) {
+        if !self.rx.has_changed().unwrap_or(false) {
+            return;
+        }
+        let live = self.rx.borrow_and_update().clone();
+        let toxics = live.get(&self.route).map(Vec::as_slice).unwrap_or_default();
+
+        let mut previous = std::mem::take(&mut self.active);
+        for toxic in toxics {
+            if !toxic.enabled || toxic.stream != self.direction {
+                continue;
+            }
+            let rolled_in = *self
+                .rolls
+                .entry(toxic.name.clone())
+                .or_insert_with(|| fastrand::f64() < toxic.toxicity);
+            if !rolled_in {
+                continue;
+            }
+            let kept = previous
+                .iter()
+                .position(|active| active.config == *toxic)
+                .map(|idx| previous.swap_remove(idx));
+            self.active
+                .push(kept.unwrap_or_else(|| ActiveToxic::new(toxic.clone())));
+        }
+    }
+
+    pub fn is_idle(&mut self) -> bool {
+        self.refresh();
+        self.active.is_empty()
+    }
+
+    // Timeout and reset toxics fire on their own, even when no data is flowing.
+    pub fn deadline(&mut self) -> Option<(Instant, Verdict)> {
+        self.refresh();
+        self.active
+            .iter()
+            .filter_map(|active| match active.config.kind {
+                ToxicKind::Timeout { timeout } if timeout > 0 => Some((
+                    active.since + Duration::from_millis(timeout),
+                    Verdict::Close,
+                )),
+                ToxicKind::ResetPeer { timeout } => Some((
+                    active.since + Duration::from_millis(timeout),
+                    Verdict::Reset,
+                )),
+                _ => None,
+            })
+            .min_by_key(|(at, _)| *at)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub async fn forward<W: AsyncWrite + Unpin>(
+        &mut self,
+        data: &mut [u8],
+        read_at: Instant,
+        writer: &mut W,
+    ) -> io::Result<Verdict> {
+        self.refresh();
+        let blackholed = self
+            .active
+            .iter()
+            .any(|active| matches!(active.config.kind, ToxicKind::Timeout { .. }));
+        if blackholed {
+            return Ok(Verdict::Continue);
+        }
+
+        let mut slicer = None;
+        for active in &mut self.active {
+            match active.config.kind {
+                ToxicKind::Corrupt { probability } => {
+                    for byte in data.iter_mut() {
+                        if fastrand::f64() < probability {
+                            *byte ^= 1 << fastrand::u8(0..8);
+                        }
+                    }
+                }
+                ToxicKind::Latency { latency, jitter } => {
+                    let delay = latency.saturating_add(fastrand::u64(0..=jitter.saturating_mul(2)));
+                    let delay = delay.saturating_sub(jitter);
+                    sleep_until((read_at + Duration::from_millis(delay)).into()).await;
+                }
+                ToxicKind::Bandwidth { .. } => {
+                    if let Some(bucket) = active.bucket.as_mut() {
+                        let wait = bucket.reserve(data.len() as f64);
+                        if !wait.is_zero() {
+                            sleep(wait).await;
+                        }
+                    }
+                }
+                ToxicKind::Slicer {
+                    average_size,
+                    size_variation,
+                    delay,
+                } => slicer = Some((average_size, size_variation, delay)),
+                ToxicKind::Timeout { .. } | ToxicKind::ResetPeer { .. } => {}
+            }
+        }
+
+        let Some((average_size, size_variation, delay)) = slicer else {
+            writer.write_all(data).await?;
+            return Ok(Verdict::Continue);
+        };
+        let mut rest: &[u8] = data;
+        while !rest.is_empty() {
+            let variation = size_variation.min(average_size - 1);
+            let size = average_size - variation + fastrand::usize(0..=variation * 2);
+            let (slice, tail) = rest.split_at(size.min(rest.len()));
+            writer.write_all(slice).await?;
+            writer.flush().await?;
+            rest = tail;
+            if !rest.is_empty() && delay > 0 {
+                sleep(Duration::from_micros(delay)).await;
+            }
+        }
+        Ok(Verdict::Continue)
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+
+use http_body_util::Full;
+use hyper::{Method, Response, StatusCode, body::Bytes};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::{
+    net::lookup_host,
+    sync::{mpsc, oneshot},
+};
+
+use crate::{AppError, RouteConfig, ToxicConfig, ToxicRegistry, unix_path};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    NotFound(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("proxy is shutting down")]
+    Unavailable,
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProxyInfo {
+    pub name: String,
+    pub listen: String,
+    pub upstream: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProxySpec {
+    pub name: String,
+    pub listen: String,
+    pub upstream: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl ProxySpec {
+    pub fn into_route(self) -> RouteConfig {
+        RouteConfig {
+            name: self.name,
+            listen_addr: self.listen,
+            target_addr: self.upstream,
+            enabled: self.enabled,
+            ..RouteConfig::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ProxyPatch {
+    pub listen: Option<String>,
+    pub upstream: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+// Proxy changes go through the main loop, which owns the listeners and the live config.
+pub enum AdminOp {
+    List,
+    Create(ProxySpec),
+    Update(String, ProxyPatch),
+    Delete(String),
+    Populate(Vec<ProxySpec>),
+    EnableAll,
+}
+
+pub struct AdminRequest {
+    pub op: AdminOp,
+    pub reply: oneshot::Sender<Result<Vec<ProxyInfo>, ApiError>>,
+}
+
+type ApiResult = Result<(StatusCode, Value), ApiError>;
+
+pub fn is_toxiproxy_path(path: &str) -> bool {
+    ["/version", "/reset", "/populate", "/proxies"]
+        .iter()
+        .any(|prefix| path == *prefix || path.starts_with(&format!("{}/", prefix)))
+}
+
+pub async fn handle_toxiproxy_request(
+    method: &Method,
+    path: &str,
+    body: &[u8],
+    toxics: &ToxicRegistry,
+    admin_tx: &mpsc::Sender<AdminRequest>,
+) -> Result<Response<Full<Bytes>>, AppError> {
+    let (status, value) = match route_request(method, path, body, toxics, admin_tx).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            let status = e.status();
+            (
+                status,
+                json!({ "error": e.to_string(), "status": status.as_u16() }),
+            )
+        }
+    };
+    let builder = Response::builder().status(status);
+    let response = if status == StatusCode::NO_CONTENT {
+        builder.body(Full::new(Bytes::new()))?
+    } else {
+        builder
+            .header("Content-Type", "application/json")
+            .body(Full::new(Bytes::from(serde_json::to_string(&value)?)))?
+    };
+    Ok(response)
+}
+
+async fn route_request(
+    method: &Method,
+    path: &str,
+    body: &[u8],
+    toxics: &ToxicRegistry,
+    admin_tx: &mpsc::Sender<AdminRequest>,
+) -> ApiResult {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["version"]) => Ok((
+            StatusCode::OK,
+            json!({ "version": env!("CARGO_PKG_VERSION") }),
+        )),
+        (&Method::POST, ["reset"]) => {
+            toxics.clear();
+            admin(admin_tx, AdminOp::EnableAll).await?;
+            println!("[TOXIPROXY] Reset: all proxies enabled, all toxics removed");
+            Ok((StatusCode::NO_CONTENT, Value::Null))
+        }
+        (&Method::POST, ["populate"]) => {
+            let mut specs: Vec<ProxySpec> = parse_body(body)?;
+            for spec in &mut specs {
+                spec.listen = resolve_listen(&spec.listen).await?;
+            }
+            let proxies = admin(admin_tx, AdminOp::Populate(specs)).await?;
+            let proxies: Vec<Value> = proxies.iter().map(|p| proxy_json(p, toxics)).collect();
+            Ok((StatusCode::CREATED, json!({ "proxies": proxies })))
+        }
+        (&Method::GET, ["proxies"]) => {
+            let proxies: BTreeMap<String, Value> = admin(admin_tx, AdminOp::List)
+                .await?
+                .iter()
+                .map(|proxy| (proxy.name.clone(), proxy_json(proxy, toxics)))
+                .collect();
+            Ok((StatusCode::OK, json!(proxies)))
+        }
+        (&Method::POST, ["proxies"]) => {
+            let mut spec: ProxySpec = parse_body(body)?;
+            spec.listen = resolve_listen(&spec.listen).await?;
+            let name = spec.name.clone();
+            let proxy = single(admin(admin_tx, AdminOp::Create(spec)).await?)?;
+            println!("[TOXIPROXY] Created proxy '{}'", name);
+            Ok((StatusCode::CREATED, proxy_json(&proxy, toxics)))
+        }
+        (&Method::GET, ["proxies", name]) => {
+            let proxy = find_proxy(admin_tx, name).await?;
+            Ok((StatusCode::OK, proxy_json(&proxy, toxics)))
+        }
+        (&Method::POST, ["proxies", name]) => {
+            let mut changes: ProxyPatch = parse_body(body)?;
+            if let Some(listen) = &changes.listen {
+                changes.listen = Some(resolve_listen(listen).await?);
+            }
+            let op = AdminOp::Update((*name).to_string(), changes);
+            let proxy = single(admin(admin_tx, op).await?)?;
+            println!("[TOXIPROXY] Updated proxy '{}'", name);
+            Ok((StatusCode::OK, proxy_json(&proxy, toxics)))
+        }
+        (&Method::DELETE, ["proxies", name]) => {
+            admin(admin_tx, AdminOp::Delete((*name).to_string())).await?;
+            println!("[TOXIPROXY] Deleted proxy '{}'", name);
+            Ok((StatusCode::NO_CONTENT, Value::Null))
+        }
+        (&Method::GET, ["proxies", name, "toxics"]) => {
+            let route_toxics = toxics
+                .route_toxics(name)
+                .ok_or_else(|| ApiError::NotFound("proxy not found".to_string()))?;
+            Ok((StatusCode::OK, json!(route_toxics)))
+        }
+        (&Method::POST, ["proxies", name, "toxics"]) => {
+            let toxic = toxics.add(name, parse_toxic(body)?)?;
+            println!(
+                "[TOXIPROXY] Added toxic '{}' to proxy '{}'",
+                toxic.name, name
+            );
+            Ok((StatusCode::OK, json!(toxic)))
+        }
+        (&Method::GET, ["proxies", name, "toxics", toxic]) => {
+            let found = toxics
+                .route_toxics(name)
+                .ok_or_else(|| ApiError::NotFound("proxy not found".to_string()))?
+                .into_iter()
+                .find(|t| t.name == *toxic)
+                .ok_or_else(|| ApiError::NotFound("toxic not found".to_string()))?;
+            Ok((StatusCode::OK, json!(found)))
+        }
+        (&Method::POST, ["proxies", name, "toxics", toxic]) => {
+            let changes: Value = parse_body(body)?;
+            let updated = toxics.update(name, toxic, &changes)?;
+            println!("[TOXIPROXY] Updated toxic '{}' on proxy '{}'", toxic, name);
+            Ok((StatusCode::OK, json!(updated)))
+        }
+        (&Method::DELETE, ["proxies", name, "toxics", toxic]) => {
+            toxics.remove(name, toxic)?;
+            println!(
+                "[TOXIPROXY] Removed toxic '{}' from proxy '{}'",
+                toxic, name
+            );
+            Ok((StatusCode::NO_CONTENT, Value::Null))
+        }
+        _ => Err(ApiError::NotFound("not found".to_string())),
+    }
+}
+
+async fn admin(
+    admin_tx: &mpsc::Sender<AdminRequest>,
+    op: AdminOp,
+) -> Result<Vec<ProxyInfo>, ApiError> {
+    let (reply, reply_rx) = oneshot::channel();
+    admin_tx
+        .send(AdminRequest { op, reply })
+        .await
+        .map_err(|_| ApiError::Unavailable)?;
+    reply_rx.await.map_err(|_| ApiError::Unavailable)?
+}
+
+async fn find_proxy(
+    admin_tx: &mpsc::Sender<AdminRequest>,
+    name: &str,
+) -> Result<ProxyInfo, ApiError> {
+    admin(admin_tx, AdminOp::List)
+        .await?
+        .into_iter()
+        .find(|proxy| proxy.name == name)
+        .ok_or_else(|| ApiError::NotFound("proxy not found".to_string()))
+}
+
+fn single(proxies: Vec<ProxyInfo>) -> Result<ProxyInfo, ApiError> {
+    proxies
+        .into_iter()
+        .next()
+        .ok_or_else(|| ApiError::NotFound("proxy not found".to_string()))
+}
+
+fn proxy_json(proxy: &ProxyInfo, toxics: &ToxicRegistry) -> Value {
+    let mut value = json!(proxy);
+    value["toxics"] = json!(toxics.route_toxics(&proxy.name).unwrap_or_default());
+    value
+}
+
+fn parse_body<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(|e| ApiError::BadRequest(e.to_string()))
+}
+
+// toxiproxy clients may leave out the name, stream and attributes of a new toxic.
+fn parse_toxic(body: &[u8]) -> Result<ToxicConfig, ApiError> {
+    let mut value: Value = parse_body(body)?;
+    let Some(fields) = value.as_object_mut() else {
+        return Err(ApiError::BadRequest(
+            "toxic must be a JSON object".to_string(),
+        ));
+    };
+    let stream = fields
+        .entry("stream")
+        .or_insert_with(|| json!("downstream"))
+        .clone();
+    fields.entry("attributes").or_insert_with(|| json!({}));
+    if !fields.contains_key("name") {
+        let kind = fields
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("toxic");
+        let name = format!("{}_{}", kind, stream.as_str().unwrap_or("downstream"));
+        fields.insert("name".to_string(), json!(name));
+    }
+    serde_json::from_value(value).map_err(|e| ApiError::BadRequest(e.to_string()))
+}
+
+// Clients usually ask for "localhost:0"; the route config needs a literal socket address.
+async fn resolve_listen(listen: &str) -> Result<String, ApiError> {
+    if listen.parse::<std::net::SocketAddr>().is_ok() || unix_path(listen).is_some() {
+        return Ok(listen.to_string());
+    }
+    lookup_host(listen)
+        .await
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .map(|addr| addr.to_string())
+        .ok_or_else(|| ApiError::BadRequest(format!("invalid listen address '{}'", listen)))
+}
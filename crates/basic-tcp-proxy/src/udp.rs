@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    net::UdpSocket,
+    select,
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
+    task::JoinSet,
+    time::sleep,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{Config, MetricEvent, Resolver, UdpRouteConfig};
+
+// Datagrams queued for one client's upstream socket; beyond that they are dropped, as UDP would.
+const SESSION_QUEUE_SIZE: usize = 1024;
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+#[derive(Clone)]
+pub struct UdpContext {
+    pub route: UdpRouteConfig,
+    pub config_rx: watch::Receiver<Arc<Config>>,
+    pub graceful_token: CancellationToken,
+    pub metrics_tx: mpsc::Sender<MetricEvent>,
+    pub resolver: Arc<Resolver>,
+}
+
+// Relays datagrams between clients and the route's target until the graceful token is cancelled.
+// Each client address is a session with its own connected upstream socket, reported to the
+// metrics like a TCP connection: opened on its first datagram, closed once it has been idle.
+pub async fn run_udp_server(socket: UdpSocket, ctx: UdpContext) {
+    let socket = Arc::new(socket);
+    let mut sessions: HashMap<SocketAddr, mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut session_tasks = JoinSet::new();
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let (n, client_addr) = select! {
+            result = socket.recv_from(&mut buf) => match result {
+                Ok(received) => received,
+                Err(e) => {
+                    eprintln!("[UDP] Failed to receive on route '{}': {}", ctx.route.name, e);
+                    continue;
+                }
+            },
+            _ = ctx.graceful_token.cancelled() => break,
+        };
+        let datagram = buf[..n].to_vec();
+
+        let datagram = match sessions.get(&client_addr) {
+            Some(tx) => match tx.try_send(datagram) {
+                Ok(()) | Err(TrySendError::Full(_)) => continue,
+                // The session expired since this client's last datagram.
+                Err(TrySendError::Closed(datagram)) => datagram,
+            },
+            None => datagram,
+        };
+
+        let access = ctx.config_rx.borrow().access.check(client_addr.ip());
+        if let Err(rule) = access {
+            let _ = ctx
+                .metrics_tx
+                .send(MetricEvent::ConnectionDenied(client_addr, rule))
+                .await;
+            continue;
+        }
+
+        sessions.retain(|_, tx| !tx.is_closed());
+        while session_tasks.try_join_next().is_some() {}
+
+        let (tx, rx) = mpsc::channel(SESSION_QUEUE_SIZE);
+        let _ = tx.try_send(datagram);
+        sessions.insert(client_addr, tx);
+        session_tasks.spawn(run_session(
+            Arc::clone(&socket),
+            client_addr,
+            rx,
+            ctx.clone(),
+        ));
+    }
+
+    println!(
+        "Stopped relaying datagrams on udp route '{}'",
+        ctx.route.name
+    );
+    drop(sessions);
+    session_tasks.join_all().await;
+}
+
+// Datagrams have no handshake to race, so the session sticks to the most preferred address.
+async fn connect_upstream(target_addr: &str, resolver: &Resolver) -> io::Result<UdpSocket> {
+    let target = resolver.resolve(target_addr).await?[0];
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let upstream = UdpSocket::bind(local).await?;
+    upstream.connect(target).await?;
+    Ok(upstream)
+}
+
+// The upstream socket is opened here rather than in the receive loop, so a slow lookup for one
+// client never holds up datagrams for the others. Its first datagrams wait in the queue meanwhile.
+async fn run_session(
+    socket: Arc<UdpSocket>,
+    client_addr: SocketAddr,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    ctx: UdpContext,
+) {
+    let upstream = select! {
+        result = connect_upstream(&ctx.route.target_addr, &ctx.resolver) => result,
+        _ = ctx.graceful_token.cancelled() => return,
+    };
+    let upstream = match upstream {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            eprintln!(
+                "[UDP] Failed to open upstream {} for {}: {}",
+                ctx.route.target_addr, client_addr, e
+            );
+            return;
+        }
+    };
+
+    let idle_timeout = Duration::from_secs(ctx.route.idle_timeout_secs);
+    let _ = ctx
+        .metrics_tx
+        .send(MetricEvent::ConnectionOpened(client_addr))
+        .await;
+
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        select! {
+            Some(datagram) = rx.recv() => {
+                // An unreachable target shows up as an error here; the client may retry.
+                if upstream.send(&datagram).await.is_ok() {
+                    let _ = ctx
+                        .metrics_tx
+                        .send(MetricEvent::BytesUpstream(client_addr, datagram.len() as u64))
+                        .await;
+                }
+            }
+            result = upstream.recv(&mut buf) => {
+                let Ok(n) = result else { continue };
+                if socket.send_to(&buf[..n], client_addr).await.is_ok() {
+                    let _ = ctx
+                        .metrics_tx
+                        .send(MetricEvent::BytesDownstream(client_addr, n as u64))
+                        .await;
+                }
+            }
+            () = sleep(idle_timeout) => break,
+            _ = ctx.graceful_token.cancelled() => break,
+        }
+    }
+
+    // Closing the queue first makes the next datagram from this client start a new session.
+    rx.close();
+    let _ = ctx
+        .metrics_tx
+        .send(MetricEvent::ConnectionClosed(client_addr))
+        .await;
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    io::{self, IoSlice, IoSliceMut},
+    mem::MaybeUninit,
+    os::fd::{AsFd, BorrowedFd, OwnedFd},
+    path::Path,
+};
+
+use rustix::net::{
+    RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, SendAncillaryBuffer,
+    SendAncillaryMessage, SendFlags, recvmsg, sendmsg,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, Interest},
+    net::{TcpListener, UnixStream},
+};
+
+use crate::AppError;
+
+const MAX_HANDOFF_FDS: usize = 64;
+const MAX_MANIFEST_LEN: usize = 64 * 1024;
+const ACK: u8 = b'k';
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    routes: Vec<String>,
+    metrics: bool,
+}
+
+pub struct InheritedListeners {
+    pub routes: HashMap<String, TcpListener>,
+    pub metrics: Option<TcpListener>,
+}
+
+pub async fn send_listeners(
+    stream: &mut UnixStream,
+    routes: &[(&str, BorrowedFd<'_>)],
+    metrics: Option<BorrowedFd<'_>>,
+) -> Result<(), AppError> {
+    let manifest = Manifest {
+        routes: routes.iter().map(|(name, _)| (*name).to_string()).collect(),
+        metrics: metrics.is_some(),
+    };
+    let payload = serde_json::to_vec(&manifest)?;
+    let fds: Vec<BorrowedFd<'_>> = routes.iter().map(|(_, fd)| *fd).chain(metrics).collect();
+    if fds.len() > MAX_HANDOFF_FDS {
+        return Err(AppError::Unexpected(format!(
+            "cannot hand off more than {} listeners",
+            MAX_HANDOFF_FDS
+        )));
+    }
+
+    // Length prefix travels with the descriptors so the receiver gets both in one recvmsg.
+    let len = u32::try_from(payload.len())
+        .map_err(|_| AppError::Unexpected("handoff manifest too large".to_string()))?
+        .to_be_bytes();
+    let mut space = vec![MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(fds.len()))];
+    stream
+        .async_io(Interest::WRITABLE, || {
+            let mut control = SendAncillaryBuffer::new(&mut space);
+            control.push(SendAncillaryMessage::ScmRights(&fds));
+            sendmsg(
+                &*stream,
+                &[IoSlice::new(&len)],
+                &mut control,
+                SendFlags::empty(),
+            )
+            .map_err(io::Error::from)
+        })
+        .await?;
+    stream.write_all(&payload).await?;
+
+    let mut ack = [0u8; 1];
+    stream.read_exact(&mut ack).await?;
+    if ack[0] != ACK {
+        return Err(AppError::Unexpected(
+            "new process did not acknowledge the handoff".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn receive_listeners(path: &Path) -> Result<Option<InheritedListeners>, AppError> {
+    let mut stream = match UnixStream::connect(path).await {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut len = [0u8; 4];
+    let mut space = vec![MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(MAX_HANDOFF_FDS))];
+    let mut fds: Vec<OwnedFd> = Vec::new();
+    let received = stream
+        .async_io(Interest::READABLE, || {
+            let mut control = RecvAncillaryBuffer::new(&mut space);
+            let msg = recvmsg(
+                &stream,
+                &mut [IoSliceMut::new(&mut len)],
+                &mut control,
+                RecvFlags::CMSG_CLOEXEC,
+            )
+            .map_err(io::Error::from)?;
+            for message in control.drain() {
+                if let RecvAncillaryMessage::ScmRights(rights) = message {
+                    fds.extend(rights);
+                }
+            }
+            Ok(msg.bytes)
+        })
+        .await?;
+    if received != len.len() {
+        return Err(AppError::Unexpected(
+            "truncated handoff header from old process".to_string(),
+        ));
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MANIFEST_LEN {
+        return Err(AppError::Unexpected(
+            "handoff manifest too large".to_string(),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    let manifest: Manifest = serde_json::from_slice(&payload)?;
+
+    let expected = manifest.routes.len() + usize::from(manifest.metrics);
+    if fds.len() != expected {
+        return Err(AppError::Unexpected(format!(
+            "expected {} listener(s) from old process, got {}",
+            expected,
+            fds.len()
+        )));
+    }
+
+    let mut fds = fds.into_iter();
+    let mut routes = HashMap::new();
+    for name in manifest.routes {
+        let fd = fds.next().expect("fd count checked above");
+        routes.insert(name, into_listener(fd)?);
+    }
+    let metrics = fds.next().map(into_listener).transpose()?;
+
+    stream.write_all(&[ACK]).await?;
+
+    Ok(Some(InheritedListeners { routes, metrics }))
+}
+
+pub fn dup_listener(listener: &TcpListener) -> Result<OwnedFd, io::Error> {
+    listener.as_fd().try_clone_to_owned()
+}
+
+pub fn into_listener(listener: impl Into<std::net::TcpListener>) -> Result<TcpListener, io::Error> {
+    let listener = listener.into();
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
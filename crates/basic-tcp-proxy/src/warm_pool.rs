@@ -0,0 +1,182 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use tokio::{select, sync::Notify, time::sleep};
+use tokio_util::sync::CancellationToken;
+
+use crate::{RouteContext, Stream, tcp_established};
+
+// How often idle connections are checked for expiry and for the target closing them.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+const CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+struct WarmConnection {
+    stream: Stream,
+    target_addr: String,
+    since: Instant,
+    // The refill task that opened it. A route re-spawned by a reload gets a new task, which
+    // must not count or clean up after the previous one.
+    filler: u64,
+}
+
+impl WarmConnection {
+    // TCP connections go by the kernel's state, which also catches a greeting followed by a
+    // close. Data the target sent already stays queued for the client either way.
+    fn is_alive(&self) -> bool {
+        match &self.stream {
+            Stream::Tcp(stream) => tcp_established(stream),
+            // Unix sockets have no such state; RDHUP reports the target's shutdown all the same.
+            Stream::Unix(stream) => {
+                let mut fds = [PollFd::new(stream, PollFlags::RDHUP)];
+                let closed = PollFlags::RDHUP | PollFlags::HUP | PollFlags::ERR;
+                poll(&mut fds, Some(&Timespec::default()))
+                    .is_ok_and(|_| !fds[0].revents().intersects(closed))
+            }
+        }
+    }
+}
+
+// Connections opened ahead of time to route targets, keyed by route. Each route with a
+// `warm_pool` keeps it filled from a background task.
+#[derive(Default)]
+pub struct WarmPool {
+    idle: Mutex<HashMap<String, Vec<WarmConnection>>>,
+    // Wakes the refill tasks after a connection was taken.
+    taken: Notify,
+    next_filler: AtomicU64,
+}
+
+impl WarmPool {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    // The newest idle connection to `target_addr` that is still usable. Expired or closed ones
+    // found on the way are dropped.
+    pub fn checkout(&self, route: &str, target_addr: &str, max_idle: Duration) -> Option<Stream> {
+        let mut idle = self.idle.lock().expect("warm pool poisoned");
+        let connections = idle.get_mut(route)?;
+        let mut found = None;
+        while let Some(conn) = connections.pop() {
+            if conn.target_addr == target_addr && conn.since.elapsed() < max_idle && conn.is_alive()
+            {
+                found = Some(conn.stream);
+                break;
+            }
+        }
+        drop(idle);
+        self.taken.notify_waiters();
+        found
+    }
+
+    fn idle_count(&self, route: &str, filler: u64) -> usize {
+        let idle = self.idle.lock().expect("warm pool poisoned");
+        idle.get(route).map_or(0, |connections| {
+            connections
+                .iter()
+                .filter(|conn| conn.filler == filler)
+                .count()
+        })
+    }
+
+    // Drops idle connections that expired, were closed by the target or no longer match the
+    // route's config.
+    fn sweep(&self, route: &str, target_addr: Option<&str>, max_idle: Duration) {
+        let mut idle = self.idle.lock().expect("warm pool poisoned");
+        if let Some(connections) = idle.get_mut(route) {
+            connections.retain(|conn| {
+                target_addr == Some(conn.target_addr.as_str())
+                    && conn.since.elapsed() < max_idle
+                    && conn.is_alive()
+            });
+        }
+    }
+
+    fn add(&self, route: &str, stream: Stream, target_addr: String, filler: u64) {
+        let mut idle = self.idle.lock().expect("warm pool poisoned");
+        idle.entry(route.to_string())
+            .or_default()
+            .push(WarmConnection {
+                stream,
+                target_addr,
+                since: Instant::now(),
+                filler,
+            });
+    }
+
+    // Drops what one refill task opened, leaving a successor's connections in place.
+    fn remove_filler(&self, route: &str, filler: u64) {
+        let mut idle = self.idle.lock().expect("warm pool poisoned");
+        if let Some(connections) = idle.get_mut(route) {
+            connections.retain(|conn| conn.filler != filler);
+            if connections.is_empty() {
+                idle.remove(route);
+            }
+        }
+    }
+
+    // Keeps the route's pool at its configured size until `stop` is cancelled, following config
+    // reloads. Routes without a `warm_pool` just wait for one to be configured.
+    pub async fn refill(&self, ctx: &RouteContext, stop: &CancellationToken) {
+        let filler = self.next_filler.fetch_add(1, Ordering::Relaxed);
+        let mut config_rx = ctx.config_rx.clone();
+        loop {
+            let config = config_rx.borrow_and_update().clone();
+            let wanted = config.route(&ctx.route).and_then(|route| {
+                let warm_pool = route.warm_pool?;
+                Some((route.target_addr, warm_pool))
+            });
+            let max_idle = wanted.as_ref().map_or(Duration::ZERO, |(_, warm_pool)| {
+                Duration::from_secs(warm_pool.max_idle_secs)
+            });
+            let target_addr = wanted.as_ref().map(|(target_addr, _)| target_addr.as_str());
+            self.sweep(&ctx.route, target_addr, max_idle);
+
+            if let Some((target_addr, warm_pool)) = &wanted
+                && self.idle_count(&ctx.route, filler) < warm_pool.size
+            {
+                // Without fast open, so `is_alive` can go by the connection's TCP state.
+                let result = select! {
+                    result = Stream::connect_handshaken(target_addr, &ctx.resolver) => result,
+                    _ = stop.cancelled() => break,
+                };
+                match result {
+                    Ok(stream) => {
+                        self.add(&ctx.route, stream, target_addr.clone(), filler);
+                        continue;
+                    }
+                    Err(e) => {
+                        println!(
+                            "[WARM] Route '{}' failed to connect to {}: {}",
+                            ctx.route, target_addr, e
+                        );
+                        select! {
+                            () = sleep(CONNECT_BACKOFF) => {}
+                            _ = stop.cancelled() => break,
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            select! {
+                () = self.taken.notified(), if wanted.is_some() => {}
+                () = sleep(SWEEP_INTERVAL), if wanted.is_some() => {}
+                result = config_rx.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                _ = stop.cancelled() => break,
+            }
+        }
+        self.remove_filler(&ctx.route, filler);
+    }
+}
@@ -0,0 +1,230 @@
+use std::io;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha1::{Digest, Sha1};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, duplex, split},
+    select,
+    sync::mpsc,
+};
+
+// RFC 6455 section 1.3: appended to the client's key before hashing it into the accept value.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+// Payload lengths that don't fit the 7-bit field are sent in the next 2 or 8 bytes.
+const EXTENDED_16: u8 = 126;
+const EXTENDED_64: u8 = 127;
+
+// Larger frames are refused rather than buffered.
+const MAX_PAYLOAD: usize = 16 * 1024 * 1024;
+// RFC 6455 section 5.5: control frames fit the 7-bit length and are never fragmented.
+const MAX_CONTROL_PAYLOAD: u8 = 125;
+
+// Status codes sent in the close frame when the peer breaks the protocol.
+const PROTOCOL_ERROR: u16 = 1002;
+const MESSAGE_TOO_BIG: u16 = 1009;
+const BUFFER_SIZE: usize = 16 * 1024;
+
+// Clients mask every frame they send; servers must not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsRole {
+    Client,
+    Server,
+}
+
+// The `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub fn websocket_accept_key(key: &str) -> String {
+    STANDARD.encode(Sha1::digest(format!("{}{}", key, HANDSHAKE_GUID)))
+}
+
+struct Frame {
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+enum FrameError {
+    Io,
+    // The peer broke RFC 6455; answered with a close frame carrying this status code.
+    Protocol(u16),
+}
+
+impl From<io::Error> for FrameError {
+    fn from(_: io::Error) -> Self {
+        Self::Io
+    }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+// `None` once the peer closed the connection between frames. `role` is our side: a server only
+// takes masked frames and a client only unmasked ones (RFC 6455 section 5.1).
+async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    role: WsRole,
+) -> Result<Option<Frame>, FrameError> {
+    let mut header = [0u8; 2];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let fin = header[0] & 0x80 != 0;
+    let opcode = header[0] & 0x0f;
+    let masked = header[1] & 0x80 != 0;
+    // No extension is negotiated, so the RSV bits must be clear.
+    let rsv = header[0] & 0x70 != 0;
+    let known = matches!(opcode, CONTINUATION | TEXT | BINARY | CLOSE | PING | PONG);
+    let control = opcode & 0x08 != 0;
+    let control_ok = fin && header[1] & 0x7f <= MAX_CONTROL_PAYLOAD;
+    if rsv || !known || masked != (role == WsRole::Server) || (control && !control_ok) {
+        return Err(FrameError::Protocol(PROTOCOL_ERROR));
+    }
+
+    let len = match header[1] & 0x7f {
+        EXTENDED_16 => u64::from(reader.read_u16().await?),
+        EXTENDED_64 => reader.read_u64().await?,
+        len => u64::from(len),
+    };
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= MAX_PAYLOAD)
+        .ok_or(FrameError::Protocol(MESSAGE_TOO_BIG))?;
+    let mask = if masked {
+        let mut mask = [0u8; 4];
+        reader.read_exact(&mut mask).await?;
+        Some(mask)
+    } else {
+        None
+    };
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    Ok(Some(Frame { opcode, payload }))
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    opcode: u8,
+    payload: &[u8],
+    role: WsRole,
+) -> io::Result<()> {
+    let mask_bit = if role == WsRole::Client { 0x80 } else { 0 };
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match (u8::try_from(payload.len()), u16::try_from(payload.len())) {
+        (Ok(len), _) if len < EXTENDED_16 => frame.push(mask_bit | len),
+        (_, Ok(len)) => {
+            frame.push(mask_bit | EXTENDED_16);
+            frame.extend_from_slice(&len.to_be_bytes());
+        }
+        _ => {
+            frame.push(mask_bit | EXTENDED_64);
+            frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        }
+    }
+    let start = frame.len();
+    if role == WsRole::Client {
+        let mask = fastrand::u32(..).to_be_bytes();
+        frame.extend_from_slice(&mask);
+        frame.extend_from_slice(payload);
+        apply_mask(&mut frame[start + 4..], mask);
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    writer.write_all(&frame).await
+}
+
+// Turns a WebSocket connection into a plain byte stream: bytes written to it go out as binary
+// frames, and the payloads of incoming data frames can be read back. Pings are answered, and a
+// close frame from either end closes both. Frames breaking the protocol close it with a 1002.
+pub fn websocket_byte_stream<S>(websocket: S, role: WsRole) -> DuplexStream
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (local, remote) = duplex(BUFFER_SIZE);
+    let (mut ws_read, mut ws_write) = split(websocket);
+    let (mut bytes_read, mut bytes_write) = split(remote);
+    let (control_tx, mut control_rx) = mpsc::channel::<Frame>(4);
+
+    tokio::spawn(async move {
+        loop {
+            let frame = match read_frame(&mut ws_read, role).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) | Err(FrameError::Io) => break,
+                Err(FrameError::Protocol(code)) => {
+                    let close = Frame {
+                        opcode: CLOSE,
+                        payload: code.to_be_bytes().to_vec(),
+                    };
+                    let _ = control_tx.send(close).await;
+                    break;
+                }
+            };
+            let open = match frame.opcode {
+                CONTINUATION | TEXT | BINARY => bytes_write.write_all(&frame.payload).await.is_ok(),
+                PING => {
+                    let pong = Frame {
+                        opcode: PONG,
+                        payload: frame.payload,
+                    };
+                    let _ = control_tx.send(pong).await;
+                    true
+                }
+                CLOSE => {
+                    let close = Frame {
+                        opcode: CLOSE,
+                        payload: Vec::new(),
+                    };
+                    let _ = control_tx.send(close).await;
+                    false
+                }
+                _ => true,
+            };
+            if !open {
+                break;
+            }
+        }
+        let _ = bytes_write.shutdown().await;
+    });
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        loop {
+            select! {
+                result = bytes_read.read(&mut buf) => match result {
+                    Ok(0) | Err(_) => {
+                        let _ = write_frame(&mut ws_write, CLOSE, &[], role).await;
+                        break;
+                    }
+                    Ok(n) => {
+                        if write_frame(&mut ws_write, BINARY, &buf[..n], role).await.is_err() {
+                            break;
+                        }
+                    }
+                },
+                Some(frame) = control_rx.recv() => {
+                    let sent = write_frame(&mut ws_write, frame.opcode, &frame.payload, role).await;
+                    if sent.is_err() || frame.opcode == CLOSE {
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = ws_write.shutdown().await;
+    });
+
+    local
+}
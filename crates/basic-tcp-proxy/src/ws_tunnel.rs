@@ -0,0 +1,292 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    os::fd::{AsFd, OwnedFd},
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use http_body_util::{Empty, Full};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    client::conn::http1 as client_http1,
+    header::{self, HeaderMap},
+    server::conn::http1,
+    service::service_fn,
+    upgrade::OnUpgrade,
+};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+
+use crate::{
+    Resolver, Stream, WsRole, WsTunnelConfig, websocket_accept_key, websocket_byte_stream,
+};
+
+// Handshakes that take longer than this are given up on, on both sides.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum WsTunnelError {
+    #[error("failed to connect: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] hyper::Error),
+
+    #[error("handshake timed out")]
+    Timeout,
+
+    #[error("server answered {0}")]
+    Rejected(StatusCode),
+
+    #[error("server sent a wrong Sec-WebSocket-Accept")]
+    BadAccept,
+
+    #[error("invalid request: {0}")]
+    Request(#[from] hyper::http::Error),
+}
+
+impl WsTunnelError {
+    // A refused token or path won't be accepted on the next try either.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Rejected(status) => status.is_server_error(),
+            Self::BadAccept | Self::Request(_) => false,
+            Self::Io(_) | Self::Http(_) | Self::Timeout => true,
+        }
+    }
+}
+
+// Opens a tunnel to the server at `target_addr`, retrying with a doubling backoff while it is
+// unreachable. Returns the tunnel as a byte stream and a handle to its socket.
+pub async fn ws_tunnel_connect(
+    target_addr: &str,
+    config: &WsTunnelConfig,
+    resolver: &Resolver,
+) -> Result<(DuplexStream, OwnedFd), WsTunnelError> {
+    let mut backoff = Duration::from_millis(config.retry_backoff_ms);
+    let mut attempt = 1;
+    loop {
+        let result = match timeout(HANDSHAKE_TIMEOUT, dial(target_addr, config, resolver)).await {
+            Ok(result) => result,
+            Err(_) => Err(WsTunnelError::Timeout),
+        };
+        match result {
+            Err(e) if e.is_retryable() && attempt < config.connect_attempts => {
+                println!(
+                    "[WS] Tunnel to {} failed (attempt {}/{}): {}; retrying in {:?}",
+                    target_addr, attempt, config.connect_attempts, e, backoff
+                );
+                sleep(backoff).await;
+                backoff = backoff.saturating_mul(2);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn dial(
+    target_addr: &str,
+    config: &WsTunnelConfig,
+    resolver: &Resolver,
+) -> Result<(DuplexStream, OwnedFd), WsTunnelError> {
+    let stream = Stream::connect(target_addr, resolver).await?;
+    let socket = stream.as_fd().try_clone_to_owned()?;
+    let (mut sender, connection) = client_http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        let _ = connection.with_upgrades().await;
+    });
+
+    // Unix socket targets have no meaningful host to put in the header.
+    let host = config
+        .host
+        .as_deref()
+        .unwrap_or(if target_addr.starts_with("unix:") {
+            "localhost"
+        } else {
+            target_addr
+        });
+    let key = STANDARD.encode(fastrand::u128(..).to_be_bytes());
+    let req = Request::get(config.path.as_str())
+        .header(header::HOST, host)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_VERSION, "13")
+        .header(header::SEC_WEBSOCKET_KEY, &key)
+        .header(header::AUTHORIZATION, format!("Bearer {}", config.token))
+        .body(Empty::<Bytes>::new())?;
+    let mut response = sender.send_request(req).await?;
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(WsTunnelError::Rejected(response.status()));
+    }
+    let accept = response.headers().get(header::SEC_WEBSOCKET_ACCEPT);
+    if accept.is_none_or(|accept| accept.as_bytes() != websocket_accept_key(&key).as_bytes()) {
+        return Err(WsTunnelError::BadAccept);
+    }
+    let upgraded = hyper::upgrade::on(&mut response).await?;
+    Ok((
+        websocket_byte_stream(TokioIo::new(upgraded), WsRole::Client),
+        socket,
+    ))
+}
+
+type Tunnel = (OnUpgrade, Stream);
+
+// Serves HTTP/1.1 on the client connection until a tunnel handshake succeeds, then returns the
+// tunnel as a byte stream and the connection to `target_addr` made for it. Failed handshakes get
+// an error response; `None` means the client left without a tunnel.
+pub async fn ws_tunnel_accept<S>(
+    client: S,
+    config: &WsTunnelConfig,
+    target_addr: &str,
+    resolver: &Arc<Resolver>,
+    client_addr: SocketAddr,
+) -> Result<Option<(DuplexStream, Stream)>, hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (tunnel_tx, mut tunnel_rx) = mpsc::channel::<Tunnel>(1);
+    let config = Arc::new(config.clone());
+    let target_addr: Arc<str> = Arc::from(target_addr);
+    let resolver = Arc::clone(resolver);
+    let service = service_fn(move |req| {
+        let config = Arc::clone(&config);
+        let target_addr = Arc::clone(&target_addr);
+        let resolver = Arc::clone(&resolver);
+        let tunnel_tx = tunnel_tx.clone();
+        async move {
+            Ok::<_, Infallible>(
+                accept(
+                    req,
+                    &config,
+                    &target_addr,
+                    &resolver,
+                    client_addr,
+                    &tunnel_tx,
+                )
+                .await,
+            )
+        }
+    });
+
+    // Returns once the client is gone or, after a 101, once hyper has handed over the socket.
+    http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(HANDSHAKE_TIMEOUT)
+        .serve_connection(TokioIo::new(client), service)
+        .with_upgrades()
+        .await?;
+
+    let Ok((on_upgrade, stream)) = tunnel_rx.try_recv() else {
+        return Ok(None);
+    };
+    let upgraded = on_upgrade.await?;
+    Ok(Some((
+        websocket_byte_stream(TokioIo::new(upgraded), WsRole::Server),
+        stream,
+    )))
+}
+
+fn websocket_key(headers: &HeaderMap) -> Option<&str> {
+    let has_token = |name: header::HeaderName, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    let version_13 = headers
+        .get(header::SEC_WEBSOCKET_VERSION)
+        .is_some_and(|version| version == "13");
+    if !has_token(header::CONNECTION, "upgrade")
+        || !has_token(header::UPGRADE, "websocket")
+        || !version_13
+    {
+        return None;
+    }
+    headers
+        .get(header::SEC_WEBSOCKET_KEY)
+        .and_then(|key| key.to_str().ok())
+}
+
+async fn accept(
+    req: Request<Incoming>,
+    config: &WsTunnelConfig,
+    target_addr: &str,
+    resolver: &Resolver,
+    client_addr: SocketAddr,
+    tunnel_tx: &mpsc::Sender<Tunnel>,
+) -> Response<Full<Bytes>> {
+    if req.uri().path() != config.path {
+        return error(StatusCode::NOT_FOUND, "Not found");
+    }
+    let Some(key) = websocket_key(req.headers()).map(str::to_string) else {
+        return response(StatusCode::UPGRADE_REQUIRED)
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .body(Full::new(Bytes::from("WebSocket handshake required\n")))
+            .expect("valid response");
+    };
+    if req.method() != Method::GET {
+        return error(StatusCode::METHOD_NOT_ALLOWED, "Only GET is supported");
+    }
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Compared in constant time, so response times don't give the token away byte by byte.
+    let valid =
+        token.is_some_and(|token| bool::from(token.as_bytes().ct_eq(config.token.as_bytes())));
+    if !valid {
+        println!("[WS] {} presented a wrong token", client_addr);
+        return response(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .body(Full::new(Bytes::new()))
+            .expect("valid response");
+    }
+
+    match Stream::connect(target_addr, resolver).await {
+        Ok(stream) => {
+            if tunnel_tx
+                .try_send((hyper::upgrade::on(req), stream))
+                .is_err()
+            {
+                return error(StatusCode::BAD_REQUEST, "A tunnel is already open");
+            }
+            response(StatusCode::SWITCHING_PROTOCOLS)
+                .header(header::CONNECTION, "upgrade")
+                .header(header::UPGRADE, "websocket")
+                .header(header::SEC_WEBSOCKET_ACCEPT, websocket_accept_key(&key))
+                .body(Full::new(Bytes::new()))
+                .expect("valid response")
+        }
+        Err(e) => {
+            println!(
+                "[WS] {} failed to connect to {}: {}",
+                client_addr, target_addr, e
+            );
+            error(StatusCode::BAD_GATEWAY, "Target unreachable")
+        }
+    }
+}
+
+fn response(status: StatusCode) -> hyper::http::response::Builder {
+    Response::builder().status(status)
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    response(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(Full::new(Bytes::from(format!("{}\n", message))))
+        .expect("valid response")
+}
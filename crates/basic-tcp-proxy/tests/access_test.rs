@@ -0,0 +1,115 @@
+use std::{net::IpAddr, path::Path, time::Duration};
+
+use basic_tcp_proxy::{AccessConfig, AccessPolicy, Cidr, Config, Proxy, parse_access_list};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::timeout,
+};
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn cidr(s: &str) -> Cidr {
+    s.parse().unwrap()
+}
+
+async fn is_relayed(addr: std::net::SocketAddr) -> bool {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    if stream.write_all(b"ping").await.is_err() {
+        return false;
+    }
+    let mut buf = [0u8; 4];
+    matches!(
+        timeout(Duration::from_secs(2), stream.read_exact(&mut buf)).await,
+        Ok(Ok(_))
+    )
+}
+
+fn write_list(path: &Path, content: &str) {
+    std::fs::write(path, content).unwrap();
+}
+
+#[test]
+fn test_rule_matching_ipv4_and_ipv6() {
+    let access = AccessConfig {
+        default: AccessPolicy::Deny,
+        allow: vec![cidr("10.0.0.0/8"), cidr("2001:db8::/32")],
+        deny: vec![cidr("10.1.0.0/16")],
+        list_rules: parse_access_list("# office\nallow 10.1.2.3\ndeny 2001:db8:bad::/48\n")
+            .unwrap(),
+        ..AccessConfig::default()
+    };
+
+    assert_eq!(access.check(ip("10.2.0.1")), Ok(()));
+    assert_eq!(access.check(ip("10.1.0.1")), Err(Some(cidr("10.1.0.0/16"))));
+    assert_eq!(access.check(ip("10.1.2.3")), Ok(()));
+    assert_eq!(access.check(ip("::ffff:10.1.2.3")), Ok(()));
+    assert_eq!(access.check(ip("2001:db8:1::1")), Ok(()));
+    assert_eq!(
+        access.check(ip("2001:db8:bad::1")),
+        Err(Some(cidr("2001:db8:bad::/48")))
+    );
+    assert_eq!(access.check(ip("192.168.1.1")), Err(None));
+    assert_eq!(access.check(ip("fe80::1")), Err(None));
+
+    assert!(parse_access_list("permit 10.0.0.0/8").is_err());
+    assert!(parse_access_list("allow 10.0.0.0/33").is_err());
+}
+
+#[tokio::test]
+async fn test_deny_by_default_with_reloadable_list_file() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-access-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("proxy.toml");
+    let list_path = dir.join("access.list");
+    std::fs::write(
+        &config_path,
+        format!(
+            "target_addr = \"{}\"\nwatch_config = true\nwatch_interval_secs = 1\n\n[access]\ndefault = \"deny\"\nallow = [\"10.0.0.0/8\"]\nlist_file = \"access.list\"\n",
+            echo_addr
+        ),
+    )
+    .unwrap();
+    write_list(&list_path, "# nothing yet\n");
+
+    let config = Config::from_file(&config_path).unwrap();
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    assert!(!is_relayed(proxy_addr).await);
+
+    write_list(&list_path, "allow 127.0.0.0/8\n");
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(metrics_rx.borrow().config_reloads, 1);
+    assert!(is_relayed(proxy_addr).await);
+
+    write_list(&list_path, "allow 127.0.0.0/8\ndeny 127.0.0.1/32\n");
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(metrics_rx.borrow().config_reloads, 2);
+    assert!(!is_relayed(proxy_addr).await);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let snapshot = metrics_rx.borrow().clone();
+    assert_eq!(snapshot.denied_connections.get("default"), Some(&1));
+    assert_eq!(snapshot.denied_connections.get("127.0.0.1/32"), Some(&1));
+    assert!(
+        snapshot
+            .to_plain_text()
+            .contains("connections_denied{rule=\"127.0.0.1/32\"} 1")
+    );
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,168 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use basic_tcp_proxy::{BandwidthConfig, Config, Proxy, RateLimit, RouteConfig};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+const RATE: u64 = 100_000;
+
+async fn spawn_echo() -> (SocketAddr, JoinHandle<()>) {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    (echo_addr, echo_handle)
+}
+
+async fn start_proxy(
+    echo_addr: SocketAddr,
+    bandwidth: BandwidthConfig,
+    routes: Vec<RouteConfig>,
+) -> (Proxy, SocketAddr) {
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        metrics_addr: "127.0.0.1:0".to_string(),
+        bandwidth,
+        routes,
+        ..Config::default()
+    };
+    Proxy::new(config).await.unwrap()
+}
+
+// Pushes `len` bytes through the echo target and returns how long the round trip took.
+async fn timed_transfer(addr: SocketAddr, len: usize) -> Duration {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+    let start = Instant::now();
+
+    let writer_handle = tokio::spawn(async move {
+        writer.write_all(&vec![0x5a; len]).await.unwrap();
+        writer
+    });
+    let mut received = vec![0u8; len];
+    reader.read_exact(&mut received).await.unwrap();
+    let elapsed = start.elapsed();
+
+    drop(writer_handle.await.unwrap());
+    assert!(received.iter().all(|&b| b == 0x5a));
+    elapsed
+}
+
+#[tokio::test]
+async fn test_per_connection_upstream_limit() {
+    let bandwidth = BandwidthConfig {
+        per_connection: RateLimit {
+            upstream: Some(RATE),
+            downstream: None,
+        },
+        ..BandwidthConfig::default()
+    };
+    let (echo_addr, echo_handle) = spawn_echo().await;
+    let (mut proxy, proxy_addr) = start_proxy(echo_addr, bandwidth, Vec::new()).await;
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    // 200 KB at 100 KB/s, minus the 10 KB initial burst.
+    let elapsed = timed_transfer(proxy_addr, 200_000).await;
+    println!("per-connection transfer took {:?}", elapsed);
+    assert!(
+        elapsed >= Duration::from_millis(1700),
+        "too fast: {:?}",
+        elapsed
+    );
+    assert!(elapsed < Duration::from_secs(4), "too slow: {:?}", elapsed);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
+
+#[tokio::test]
+async fn test_per_client_limit_is_shared_across_connections() {
+    let bandwidth = BandwidthConfig {
+        per_client: RateLimit {
+            upstream: None,
+            downstream: Some(RATE),
+        },
+        ..BandwidthConfig::default()
+    };
+    let (echo_addr, echo_handle) = spawn_echo().await;
+    let (mut proxy, proxy_addr) = start_proxy(echo_addr, bandwidth, Vec::new()).await;
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let start = Instant::now();
+    let (first, second) = tokio::join!(
+        timed_transfer(proxy_addr, 100_000),
+        timed_transfer(proxy_addr, 100_000)
+    );
+    let elapsed = start.elapsed();
+    println!(
+        "per-client transfers took {:?} and {:?}, {:?} in total",
+        first, second, elapsed
+    );
+    assert!(
+        elapsed >= Duration::from_millis(1700),
+        "too fast: {:?}",
+        elapsed
+    );
+    assert!(elapsed < Duration::from_secs(4), "too slow: {:?}", elapsed);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
+
+#[tokio::test]
+async fn test_route_aggregate_limit_only_affects_its_route() {
+    let (echo_addr, echo_handle) = spawn_echo().await;
+    let limited = RouteConfig {
+        name: "limited".to_string(),
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        bandwidth: Some(RateLimit {
+            upstream: Some(RATE),
+            downstream: None,
+        }),
+        ..RouteConfig::default()
+    };
+    let (mut proxy, proxy_addr) =
+        start_proxy(echo_addr, BandwidthConfig::default(), vec![limited]).await;
+    let limited_addr = proxy.route_addr("limited").unwrap();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let unlimited = timed_transfer(proxy_addr, 200_000).await;
+    println!("unlimited route transfer took {:?}", unlimited);
+    assert!(
+        unlimited < Duration::from_secs(1),
+        "too slow: {:?}",
+        unlimited
+    );
+
+    let start = Instant::now();
+    tokio::join!(
+        timed_transfer(limited_addr, 100_000),
+        timed_transfer(limited_addr, 100_000)
+    );
+    let elapsed = start.elapsed();
+    println!("limited route transfers took {:?}", elapsed);
+    assert!(
+        elapsed >= Duration::from_millis(1700),
+        "too fast: {:?}",
+        elapsed
+    );
+    assert!(elapsed < Duration::from_secs(4), "too slow: {:?}", elapsed);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
@@ -0,0 +1,163 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use basic_tcp_proxy::{CaptureConfig, Config, Proxy};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+struct Packet {
+    src_port: u16,
+    flags: u8,
+    payload: Vec<u8>,
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+// Reads the IPv4/TCP packets of every Enhanced Packet Block in the capture files, oldest first.
+fn read_captures(dir: &Path) -> (Vec<PathBuf>, Vec<Packet>) {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| entries.map(|entry| entry.unwrap().path()).collect())
+        .unwrap_or_default();
+    files.sort();
+
+    let mut packets = Vec::new();
+    for file in &files {
+        let data = std::fs::read(file).unwrap();
+        assert_eq!(u32_at(&data, 0), 0x0A0D_0D0A, "{}", file.display());
+        let mut offset = 0;
+        while offset < data.len() {
+            let block_type = u32_at(&data, offset);
+            let len = u32_at(&data, offset + 4) as usize;
+            assert_eq!(u32_at(&data, offset + len - 4) as usize, len);
+            if block_type == 6 {
+                let captured = u32_at(&data, offset + 20) as usize;
+                let ip = &data[offset + 28..offset + 28 + captured];
+                assert_eq!(ip[0], 0x45);
+                let tcp = &ip[20..];
+                packets.push(Packet {
+                    src_port: u16::from_be_bytes([tcp[0], tcp[1]]),
+                    flags: tcp[13],
+                    payload: tcp[20..].to_vec(),
+                });
+            }
+            offset += len;
+        }
+    }
+    (files, packets)
+}
+
+async fn wait_for_packets(dir: &Path, count: usize) -> (Vec<PathBuf>, Vec<Packet>) {
+    for _ in 0..40 {
+        let (files, packets) = read_captures(dir);
+        if packets.len() >= count {
+            return (files, packets);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    read_captures(dir)
+}
+
+async fn echo_session(addr: SocketAddr, messages: &[&[u8]]) -> SocketAddr {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    for msg in messages {
+        stream.write_all(msg).await.unwrap();
+        let mut buf = vec![0u8; msg.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, msg);
+    }
+    stream.local_addr().unwrap()
+}
+
+async fn http_post(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_capture_rotation_and_runtime_toggles() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-capture-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        capture: CaptureConfig {
+            dir: dir.clone(),
+            max_file_size: 4096,
+            routes: vec!["default".to_string()],
+            clients: Vec::new(),
+        },
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let metrics_addr = proxy.metrics_addr();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let chunk = [b'x'; 1000];
+    let messages: Vec<&[u8]> = vec![b"hello", &chunk, &chunk, &chunk, &chunk, b"bye"];
+    let client = echo_session(proxy_addr, &messages).await;
+
+    // Handshake, 6 segments each way and the closing FIN/FIN/ACK.
+    let (files, packets) = wait_for_packets(&dir, 3 + 12 + 3).await;
+    assert!(files.len() >= 2, "no rotation: {:?}", files);
+    assert_eq!(packets.len(), 18);
+    assert_eq!(packets[0].flags, 0x02);
+    assert_eq!(packets[1].flags, 0x12);
+    assert_eq!(packets.last().unwrap().flags, 0x10);
+
+    let sent: Vec<u8> = messages.concat();
+    let from_client: Vec<u8> = packets
+        .iter()
+        .filter(|p| p.src_port == client.port())
+        .flat_map(|p| p.payload.clone())
+        .collect();
+    let to_client: Vec<u8> = packets
+        .iter()
+        .filter(|p| p.src_port == proxy_addr.port())
+        .flat_map(|p| p.payload.clone())
+        .collect();
+    assert_eq!(from_client, sent);
+    assert_eq!(to_client, sent);
+
+    let response = http_post(metrics_addr, "/capture/routes/default/disable").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    echo_session(proxy_addr, &[b"not captured"]).await;
+
+    let response = http_post(metrics_addr, "/capture/clients/127.0.0.0/8/enable").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("127.0.0.0/8"), "{}", response);
+    echo_session(proxy_addr, &[b"captured"]).await;
+
+    let (_, packets) = wait_for_packets(&dir, 18 + 8).await;
+    assert_eq!(packets.len(), 18 + 8);
+    assert!(packets.iter().all(|p| p.payload != b"not captured"));
+    assert!(packets.iter().any(|p| p.payload == b"captured"));
+
+    let response = http_post(metrics_addr, "/capture/clients/not-a-cidr/enable").await;
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,116 @@
+use std::{net::SocketAddr, time::Duration};
+
+use basic_tcp_proxy::{Config, HttpConnectConfig, Proxy, ProxyUser, RouteConfig};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+// alice:s3cret
+const ALICE: &str = "Basic YWxpY2U6czNjcmV0";
+
+// Sends one request and returns the stream with the response head.
+async fn request(proxy_addr: SocketAddr, head: &str) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).await.unwrap();
+        response.push(byte[0]);
+    }
+    (stream, String::from_utf8(response).unwrap())
+}
+
+async fn connect(proxy_addr: SocketAddr, target: &str, auth: Option<&str>) -> (TcpStream, String) {
+    let auth = auth
+        .map(|auth| format!("Proxy-Authorization: {}\r\n", auth))
+        .unwrap_or_default();
+    request(
+        proxy_addr,
+        &format!(
+            "CONNECT {} HTTP/1.1\r\nHost: {}\r\n{}\r\n",
+            target, target, auth
+        ),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_http_connect_tunnels() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        routes: vec![RouteConfig {
+            name: "connect".to_string(),
+            http_connect: Some(HttpConnectConfig {
+                users: vec![ProxyUser {
+                    username: "alice".to_string(),
+                    password: "s3cret".to_string(),
+                }],
+                allow: vec!["127.0.0.0/8".parse().unwrap()],
+            }),
+            ..RouteConfig::default()
+        }],
+        ..Config::default()
+    };
+    let (mut proxy, _) = Proxy::new(config).await.unwrap();
+    let connect_addr = proxy.route_addr("connect").unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let target = echo_addr.to_string();
+    let (_, head) = connect(connect_addr, &target, None).await;
+    assert!(head.starts_with("HTTP/1.1 407"), "{}", head);
+    assert!(head.contains("proxy-authenticate: Basic"), "{}", head);
+
+    let (_, head) = connect(connect_addr, "example.com:443", Some(ALICE)).await;
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_addr = closed.local_addr().unwrap().to_string();
+    drop(closed);
+    let (_, head) = connect(connect_addr, &closed_addr, Some(ALICE)).await;
+    assert!(head.starts_with("HTTP/1.1 502"), "{}", head);
+
+    let (_, head) = request(connect_addr, "GET / HTTP/1.1\r\nHost: x\r\n\r\n").await;
+    assert!(head.starts_with("HTTP/1.1 405"), "{}", head);
+
+    // Bytes sent right behind the request still reach the target.
+    let (mut tunnel, head) = request(
+        connect_addr,
+        &format!(
+            "CONNECT {} HTTP/1.1\r\nHost: {}\r\nProxy-Authorization: {}\r\n\r\nearly",
+            target, target, ALICE
+        ),
+    )
+    .await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    tunnel.write_all(b" and late").await.unwrap();
+    let mut buf = [0u8; 14];
+    tunnel.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"early and late");
+    drop(tunnel);
+
+    let snapshot = tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.total_connections == 1 && s.active_connections == 0),
+    )
+    .await
+    .expect("tunnel never showed up in the metrics")
+    .unwrap()
+    .clone();
+    assert_eq!(snapshot.bytes_upstream, 14);
+    assert_eq!(snapshot.bytes_downstream, 14);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
@@ -0,0 +1,317 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use basic_tcp_proxy::{Config, HttpProxyConfig, HttpRule, Proxy};
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::{
+    Request, Response,
+    body::{Bytes, Incoming},
+    client::conn::http1::{SendRequest, handshake},
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+// Answers every request with its name, the path and the forwarding headers it received, and
+// counts the connections it accepted.
+async fn backend(name: &'static str) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = Arc::clone(&connections);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let service = service_fn(move |req: Request<Incoming>| async move {
+                let header = |name: &str| {
+                    req.headers()
+                        .get(name)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let body = format!(
+                    "{} {} xff=[{}] fwd=[{}]",
+                    name,
+                    req.uri(),
+                    header("x-forwarded-for"),
+                    header("forwarded")
+                );
+                Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body))))
+            });
+            tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+        }
+    });
+    (addr, connections)
+}
+
+async fn get(sender: &mut SendRequest<Empty<Bytes>>, host: &str, path: &str) -> (u16, String) {
+    let req = Request::get(path)
+        .header("host", host)
+        .header("x-forwarded-for", "10.0.0.1")
+        .body(Empty::new())
+        .unwrap();
+    let response = sender.send_request(req).await.unwrap();
+    let status = response.status().as_u16();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&body).into_owned())
+}
+
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+// Reads one short (< 126 bytes) frame and returns its opcode and unmasked payload.
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.unwrap();
+    let mut mask = [0u8; 4];
+    if header[1] & 0x80 != 0 {
+        stream.read_exact(&mut mask).await.unwrap();
+    }
+    let mut payload = vec![0u8; usize::from(header[1] & 0x7f)];
+    stream.read_exact(&mut payload).await.unwrap();
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    (header[0] & 0x0f, payload)
+}
+
+// Builds a final text frame; clients must mask what they send, servers must not.
+fn frame(payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let len = u8::try_from(payload.len()).unwrap();
+    let mut frame = vec![0x81, len];
+    if let Some(mask) = mask {
+        frame[1] |= 0x80;
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    frame
+}
+
+// A minimal WebSocket echo server: accepts the handshake, greets, then echoes text frames until
+// the client sends a close frame.
+async fn websocket_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let head = read_head(&mut stream).await.to_ascii_lowercase();
+        assert!(head.contains("upgrade: websocket"), "{}", head);
+        assert!(head.contains("connection: upgrade"), "{}", head);
+        assert!(
+            head.contains("sec-websocket-key: dghlihnhbxbszsbub25jzq=="),
+            "{}",
+            head
+        );
+        // The greeting shares a packet with the handshake response.
+        let mut response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n".to_vec();
+        response.extend(frame(b"hi", None));
+        stream.write_all(&response).await.unwrap();
+        loop {
+            let (opcode, payload) = read_frame(&mut stream).await;
+            if opcode == 0x8 {
+                break;
+            }
+            stream.write_all(&frame(&payload, None)).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_websocket_upgrade_is_relayed() {
+    let ws_addr = websocket_echo().await;
+    let (default_addr, _) = backend("default").await;
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: default_addr.to_string(),
+        http: Some(HttpProxyConfig {
+            rules: vec![HttpRule {
+                name: "ws".to_string(),
+                host: None,
+                path_prefix: Some("/chat".to_string()),
+                target_addr: ws_addr.to_string(),
+            }],
+            ..HttpProxyConfig::default()
+        }),
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /chat HTTP/1.1\r\nHost: ws.test\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let head = read_head(&mut stream).await;
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    let lowercase = head.to_ascii_lowercase();
+    assert!(lowercase.contains("upgrade: websocket"), "{}", head);
+    assert!(lowercase.contains("connection: upgrade"), "{}", head);
+    assert!(
+        head.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+        "{}",
+        head
+    );
+
+    let exchange = async {
+        assert_eq!(read_frame(&mut stream).await, (0x1, b"hi".to_vec()));
+        stream
+            .write_all(&frame(b"hello", Some([1, 2, 3, 4])))
+            .await
+            .unwrap();
+        assert_eq!(read_frame(&mut stream).await, (0x1, b"hello".to_vec()));
+        // A close frame with no payload.
+        stream.write_all(&[0x88, 0x80, 0, 0, 0, 0]).await.unwrap();
+    };
+    tokio::time::timeout(Duration::from_secs(5), exchange)
+        .await
+        .expect("no echo through the proxy");
+    drop(stream);
+
+    let snapshot = tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.total_connections == 1 && s.active_connections == 0),
+    )
+    .await
+    .expect("connection never closed in the metrics")
+    .unwrap()
+    .clone();
+    // Frames after the handshake are counted as relayed bytes: 11 + 6 up, 4 + 7 down.
+    assert_eq!(snapshot.bytes_upstream, 17);
+    assert_eq!(snapshot.bytes_downstream, 11);
+    assert_eq!(snapshot.http_requests["default/ws"].statuses[&101], 1);
+
+    proxy_handle.abort();
+}
+
+#[tokio::test]
+async fn test_http_routing_forwarding_and_pooling() {
+    let (api_addr, api_connections) = backend("api").await;
+    let (static_addr, _) = backend("static").await;
+    let (default_addr, _) = backend("default").await;
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_addr = closed.local_addr().unwrap();
+    drop(closed);
+
+    let rule =
+        |name: &str, host: Option<&str>, path_prefix: Option<&str>, target: SocketAddr| HttpRule {
+            name: name.to_string(),
+            host: host.map(str::to_string),
+            path_prefix: path_prefix.map(str::to_string),
+            target_addr: target.to_string(),
+        };
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: default_addr.to_string(),
+        http: Some(HttpProxyConfig {
+            rules: vec![
+                rule("api", Some("api.test"), Some("/v1"), api_addr),
+                rule("static", None, Some("/static"), static_addr),
+                rule("down", None, Some("/down"), closed_addr),
+            ],
+            ..HttpProxyConfig::default()
+        }),
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    // Every request below shares one keep-alive client connection.
+    let stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let (mut sender, connection) = handshake(TokioIo::new(stream)).await.unwrap();
+    tokio::spawn(connection);
+
+    let (status, body) = get(&mut sender, "API.test:8080", "/v1/users?page=2").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        "api /v1/users?page=2 xff=[10.0.0.1, 127.0.0.1] fwd=[for=127.0.0.1;host=\"api.test:8080\";proto=http]"
+    );
+    assert!(
+        get(&mut sender, "api.test", "/v1")
+            .await
+            .1
+            .starts_with("api /v1 ")
+    );
+    assert!(
+        get(&mut sender, "api.test", "/v1")
+            .await
+            .1
+            .starts_with("api /v1 ")
+    );
+    // Path prefixes match whole segments, and host rules only their host.
+    assert!(
+        get(&mut sender, "api.test", "/v10")
+            .await
+            .1
+            .starts_with("default /v10 ")
+    );
+    assert!(
+        get(&mut sender, "www.test", "/v1")
+            .await
+            .1
+            .starts_with("default /v1 ")
+    );
+    assert!(
+        get(&mut sender, "www.test", "/static/app.js")
+            .await
+            .1
+            .starts_with("static /static/app.js ")
+    );
+    assert_eq!(get(&mut sender, "www.test", "/down").await.0, 502);
+
+    // Three requests to the api backend went over one pooled connection.
+    assert_eq!(api_connections.load(Ordering::SeqCst), 1);
+
+    let snapshot = tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.http_requests.values().map(|r| r.requests).sum::<u64>() == 7),
+    )
+    .await
+    .expect("requests never showed up in the metrics")
+    .unwrap()
+    .clone();
+    assert_eq!(snapshot.total_connections, 1);
+    let api = &snapshot.http_requests["default/api"];
+    assert_eq!((api.requests, api.statuses[&200]), (3, 3));
+    assert_eq!(snapshot.http_requests["default/default"].requests, 2);
+    assert_eq!(snapshot.http_requests["default/down"].statuses[&502], 1);
+    assert!(
+        snapshot
+            .to_plain_text()
+            .contains("http_responses{rule=\"default/down\",status=\"502\"} 1")
+    );
+
+    proxy_handle.abort();
+}
@@ -0,0 +1,153 @@
+use std::{net::SocketAddr, time::Duration};
+
+use basic_tcp_proxy::{Config, LimitAction, LimitsConfig, MetricsSnapshot, Proxy};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+    task::JoinHandle,
+    time::timeout,
+};
+
+async fn start_proxy(
+    limits: LimitsConfig,
+) -> (SocketAddr, watch::Receiver<MetricsSnapshot>, JoinHandle<()>) {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        metrics_addr: "127.0.0.1:0".to_string(),
+        limits,
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let metrics_rx = proxy.metrics();
+    let handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+    (proxy_addr, metrics_rx, handle)
+}
+
+async fn echo(stream: &mut TcpStream, msg: &[u8]) -> std::io::Result<Vec<u8>> {
+    stream.write_all(msg).await?;
+    let mut buf = vec![0u8; msg.len()];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+fn rejected(metrics_rx: &watch::Receiver<MetricsSnapshot>, reason: &str) -> u64 {
+    metrics_rx
+        .borrow()
+        .rejected_connections
+        .get(reason)
+        .copied()
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn test_per_ip_limit_closes_excess_connections() {
+    let (proxy_addr, metrics_rx, handle) = start_proxy(LimitsConfig {
+        max_connections_per_ip: Some(1),
+        ..LimitsConfig::default()
+    })
+    .await;
+
+    let mut first = TcpStream::connect(proxy_addr).await.unwrap();
+    assert_eq!(echo(&mut first, b"one").await.unwrap(), b"one");
+
+    let mut second = TcpStream::connect(proxy_addr).await.unwrap();
+    let mut buf = [0u8; 1];
+    let n = timeout(Duration::from_secs(2), second.read(&mut buf))
+        .await
+        .expect("rejected connection was not closed")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(rejected(&metrics_rx, "per_ip"), 1);
+    assert!(
+        metrics_rx
+            .borrow()
+            .to_plain_text()
+            .contains("connections_rejected{reason=\"per_ip\"} 1")
+    );
+
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let mut third = TcpStream::connect(proxy_addr).await.unwrap();
+    assert_eq!(echo(&mut third, b"three").await.unwrap(), b"three");
+
+    handle.abort();
+}
+
+#[tokio::test]
+async fn test_queue_mode_admits_after_release() {
+    let (proxy_addr, metrics_rx, handle) = start_proxy(LimitsConfig {
+        max_connections: Some(1),
+        on_limit: LimitAction::Queue,
+        queue_size: 1,
+        queue_timeout_secs: 5,
+        ..LimitsConfig::default()
+    })
+    .await;
+
+    let mut first = TcpStream::connect(proxy_addr).await.unwrap();
+    assert_eq!(echo(&mut first, b"one").await.unwrap(), b"one");
+
+    let mut queued = TcpStream::connect(proxy_addr).await.unwrap();
+    queued.write_all(b"two").await.unwrap();
+
+    let mut overflow = TcpStream::connect(proxy_addr).await.unwrap();
+    let mut buf = [0u8; 3];
+    let n = timeout(Duration::from_secs(2), overflow.read(&mut buf))
+        .await
+        .expect("overflow connection was not closed")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+
+    assert!(
+        timeout(Duration::from_millis(300), queued.read_exact(&mut buf))
+            .await
+            .is_err(),
+        "queued connection was relayed before a slot was free"
+    );
+
+    drop(first);
+    timeout(Duration::from_secs(2), queued.read_exact(&mut buf))
+        .await
+        .expect("queued connection was never admitted")
+        .unwrap();
+    assert_eq!(&buf, b"two");
+    assert_eq!(rejected(&metrics_rx, "queue_full"), 1);
+
+    handle.abort();
+}
+
+#[tokio::test]
+async fn test_connection_rate_limit_per_ip() {
+    let (proxy_addr, metrics_rx, handle) = start_proxy(LimitsConfig {
+        connections_per_sec_per_ip: Some(1.0),
+        connection_burst_per_ip: Some(2),
+        ..LimitsConfig::default()
+    })
+    .await;
+
+    let mut accepted = 0;
+    for _ in 0..4 {
+        let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+        if echo(&mut stream, b"x").await.is_ok() {
+            accepted += 1;
+        }
+    }
+    assert_eq!(accepted, 2);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(rejected(&metrics_rx, "rate_limit"), 2);
+
+    handle.abort();
+}
@@ -0,0 +1,187 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use basic_tcp_proxy::{Config, MetricsSnapshot, MirrorConfig, Proxy};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UnixListener},
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+
+async fn start_proxy(
+    mirror: MirrorConfig,
+) -> (
+    SocketAddr,
+    watch::Receiver<MetricsSnapshot>,
+    JoinHandle<()>,
+    JoinHandle<()>,
+) {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        mirror: Some(mirror),
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+    (proxy_addr, metrics_rx, proxy_handle, echo_handle)
+}
+
+async fn echo_through(addr: SocketAddr, payload: &[u8]) {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut read, mut write) = stream.into_split();
+    let expected = payload.len();
+    let reader = tokio::spawn(async move {
+        let mut received = vec![0u8; expected];
+        read.read_exact(&mut received).await.unwrap();
+        received
+    });
+    write.write_all(payload).await.unwrap();
+    assert_eq!(reader.await.unwrap(), payload);
+}
+
+async fn wait_for(
+    metrics_rx: &mut watch::Receiver<MetricsSnapshot>,
+    done: impl Fn(&MetricsSnapshot) -> bool,
+) -> MetricsSnapshot {
+    tokio::time::timeout(Duration::from_secs(5), metrics_rx.wait_for(done))
+        .await
+        .expect("metrics never settled")
+        .unwrap()
+        .clone()
+}
+
+#[tokio::test]
+async fn test_shadow_receives_client_bytes() {
+    let shadow = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let shadow_addr = shadow.local_addr().unwrap();
+    let (received_tx, received_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = shadow.accept().await.unwrap();
+        // Responses from the shadow must never reach the client.
+        stream.write_all(b"shadow reply").await.unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        let _ = received_tx.send(received);
+    });
+
+    let (proxy_addr, mut metrics_rx, proxy_handle, echo_handle) = start_proxy(MirrorConfig {
+        target_addr: shadow_addr.to_string(),
+        buffer_size: 1024 * 1024,
+    })
+    .await;
+
+    let payload: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    echo_through(proxy_addr, &payload).await;
+
+    let received = tokio::time::timeout(Duration::from_secs(5), received_rx)
+        .await
+        .expect("shadow connection never finished")
+        .unwrap();
+    assert_eq!(received, payload);
+
+    let snapshot = wait_for(&mut metrics_rx, |s| s.bytes_mirrored == 100_000).await;
+    assert_eq!(snapshot.bytes_mirror_dropped, 0);
+    assert!(snapshot.to_plain_text().contains("bytes_mirrored 100000"));
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
+
+#[tokio::test]
+async fn test_stalled_or_missing_shadow_drops_instead_of_blocking() {
+    // Accepts the shadow connection but never reads from it.
+    let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stalled_addr = stalled.local_addr().unwrap();
+    let stalled_handle = tokio::spawn(async move {
+        let (_stream, _) = stalled.accept().await.unwrap();
+        std::future::pending::<()>().await;
+    });
+
+    let (proxy_addr, mut metrics_rx, proxy_handle, echo_handle) = start_proxy(MirrorConfig {
+        target_addr: stalled_addr.to_string(),
+        buffer_size: 16 * 1024,
+    })
+    .await;
+
+    // Far more than the socket buffers and the mirror buffer can hold.
+    let payload = vec![7u8; 16 * 1024 * 1024];
+    let start = Instant::now();
+    echo_through(proxy_addr, &payload).await;
+    assert!(
+        start.elapsed() < Duration::from_secs(5),
+        "{:?}",
+        start.elapsed()
+    );
+
+    let snapshot = wait_for(&mut metrics_rx, |s| s.bytes_mirror_dropped > 0).await;
+    assert!(snapshot.bytes_mirrored < payload.len() as u64);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    stalled_handle.abort();
+
+    // Nothing listens on the shadow address, so every mirrored byte is dropped.
+    let unused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let unused_addr = unused.local_addr().unwrap();
+    drop(unused);
+
+    let (proxy_addr, mut metrics_rx, proxy_handle, echo_handle) = start_proxy(MirrorConfig {
+        target_addr: unused_addr.to_string(),
+        buffer_size: 1024 * 1024,
+    })
+    .await;
+    echo_through(proxy_addr, b"hello shadow").await;
+
+    let snapshot = wait_for(&mut metrics_rx, |s| s.bytes_mirror_dropped == 12).await;
+    assert_eq!(snapshot.bytes_mirrored, 0);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
+
+#[tokio::test]
+async fn test_shadow_on_unix_socket() {
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-mirror-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let shadow_path = dir.join("shadow.sock");
+    let shadow = UnixListener::bind(&shadow_path).unwrap();
+    let (received_tx, received_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let (mut stream, _) = shadow.accept().await.unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+        let _ = received_tx.send(received);
+    });
+
+    let (proxy_addr, mut metrics_rx, proxy_handle, echo_handle) = start_proxy(MirrorConfig {
+        target_addr: format!("unix:{}", shadow_path.display()),
+        buffer_size: 1024 * 1024,
+    })
+    .await;
+    echo_through(proxy_addr, b"hello shadow").await;
+
+    let received = tokio::time::timeout(Duration::from_secs(5), received_rx)
+        .await
+        .expect("shadow connection never finished")
+        .unwrap();
+    assert_eq!(received, b"hello shadow");
+    wait_for(&mut metrics_rx, |s| s.bytes_mirrored == 12).await;
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
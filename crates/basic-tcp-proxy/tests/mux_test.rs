@@ -0,0 +1,191 @@
+use std::{net::SocketAddr, time::Duration};
+
+use basic_tcp_proxy::{
+    Config, MUX_INITIAL_WINDOW, MetricsSnapshot, MuxConfig, MuxSession, Proxy, TunnelSide,
+};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+    task::JoinSet,
+};
+
+async fn start(config: Config) -> (SocketAddr, watch::Receiver<MetricsSnapshot>) {
+    let (mut proxy, addr) = Proxy::new(config).await.unwrap();
+    let metrics_rx = proxy.metrics();
+    tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+    (addr, metrics_rx)
+}
+
+fn mux(side: TunnelSide) -> MuxConfig {
+    MuxConfig {
+        side,
+        connections: 1,
+        window_size: MUX_INITIAL_WINDOW,
+    }
+}
+
+#[tokio::test]
+async fn test_mux_carries_streams_over_one_connection() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    let (inner_addr, mut inner_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        mux: Some(mux(TunnelSide::Server)),
+        ..Config::default()
+    })
+    .await;
+    let (edge_addr, mut edge_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: inner_addr.to_string(),
+        mux: Some(mux(TunnelSide::Client)),
+        ..Config::default()
+    })
+    .await;
+
+    // Four times the window each way, so the transfer only completes if window updates flow.
+    let size = 4 * MUX_INITIAL_WINDOW as usize;
+    let mut clients = JoinSet::new();
+    for i in 0..5u8 {
+        clients.spawn(async move {
+            let stream = TcpStream::connect(edge_addr).await.unwrap();
+            let (mut reader, mut writer) = stream.into_split();
+            let data: Vec<u8> = (0..size)
+                .map(|n| u8::try_from(n % 251).unwrap() ^ i)
+                .collect();
+            let expected = data.clone();
+            let write = tokio::spawn(async move {
+                writer.write_all(&data).await.unwrap();
+                writer
+            });
+            let mut echoed = vec![0u8; size];
+            reader.read_exact(&mut echoed).await.unwrap();
+            assert!(echoed == expected, "stream {} got someone else's bytes", i);
+            drop(write.await.unwrap());
+        });
+    }
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while let Some(result) = clients.join_next().await {
+            result.unwrap();
+        }
+    })
+    .await
+    .expect("streams never finished");
+
+    let wait = |metrics_rx: &mut watch::Receiver<MetricsSnapshot>| {
+        let mut metrics_rx = metrics_rx.clone();
+        async move {
+            tokio::time::timeout(
+                Duration::from_secs(5),
+                metrics_rx.wait_for(|s| s.mux_streams_total == 5 && s.mux_streams_active == 0),
+            )
+            .await
+            .expect("streams never closed in the metrics")
+            .unwrap()
+            .clone()
+        }
+    };
+    // The edge counts five clients; the inner proxy sees a single physical connection.
+    let edge = wait(&mut edge_metrics).await;
+    assert_eq!(edge.total_connections, 5);
+    assert_eq!((edge.mux_sessions_total, edge.mux_sessions_active), (1, 1));
+    assert_eq!(edge.bytes_upstream, 5 * size as u64);
+    let inner = wait(&mut inner_metrics).await;
+    assert_eq!((inner.total_connections, inner.active_connections), (1, 1));
+    assert_eq!(
+        (inner.mux_sessions_total, inner.mux_sessions_active),
+        (1, 1)
+    );
+    assert_eq!(inner.bytes_downstream, 5 * size as u64);
+    assert!(inner.to_plain_text().contains("mux_streams_total 5"));
+}
+
+#[tokio::test]
+async fn test_mux_close_flushes_queued_frames() {
+    let (client_io, server_io) = tokio::io::duplex(4 * 1024 * 1024);
+    let (metrics_tx, _metrics_rx) = tokio::sync::mpsc::channel(64);
+    let client = MuxSession::client(client_io, MUX_INITIAL_WINDOW, metrics_tx.clone());
+    let (_server, mut streams) = MuxSession::server(server_io, MUX_INITIAL_WINDOW, metrics_tx);
+
+    // Data and FIN are queued, then the session closes before the writer gets to them.
+    let payload = vec![7u8; 64 * 1024];
+    let mut stream = client.open().unwrap();
+    stream.write_all(&payload).await.unwrap();
+    drop(stream);
+    client.close();
+
+    let mut accepted = streams.recv().await.unwrap();
+    let mut received = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), accepted.read_to_end(&mut received))
+        .await
+        .expect("stream never ended")
+        .expect("stream was reset instead of finished");
+    assert_eq!(received, payload);
+}
+
+#[tokio::test]
+async fn test_mux_pool_separates_window_sizes() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    let (inner_addr, mut inner_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        mux: Some(mux(TunnelSide::Server)),
+        ..Config::default()
+    })
+    .await;
+    let reserved = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let small_addr = reserved.local_addr().unwrap();
+    drop(reserved);
+    // Two routes to the same server side, one with a larger window.
+    let config: Config = toml::from_str(&format!(
+        r#"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{inner_addr}"
+
+        [mux]
+        side = "client"
+        connections = 1
+
+        [[routes]]
+        name = "small"
+        listen_addr = "{small_addr}"
+        target_addr = "{inner_addr}"
+
+        [routes.mux]
+        side = "client"
+        connections = 1
+        window_size = 1048576
+        "#
+    ))
+    .unwrap();
+    let (edge_addr, _) = start(config).await;
+
+    for addr in [edge_addr, small_addr] {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut buf))
+            .await
+            .expect("no echo through the mux")
+            .unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+    let inner = tokio::time::timeout(
+        Duration::from_secs(5),
+        inner_metrics.wait_for(|s| s.mux_streams_total == 2),
+    )
+    .await
+    .expect("streams never reached the server side")
+    .unwrap()
+    .clone();
+    assert_eq!(inner.mux_sessions_total, 2);
+}
@@ -0,0 +1,85 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use basic_tcp_proxy::{Config, Proxy};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+async fn spawn_tagged_server(tag: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                loop {
+                    let n = stream.read(&mut buf).await.unwrap_or(0);
+                    if n == 0 {
+                        break;
+                    }
+                    let reply = [tag.as_bytes(), b":", &buf[..n]].concat();
+                    if stream.write_all(&reply).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    addr
+}
+
+async fn roundtrip(stream: &mut TcpStream, msg: &str) -> String {
+    stream.write_all(msg.as_bytes()).await.unwrap();
+    let mut buf = vec![0u8; msg.len() + 2];
+    stream.read_exact(&mut buf).await.unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+fn write_config(path: &PathBuf, target_addr: &str) {
+    let content = format!(
+        "listen_addr = \"127.0.0.1:0\"\ntarget_addr = \"{}\"\nwatch_config = true\nwatch_interval_secs = 1\n",
+        target_addr
+    );
+    std::fs::write(path, content).unwrap();
+}
+
+#[tokio::test]
+async fn test_reload_applies_new_target_and_rejects_invalid_config() {
+    let target_a = spawn_tagged_server("a").await;
+    let target_b = spawn_tagged_server("b").await;
+
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("proxy.toml");
+    write_config(&path, &target_a.to_string());
+
+    let config = Config::from_file(&path).unwrap();
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let mut old_client = TcpStream::connect(proxy_addr).await.unwrap();
+    assert_eq!(roundtrip(&mut old_client, "hi").await, "a:hi");
+
+    write_config(&path, &target_b.to_string());
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(metrics_rx.borrow().config_reloads, 1);
+
+    let mut new_client = TcpStream::connect(proxy_addr).await.unwrap();
+    assert_eq!(roundtrip(&mut new_client, "hi").await, "b:hi");
+    assert_eq!(roundtrip(&mut old_client, "still").await, "a:still");
+
+    write_config(&path, "not-an-address");
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(metrics_rx.borrow().config_reload_errors, 1);
+
+    let mut after_reject = TcpStream::connect(proxy_addr).await.unwrap();
+    assert_eq!(roundtrip(&mut after_reject, "ok").await, "b:ok");
+
+    proxy_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,112 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use basic_tcp_proxy::{Config, IpPreference, Proxy, ResolverConfig, RouteConfig};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+async fn exchange(addr: SocketAddr, msg: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(msg).await.unwrap();
+    let mut buf = vec![0u8; msg.len()];
+    match tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut buf)).await {
+        Ok(Ok(_)) => buf,
+        _ => Vec::new(),
+    }
+}
+
+fn write_hosts(path: &Path, content: &str) {
+    // Written next to the target and renamed, so the refresh task never reads half a file.
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).unwrap();
+    std::fs::rename(&tmp, path).unwrap();
+}
+
+#[tokio::test]
+async fn test_hostname_targets_follow_the_hosts_file() {
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-dns-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let hosts_path = dir.join("hosts");
+
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    // Same port on another loopback address, answering with its own bytes.
+    let moved = TcpListener::bind(("127.0.0.2", echo_addr.port()))
+        .await
+        .unwrap();
+    let moved_handle = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = moved.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 5];
+                if stream.read_exact(&mut buf).await.is_ok() {
+                    let _ = stream.write_all(b"moved").await;
+                }
+            });
+        }
+    });
+
+    write_hosts(
+        &hosts_path,
+        "# test hosts\n127.0.0.1 backend.test\n\n192.0.2.1 dual.test\n127.0.0.3 dual.test\n127.0.0.1 dual.test\n",
+    );
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: format!("Backend.test:{}", echo_addr.port()),
+        resolver: ResolverConfig {
+            hosts_file: Some(hosts_path.clone()),
+            refresh_interval_secs: 1,
+            ip_preference: IpPreference::Ipv4First,
+            attempt_delay_ms: 100,
+            ..ResolverConfig::default()
+        },
+        routes: vec![RouteConfig {
+            name: "dual".to_string(),
+            target_addr: format!("dual.test:{}", echo_addr.port()),
+            ..RouteConfig::default()
+        }],
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let dual_addr = proxy.route_addr("dual").unwrap();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    assert_eq!(exchange(proxy_addr, b"hello").await, b"hello");
+
+    // 192.0.2.1 never answers and 127.0.0.3 refuses; the third address still connects quickly.
+    let start = Instant::now();
+    assert_eq!(exchange(dual_addr, b"racing").await, b"racing");
+    assert!(
+        start.elapsed() < Duration::from_secs(2),
+        "{:?}",
+        start.elapsed()
+    );
+
+    write_hosts(&hosts_path, "127.0.0.2 backend.test\n");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let reply = exchange(proxy_addr, b"hello").await;
+        if reply == b"moved" {
+            break;
+        }
+        assert_eq!(reply, b"hello");
+        assert!(Instant::now() < deadline, "target was never re-resolved");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    moved_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
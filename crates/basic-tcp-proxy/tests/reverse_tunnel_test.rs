@@ -0,0 +1,202 @@
+use std::{net::SocketAddr, time::Duration};
+
+use basic_tcp_proxy::{
+    Config, LimitsConfig, MetricsSnapshot, Proxy, ReverseAgentConfig, ReverseTunnelConfig,
+};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+};
+
+const TOKEN: &str = "s3cret";
+
+async fn start(config: Config) -> (SocketAddr, watch::Receiver<MetricsSnapshot>) {
+    let (mut proxy, addr) = Proxy::new(config).await.unwrap();
+    let metrics_rx = proxy.metrics();
+    tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+    (addr, metrics_rx)
+}
+
+// Sends one line to the relay the way an agent registers, and returns the answer.
+async fn register(relay_addr: SocketAddr, line: &str) -> String {
+    let mut stream = TcpStream::connect(relay_addr).await.unwrap();
+    stream.write_all(line.as_bytes()).await.unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).await.unwrap();
+    answer
+}
+
+async fn echo(public_addr: SocketAddr, message: &[u8]) {
+    let mut stream = TcpStream::connect(public_addr).await.unwrap();
+    stream.write_all(message).await.unwrap();
+    let mut buf = vec![0u8; message.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, message);
+}
+
+#[tokio::test]
+async fn test_reverse_tunnel_exposes_agent_target() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    // The relay hands out ports from a range; reserve one that is free.
+    let reserved = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let public_addr = reserved.local_addr().unwrap();
+    drop(reserved);
+
+    let (relay_addr, mut relay_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        reverse_tunnel: Some(ReverseTunnelConfig {
+            token: TOKEN.to_string(),
+            public_host: "127.0.0.1".to_string(),
+            port_range: Some([public_addr.port(), public_addr.port()]),
+        }),
+        ..Config::default()
+    })
+    .await;
+    let (_, mut agent_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        reverse_agents: vec![ReverseAgentConfig {
+            name: "echo".to_string(),
+            relay_addr: relay_addr.to_string(),
+            target_addr: echo_addr.to_string(),
+            token: TOKEN.to_string(),
+            retry_backoff_ms: 50,
+        }],
+        ..Config::default()
+    })
+    .await;
+
+    // The port opens once the agent has registered.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while TcpStream::connect(public_addr).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("service was never registered");
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        tokio::join!(echo(public_addr, b"hello"), echo(public_addr, b"world!"))
+    })
+    .await
+    .expect("no echo through the reverse tunnel");
+
+    assert_eq!(
+        register(relay_addr, "REGISTER other wrong\n").await,
+        "ERR unauthorized\n"
+    );
+    assert_eq!(
+        register(relay_addr, &format!("REGISTER echo {}\n", TOKEN)).await,
+        "ERR name taken\n"
+    );
+
+    // The port probe above was a stream of its own, with nothing sent.
+    let streams_closed =
+        |s: &MetricsSnapshot| s.mux_streams_total == 3 && s.mux_streams_active == 0;
+    let relay = tokio::time::timeout(
+        Duration::from_secs(5),
+        relay_metrics.wait_for(streams_closed),
+    )
+    .await
+    .expect("streams never closed on the relay")
+    .unwrap()
+    .clone();
+    assert_eq!(relay.mux_sessions_active, 1);
+    assert_eq!(relay.bytes_upstream, 11);
+    assert_eq!(relay.bytes_downstream, 11);
+    let agent = tokio::time::timeout(
+        Duration::from_secs(5),
+        agent_metrics.wait_for(streams_closed),
+    )
+    .await
+    .expect("streams never closed on the agent")
+    .unwrap()
+    .clone();
+    assert_eq!(agent.mux_sessions_active, 1);
+    assert_eq!(agent.bytes_upstream, 11);
+    assert_eq!(agent.bytes_downstream, 11);
+}
+
+#[tokio::test]
+async fn test_reverse_tunnel_applies_connection_limits() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    let reserved = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let public_addr = reserved.local_addr().unwrap();
+    drop(reserved);
+
+    // The agent's own connection takes one of the two slots.
+    let (relay_addr, mut relay_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        reverse_tunnel: Some(ReverseTunnelConfig {
+            token: TOKEN.to_string(),
+            public_host: "127.0.0.1".to_string(),
+            port_range: Some([public_addr.port(), public_addr.port()]),
+        }),
+        limits: LimitsConfig {
+            max_connections_per_ip: Some(2),
+            ..LimitsConfig::default()
+        },
+        ..Config::default()
+    })
+    .await;
+    start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        reverse_agents: vec![ReverseAgentConfig {
+            name: "echo".to_string(),
+            relay_addr: relay_addr.to_string(),
+            target_addr: echo_addr.to_string(),
+            token: TOKEN.to_string(),
+            retry_backoff_ms: 50,
+        }],
+        ..Config::default()
+    })
+    .await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while TcpStream::connect(public_addr).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("service was never registered");
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        relay_metrics.wait_for(|s| s.mux_streams_total == 1 && s.mux_streams_active == 0),
+    )
+    .await
+    .expect("port probe never closed")
+    .unwrap();
+
+    let mut first = TcpStream::connect(public_addr).await.unwrap();
+    first.write_all(b"one").await.unwrap();
+    let mut buf = [0u8; 3];
+    first.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"one");
+
+    let mut second = TcpStream::connect(public_addr).await.unwrap();
+    let n = tokio::time::timeout(Duration::from_secs(2), second.read(&mut buf))
+        .await
+        .expect("rejected connection was not closed")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+    let relay = tokio::time::timeout(
+        Duration::from_secs(5),
+        relay_metrics.wait_for(|s| s.rejected_connections.get("per_ip") == Some(&1)),
+    )
+    .await
+    .expect("connection was never rejected")
+    .unwrap()
+    .clone();
+    assert_eq!(relay.mux_streams_total, 2);
+}
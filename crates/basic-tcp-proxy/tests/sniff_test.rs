@@ -0,0 +1,103 @@
+use std::{net::SocketAddr, time::Duration};
+
+use basic_tcp_proxy::{Config, Proxy, SniffConfig, SniffPattern, SniffProtocol, SniffRule};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+// Greets every connection with its name, then echoes.
+async fn named_backend(name: &'static str) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.into_split();
+                write.write_all(name.as_bytes()).await.unwrap();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+    addr
+}
+
+// Sends `first` and returns which backend answered; the backend must also echo `first` intact.
+async fn routed_to(proxy_addr: SocketAddr, first: &[u8], name_len: usize) -> String {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(first).await.unwrap();
+    let mut reply = vec![0u8; name_len + first.len()];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut reply))
+        .await
+        .expect("no reply through the proxy")
+        .unwrap();
+    assert_eq!(&reply[name_len..], first);
+    String::from_utf8_lossy(&reply[..name_len]).into_owned()
+}
+
+#[tokio::test]
+async fn test_sniffing_routes_by_first_bytes() {
+    let rule = |pattern, name| async move {
+        SniffRule {
+            target_addr: named_backend(name).await.to_string(),
+            pattern,
+        }
+    };
+    let rules = vec![
+        rule(SniffPattern::Protocol(SniffProtocol::Tls), "tls__").await,
+        rule(SniffPattern::Protocol(SniffProtocol::Http), "http_").await,
+        rule(SniffPattern::Protocol(SniffProtocol::Ssh), "ssh__").await,
+        rule(SniffPattern::Protocol(SniffProtocol::Proxy), "proxy").await,
+        rule(SniffPattern::Prefix("PING".to_string()), "ping_").await,
+        rule(SniffPattern::Regex(r"^\d+\|".parse().unwrap()), "regex").await,
+    ];
+    let default_addr = named_backend("dflt_").await;
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: default_addr.to_string(),
+        sniff: Some(SniffConfig {
+            peek_timeout_ms: 200,
+            max_peek_bytes: 64,
+            rules,
+        }),
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let client_hello = [
+        0x16, 0x03, 0x01, 0x00, 0x2a, 0x01, 0x00, 0x00, 0x26, 0x03, 0x03,
+    ];
+    assert_eq!(routed_to(proxy_addr, &client_hello, 5).await, "tls__");
+    assert_eq!(
+        routed_to(proxy_addr, b"POST /api HTTP/1.1\r\n", 5).await,
+        "http_"
+    );
+    assert_eq!(
+        routed_to(proxy_addr, b"SSH-2.0-OpenSSH_9.6\r\n", 5).await,
+        "ssh__"
+    );
+    assert_eq!(
+        routed_to(proxy_addr, b"PROXY TCP4 10.0.0.1 10.0.0.2 1234 80\r\n", 5).await,
+        "proxy"
+    );
+    assert_eq!(routed_to(proxy_addr, b"PING\n", 5).await, "ping_");
+    assert_eq!(routed_to(proxy_addr, b"42|payload", 5).await, "regex");
+    // Nothing matches, so the connection goes to `target_addr` once the peek timeout expires.
+    assert_eq!(routed_to(proxy_addr, b"hello", 5).await, "dflt_");
+
+    // Server-speaks-first protocols send nothing and still reach the default target.
+    let mut silent = TcpStream::connect(proxy_addr).await.unwrap();
+    let mut greeting = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), silent.read_exact(&mut greeting))
+        .await
+        .expect("silent client was never forwarded")
+        .unwrap();
+    assert_eq!(&greeting, b"dflt_");
+
+    proxy_handle.abort();
+}
@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use basic_tcp_proxy::{
+    Config, KeepaliveConfig, Proxy, Resolver, SocketConfig, SocketOptions, TcpFastOpen,
+    apply_socket_options, bind_tcp_listener, connect_with_options,
+};
+use echo_server::EchoServer;
+use nix::sys::socket::{getsockopt, sockopt::TcpFastOpenConnect};
+use rustix::net::sockopt;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+#[tokio::test]
+async fn test_apply_socket_options() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let options = SocketOptions {
+        nodelay: Some(true),
+        fastopen: None,
+        keepalive: Some(KeepaliveConfig {
+            idle_secs: 30,
+            interval_secs: 5,
+            count: 3,
+        }),
+        recv_buffer_size: Some(65536),
+        send_buffer_size: Some(65536),
+        linger_secs: Some(2),
+        tos: Some(184),
+        user_timeout_ms: Some(10_000),
+    };
+    apply_socket_options(&stream, &options, false).unwrap();
+
+    assert!(sockopt::tcp_nodelay(&stream).unwrap());
+    assert!(sockopt::socket_keepalive(&stream).unwrap());
+    assert_eq!(
+        sockopt::tcp_keepidle(&stream).unwrap(),
+        Duration::from_secs(30)
+    );
+    assert_eq!(
+        sockopt::tcp_keepintvl(&stream).unwrap(),
+        Duration::from_secs(5)
+    );
+    assert_eq!(sockopt::tcp_keepcnt(&stream).unwrap(), 3);
+    // The kernel reserves as much again for bookkeeping.
+    assert!(sockopt::socket_recv_buffer_size(&stream).unwrap() >= 65536);
+    assert_eq!(
+        sockopt::socket_linger(&stream).unwrap(),
+        Some(Duration::from_secs(2))
+    );
+    assert_eq!(sockopt::ip_tos(&stream).unwrap(), 184);
+    assert_eq!(sockopt::tcp_user_timeout(&stream).unwrap(), 10_000);
+}
+
+#[tokio::test]
+async fn test_fastopen() {
+    let mut sockets = SocketConfig::default();
+    sockets.client.fastopen = Some(true);
+    sockets.fastopen_queue = 64;
+    let listener = bind_tcp_listener("127.0.0.1:0".parse().unwrap(), &sockets).unwrap();
+    assert_eq!(getsockopt(&listener, TcpFastOpen).unwrap(), 64);
+
+    let options = SocketOptions {
+        fastopen: Some(true),
+        ..SocketOptions::default()
+    };
+    let stream = connect_with_options(listener.local_addr().unwrap(), &options)
+        .await
+        .unwrap();
+    assert!(getsockopt(&stream, TcpFastOpenConnect).unwrap());
+}
+
+#[tokio::test]
+async fn test_fastopen_only_without_a_race() {
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-fastopen-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let hosts_path = dir.join("hosts");
+    std::fs::write(
+        &hosts_path,
+        "127.0.0.1 single.test\n127.0.0.1 dual.test\n127.0.0.2 dual.test\n",
+    )
+    .unwrap();
+    let listener = TcpListener::bind("0.0.0.0:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut config = Config::default();
+    config.resolver.hosts_file = Some(hosts_path);
+    config.sockets.upstream.fastopen = Some(true);
+    let resolver = Resolver::new(&config);
+
+    let single = resolver
+        .connect(&format!("single.test:{port}"))
+        .await
+        .unwrap();
+    assert!(getsockopt(&single, TcpFastOpenConnect).unwrap());
+    // With two addresses the first one must not win just because connect() returned early.
+    let dual = resolver
+        .connect(&format!("dual.test:{port}"))
+        .await
+        .unwrap();
+    assert!(!getsockopt(&dual, TcpFastOpenConnect).unwrap());
+    let handshaken = resolver
+        .connect_handshaken(&format!("single.test:{port}"))
+        .await
+        .unwrap();
+    assert!(!getsockopt(&handshaken, TcpFastOpenConnect).unwrap());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_proxy_with_socket_options() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let config: Config = toml::from_str(&format!(
+        r#"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo_addr}"
+
+        [sockets]
+        listen_backlog = 16
+
+        [sockets.client]
+        nodelay = true
+        fastopen = true
+        linger_secs = 0
+        user_timeout_ms = 5000
+
+        [sockets.client.keepalive]
+        idle_secs = 30
+
+        [sockets.upstream]
+        nodelay = true
+        fastopen = true
+        recv_buffer_size = 131072
+        send_buffer_size = 131072
+        tos = 184
+        "#
+    ))
+    .unwrap();
+    let keepalive = config.sockets.client.keepalive.clone().unwrap();
+    assert_eq!((keepalive.interval_secs, keepalive.count), (10, 6));
+
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut buf))
+        .await
+        .expect("no echo through the proxy")
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn test_invalid_socket_options_are_rejected() {
+    let mut config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        ..Config::default()
+    };
+    config.sockets.upstream.keepalive = Some(KeepaliveConfig {
+        idle_secs: 60,
+        interval_secs: 10,
+        count: 0,
+    });
+    assert!(config.validate().is_err());
+
+    config.sockets.upstream.keepalive = None;
+    config.sockets.listen_backlog = 0;
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,265 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use basic_tcp_proxy::{Config, Proxy, ProxyUser, ResolverConfig, RouteConfig, Socks5Config};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+enum Destination<'a> {
+    V4(Ipv4Addr),
+    V6(Ipv6Addr),
+    Domain(&'a str),
+}
+
+fn push_field(buf: &mut Vec<u8>, field: &str) {
+    buf.push(u8::try_from(field.len()).unwrap());
+    buf.extend_from_slice(field.as_bytes());
+}
+
+// Logs in as `user` and asks for a CONNECT; returns the stream and the reply code.
+async fn socks_connect(
+    proxy_addr: SocketAddr,
+    user: (&str, &str),
+    destination: Destination<'_>,
+    port: u16,
+) -> (TcpStream, u8) {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(&[5, 2, 0, 2]).await.unwrap();
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await.unwrap();
+    assert_eq!(method, [5, 2]);
+
+    let (username, password) = user;
+    let mut login = vec![1];
+    push_field(&mut login, username);
+    push_field(&mut login, password);
+    stream.write_all(&login).await.unwrap();
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).await.unwrap();
+    if status[1] != 0 {
+        return (stream, 0xff);
+    }
+
+    let mut request = vec![5, 1, 0];
+    match destination {
+        Destination::V4(ip) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Destination::V6(ip) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Destination::Domain(name) => {
+            request.push(3);
+            push_field(&mut request, name);
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    let mut reply = [0u8; 10];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply[0], 5);
+    (stream, reply[1])
+}
+
+async fn echo_through(stream: &mut TcpStream, msg: &[u8]) {
+    stream.write_all(msg).await.unwrap();
+    let mut buf = vec![0u8; msg.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, msg);
+}
+
+#[tokio::test]
+async fn test_socks5_connect_auth_and_allowlist() {
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-socks-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let hosts_path = dir.join("hosts");
+    std::fs::write(&hosts_path, "127.0.0.1 echo.test\n").unwrap();
+
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    let port = echo_addr.port();
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        resolver: ResolverConfig {
+            hosts_file: Some(hosts_path),
+            ..ResolverConfig::default()
+        },
+        routes: vec![RouteConfig {
+            name: "socks".to_string(),
+            socks5: Some(Socks5Config {
+                users: vec![ProxyUser {
+                    username: "alice".to_string(),
+                    password: "s3cret".to_string(),
+                }],
+                allow: vec!["*.test:*".parse().unwrap(), "127.0.0.0/8".parse().unwrap()],
+            }),
+            ..RouteConfig::default()
+        }],
+        ..Config::default()
+    };
+    let (mut proxy, _) = Proxy::new(config).await.unwrap();
+    let socks_addr = proxy.route_addr("socks").unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let alice = ("alice", "s3cret");
+    let (mut by_name, reply) =
+        socks_connect(socks_addr, alice, Destination::Domain("echo.test"), port).await;
+    assert_eq!(reply, 0);
+    echo_through(&mut by_name, b"through a domain").await;
+    drop(by_name);
+
+    let (mut by_ip, reply) = socks_connect(
+        socks_addr,
+        alice,
+        Destination::V4(Ipv4Addr::LOCALHOST),
+        port,
+    )
+    .await;
+    assert_eq!(reply, 0);
+    echo_through(&mut by_ip, b"through an ip").await;
+    drop(by_ip);
+
+    let (_, reply) = socks_connect(
+        socks_addr,
+        ("alice", "wrong"),
+        Destination::Domain("echo.test"),
+        port,
+    )
+    .await;
+    assert_eq!(reply, 0xff, "bad password was accepted");
+
+    // Neither ::1 nor example.com is on the allowlist.
+    let (_, reply) = socks_connect(
+        socks_addr,
+        alice,
+        Destination::V6(Ipv6Addr::LOCALHOST),
+        port,
+    )
+    .await;
+    assert_eq!(reply, 2);
+    let (_, reply) =
+        socks_connect(socks_addr, alice, Destination::Domain("example.com"), port).await;
+    assert_eq!(reply, 2);
+
+    // A refused connection is reported back with its own reply code.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+    let (_, reply) = socks_connect(
+        socks_addr,
+        alice,
+        Destination::V4(Ipv4Addr::LOCALHOST),
+        closed_port,
+    )
+    .await;
+    assert_eq!(reply, 5);
+
+    let snapshot = tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.total_connections == 2 && s.active_connections == 0),
+    )
+    .await
+    .expect("SOCKS connections never showed up in the metrics")
+    .unwrap()
+    .clone();
+    assert_eq!(snapshot.bytes_upstream, 16 + 13);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_socks5_denies_internal_destinations_by_default() {
+    let dir = std::env::temp_dir().join(format!(
+        "basic-tcp-proxy-socks-internal-{}",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let hosts_path = dir.join("hosts");
+    std::fs::write(&hosts_path, "127.0.0.1 echo.test\n").unwrap();
+
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    let port = echo_addr.port();
+
+    let route = |name: &str, allow: &[&str]| RouteConfig {
+        name: name.to_string(),
+        socks5: Some(Socks5Config {
+            users: vec![ProxyUser {
+                username: "alice".to_string(),
+                password: "s3cret".to_string(),
+            }],
+            allow: allow.iter().map(|rule| rule.parse().unwrap()).collect(),
+        }),
+        ..RouteConfig::default()
+    };
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        resolver: ResolverConfig {
+            hosts_file: Some(hosts_path),
+            ..ResolverConfig::default()
+        },
+        routes: vec![
+            route("open", &[]),
+            route("any", &["*"]),
+            route("named", &["echo.test"]),
+        ],
+        ..Config::default()
+    };
+    let (mut proxy, _) = Proxy::new(config).await.unwrap();
+    let open_addr = proxy.route_addr("open").unwrap();
+    let any_addr = proxy.route_addr("any").unwrap();
+    let named_addr = proxy.route_addr("named").unwrap();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let alice = ("alice", "s3cret");
+    for addr in [open_addr, any_addr] {
+        let (_, reply) =
+            socks_connect(addr, alice, Destination::V4(Ipv4Addr::LOCALHOST), port).await;
+        assert_eq!(reply, 2);
+        // A hostname that resolves to loopback is no way around it.
+        let (_, reply) = socks_connect(addr, alice, Destination::Domain("echo.test"), port).await;
+        assert_eq!(reply, 2);
+    }
+
+    // Naming the host opens it, but only that host.
+    let (mut stream, reply) =
+        socks_connect(named_addr, alice, Destination::Domain("echo.test"), port).await;
+    assert_eq!(reply, 0);
+    echo_through(&mut stream, b"named").await;
+    let (_, reply) = socks_connect(
+        named_addr,
+        alice,
+        Destination::V4(Ipv4Addr::LOCALHOST),
+        port,
+    )
+    .await;
+    assert_eq!(reply, 2);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
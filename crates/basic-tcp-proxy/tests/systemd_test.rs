@@ -0,0 +1,113 @@
+use std::{
+    net::TcpListener,
+    os::fd::OwnedFd,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use command_fds::{CommandFdExt, FdMapping};
+use echo_server::EchoServer;
+use rustix::process::{Pid, Signal, kill_process};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UnixDatagram},
+    time::timeout,
+};
+
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+    }
+}
+
+async fn wait_for_state(socket: &UnixDatagram, state: &str) -> String {
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = timeout(Duration::from_secs(10), socket.recv(&mut buf))
+            .await
+            .expect("no sd_notify message")
+            .unwrap();
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        if message.lines().any(|line| line == state) {
+            return message;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_socket_activation_and_notify() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-systemd-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("proxy.toml");
+    std::fs::write(
+        &config_path,
+        format!("target_addr = \"{}\"\ngrace_period_secs = 5\n", echo_addr),
+    )
+    .unwrap();
+    let notify_path = dir.join("notify.sock");
+    let notify_socket = UnixDatagram::bind(&notify_path).unwrap();
+
+    let route_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let metrics_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let route_addr = route_listener.local_addr().unwrap();
+    let metrics_addr = metrics_listener.local_addr().unwrap();
+
+    // systemd sets LISTEN_PID to the service's own pid; `exec` keeps the shell's pid.
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg("export LISTEN_PID=$$; exec \"$0\" \"$@\"")
+        .arg(env!("CARGO_BIN_EXE_basic-tcp-proxy"))
+        .arg(&config_path)
+        .env("LISTEN_FDS", "2")
+        .env("LISTEN_FDNAMES", "default:metrics")
+        .env("NOTIFY_SOCKET", &notify_path)
+        .stdout(Stdio::null())
+        .fd_mappings(vec![
+            FdMapping {
+                parent_fd: OwnedFd::from(route_listener),
+                child_fd: 3,
+            },
+            FdMapping {
+                parent_fd: OwnedFd::from(metrics_listener),
+                child_fd: 4,
+            },
+        ])
+        .unwrap()
+        .spawn()
+        .unwrap();
+    let mut child = KillOnDrop(child);
+
+    let ready = wait_for_state(&notify_socket, "READY=1").await;
+    assert!(ready.contains("STATUS=Serving 1 route(s)"));
+
+    let mut stream = TcpStream::connect(route_addr).await.unwrap();
+    stream.write_all(b"activated").await.unwrap();
+    let mut buf = [0u8; 9];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"activated");
+    drop(stream);
+
+    let mut http = TcpStream::connect(metrics_addr).await.unwrap();
+    http.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    http.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("connections_total"));
+
+    let pid = Pid::from_child(&child.0);
+    kill_process(pid, Signal::TERM).unwrap();
+    wait_for_state(&notify_socket, "STOPPING=1").await;
+    assert!(child.0.wait().unwrap().success());
+
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
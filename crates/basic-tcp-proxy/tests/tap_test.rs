@@ -0,0 +1,129 @@
+use std::{
+    io::{BufRead, BufReader},
+    net::TcpListener,
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    time::Duration,
+};
+
+use basic_tcp_proxy::hexdump;
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+    }
+}
+
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+// Collects stdout until a line matching `done` shows up.
+fn read_until(lines: &mpsc::Receiver<String>, done: impl Fn(&str) -> bool) -> Vec<String> {
+    let mut seen = Vec::new();
+    while let Ok(line) = lines.recv_timeout(Duration::from_secs(10)) {
+        let finished = done(&line);
+        seen.push(line);
+        if finished {
+            return seen;
+        }
+    }
+    panic!("output ended early: {:#?}", seen);
+}
+
+#[test]
+fn test_hexdump_layout() {
+    let dump = hexdump(0x20, b"GET / HTTP/1.1\r\nHost: x\r\n");
+    assert_eq!(
+        dump,
+        "00000020  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|\n\
+         00000030  48 6f 73 74 3a 20 78 0d  0a                       |Host: x..|\n"
+    );
+    assert_eq!(hexdump(0, b""), "");
+}
+
+#[tokio::test]
+async fn test_tap_logs_first_bytes_of_matching_clients() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-tap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("proxy.toml");
+    let listen_addr = free_addr();
+    std::fs::write(
+        &config_path,
+        format!(
+            "listen_addr = \"{}\"\ntarget_addr = \"{}\"\nmetrics_addr = \"{}\"\n\n[tap]\nclients = [\"127.0.0.0/8\"]\nmax_bytes = 20\n",
+            listen_addr,
+            echo_addr,
+            free_addr()
+        ),
+    )
+    .unwrap();
+
+    let mut child = KillOnDrop(
+        Command::new(env!("CARGO_BIN_EXE_basic-tcp-proxy"))
+            .arg(&config_path)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap(),
+    );
+    let stdout = child.0.stdout.take().unwrap();
+    let (lines_tx, lines_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if lines_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    read_until(&lines_rx, |line| line.starts_with("Proxy listening on"));
+
+    let mut stream = TcpStream::connect(&listen_addr).await.unwrap();
+    let client_addr = stream.local_addr().unwrap();
+    for msg in [&b"hello tap"[..], b"0123456789abcdef"] {
+        stream.write_all(msg).await.unwrap();
+        let mut buf = vec![0u8; msg.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    drop(stream);
+
+    let output = read_until(&lines_rx, |line| line.contains("ConnectionClosed")).join("\n");
+    let headers: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("[TAP]"))
+        .collect();
+    assert_eq!(
+        headers,
+        [
+            format!("[TAP] {} → Upstream offset 0 (9 bytes)", client_addr),
+            format!("[TAP] {} ← Downstream offset 0 (9 bytes)", client_addr),
+            format!(
+                "[TAP] {} → Upstream offset 9 (16 bytes), limit reached after 2 bytes",
+                client_addr
+            ),
+        ]
+    );
+    assert!(
+        output.contains("00000000  68 65 6c 6c 6f 20 74 61  70"),
+        "{}",
+        output
+    );
+    assert!(output.contains("00000009  30 31  "), "{}", output);
+    assert!(output.contains("|01|"), "{}", output);
+
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
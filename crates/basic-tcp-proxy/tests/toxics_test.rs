@@ -0,0 +1,208 @@
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use basic_tcp_proxy::{Config, Proxy};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinHandle,
+    time::timeout,
+};
+
+// `{echo}` in the config is replaced with the address of a fresh echo server.
+async fn start_proxy(config: &str) -> (Proxy, SocketAddr, JoinHandle<()>) {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let config = config.replace("{echo}", &echo_addr.to_string());
+    let config: Config = toml::from_str(&config).unwrap();
+    let (proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    (proxy, proxy_addr, echo_handle)
+}
+
+async fn timed_echo(stream: &mut TcpStream, msg: &[u8]) -> (Vec<u8>, Duration) {
+    let start = Instant::now();
+    stream.write_all(msg).await.unwrap();
+    let mut buf = vec![0u8; msg.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    (buf, start.elapsed())
+}
+
+async fn http_request(addr: SocketAddr, method: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method, path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_latency_toxic_toggled_over_http() {
+    let (mut proxy, proxy_addr, echo_handle) = start_proxy(
+        r#"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo}"
+
+        [[toxics]]
+        name = "lag"
+        type = "latency"
+        stream = "downstream"
+        attributes = { latency = 300, jitter = 20 }
+        "#,
+    )
+    .await;
+    let metrics_addr = proxy.metrics_addr();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let (reply, elapsed) = timed_echo(&mut stream, b"slow").await;
+    assert_eq!(reply, b"slow");
+    assert!(elapsed >= Duration::from_millis(270), "{:?}", elapsed);
+
+    let response = http_request(metrics_addr, "POST", "/toxics/default/lag/disable").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("\"enabled\":false"));
+
+    // Toggles apply to connections that are already open.
+    let (_, elapsed) = timed_echo(&mut stream, b"fast").await;
+    assert!(elapsed < Duration::from_millis(150), "{:?}", elapsed);
+
+    let listing = http_request(metrics_addr, "GET", "/toxics").await;
+    assert!(listing.contains("\"type\":\"latency\""), "{}", listing);
+    assert!(listing.contains("\"latency\":300"), "{}", listing);
+
+    let response = http_request(metrics_addr, "POST", "/toxics/default/lag/enable").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let (_, elapsed) = timed_echo(&mut stream, b"slow").await;
+    assert!(elapsed >= Duration::from_millis(270), "{:?}", elapsed);
+
+    let response = http_request(metrics_addr, "POST", "/toxics/default/missing/enable").await;
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
+
+#[tokio::test]
+async fn test_timeout_and_reset_toxics() {
+    let (mut proxy, proxy_addr, echo_handle) = start_proxy(
+        r#"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo}"
+
+        [[toxics]]
+        name = "blackhole"
+        type = "timeout"
+        stream = "upstream"
+        attributes = { timeout = 500 }
+
+        [[routes]]
+        name = "flaky"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo}"
+
+        [[routes.toxics]]
+        name = "reset"
+        type = "reset_peer"
+        attributes = { timeout = 200 }
+        "#,
+    )
+    .await;
+    let flaky_addr = proxy.route_addr("flaky").unwrap();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let start = Instant::now();
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(b"lost").await.unwrap();
+    let mut buf = [0u8; 4];
+    let n = timeout(Duration::from_secs(3), stream.read(&mut buf))
+        .await
+        .expect("timeout toxic never closed the connection")
+        .unwrap_or(0);
+    assert_eq!(n, 0, "blackholed data came back");
+    assert!(start.elapsed() >= Duration::from_millis(450));
+
+    let mut stream = TcpStream::connect(flaky_addr).await.unwrap();
+    let result = timeout(Duration::from_secs(3), stream.read(&mut buf))
+        .await
+        .expect("reset_peer toxic never fired");
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::ConnectionReset);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
+
+#[tokio::test]
+async fn test_corrupt_slicer_and_toxicity() {
+    let (mut proxy, _, echo_handle) = start_proxy(
+        r#"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo}"
+
+        [[routes]]
+        name = "corrupt"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo}"
+        toxics = [
+            { name = "flip", type = "corrupt", stream = "upstream", attributes = { probability = 1.0 } },
+        ]
+
+        [[routes]]
+        name = "sliced"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo}"
+        toxics = [
+            { name = "slices", type = "slicer", attributes = { average_size = 2, delay = 20000 } },
+        ]
+
+        [[routes]]
+        name = "lucky"
+        listen_addr = "127.0.0.1:0"
+        target_addr = "{echo}"
+        toxics = [
+            { name = "never", type = "latency", toxicity = 0.0, attributes = { latency = 1000 } },
+        ]
+        "#,
+    )
+    .await;
+    let corrupt_addr = proxy.route_addr("corrupt").unwrap();
+    let sliced_addr = proxy.route_addr("sliced").unwrap();
+    let lucky_addr = proxy.route_addr("lucky").unwrap();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let msg = b"0123456789abcdefghij";
+
+    let mut stream = TcpStream::connect(corrupt_addr).await.unwrap();
+    let (reply, _) = timed_echo(&mut stream, msg).await;
+    assert!(reply.iter().zip(msg).all(|(got, sent)| got != sent));
+
+    // 20 bytes in 2-byte slices leaves nine 20ms gaps.
+    let mut stream = TcpStream::connect(sliced_addr).await.unwrap();
+    let (reply, elapsed) = timed_echo(&mut stream, msg).await;
+    assert_eq!(reply, msg);
+    assert!(elapsed >= Duration::from_millis(170), "{:?}", elapsed);
+
+    let mut stream = TcpStream::connect(lucky_addr).await.unwrap();
+    let (reply, elapsed) = timed_echo(&mut stream, msg).await;
+    assert_eq!(reply, msg);
+    assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
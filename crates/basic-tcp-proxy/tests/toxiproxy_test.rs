@@ -0,0 +1,155 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use basic_tcp_proxy::{Config, Proxy};
+use echo_server::EchoServer;
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+async fn start_proxy() -> (SocketAddr, SocketAddr, JoinHandle<()>, JoinHandle<()>) {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        metrics_addr: "127.0.0.1:0".to_string(),
+        ..Config::default()
+    };
+    let (mut proxy, _) = Proxy::new(config).await.unwrap();
+    let metrics_addr = proxy.metrics_addr();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+    (metrics_addr, echo_addr, proxy_handle, echo_handle)
+}
+
+async fn api(addr: SocketAddr, method: &str, path: &str, body: Option<&Value>) -> (u16, Value) {
+    let body = body.map(Value::to_string).unwrap_or_default();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(body).unwrap()
+    };
+    (status, body)
+}
+
+// Objects only need the expected keys, arrays must match element by element, "*" matches anything.
+fn matches(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::String(s), _) if s == "*" => true,
+        (Value::Object(expected), Value::Object(actual)) => expected
+            .iter()
+            .all(|(key, value)| actual.get(key).is_some_and(|actual| matches(value, actual))),
+        (Value::Array(expected), Value::Array(actual)) => {
+            expected.len() == actual.len()
+                && expected.iter().zip(actual).all(|(e, a)| matches(e, a))
+        }
+        (Value::Number(expected), Value::Number(actual)) => expected.as_f64() == actual.as_f64(),
+        _ => expected == actual,
+    }
+}
+
+#[tokio::test]
+async fn test_recorded_toxiproxy_client_session() {
+    let (metrics_addr, echo_addr, proxy_handle, echo_handle) = start_proxy().await;
+
+    let fixture = include_str!("fixtures/toxiproxy_session.json")
+        .replace("{upstream}", &echo_addr.to_string());
+    let steps: Vec<Value> = serde_json::from_str(&fixture).unwrap();
+
+    for (i, step) in steps.iter().enumerate() {
+        let request = &step["request"];
+        let method = request["method"].as_str().unwrap();
+        let path = request["path"].as_str().unwrap();
+        let (status, body) = api(metrics_addr, method, path, request.get("body")).await;
+
+        let expected = &step["response"];
+        assert_eq!(
+            u64::from(status),
+            expected["status"].as_u64().unwrap(),
+            "step {} ({} {}): {}",
+            i,
+            method,
+            path,
+            body
+        );
+        if let Some(expected_body) = expected.get("body") {
+            assert!(
+                matches(expected_body, &body),
+                "step {} ({} {}): expected {}, got {}",
+                i,
+                method,
+                path,
+                expected_body,
+                body
+            );
+        }
+    }
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
+
+#[tokio::test]
+async fn test_created_proxy_relays_with_toxics() {
+    let (metrics_addr, echo_addr, proxy_handle, echo_handle) = start_proxy().await;
+
+    let spec = serde_json::json!({
+        "name": "echo",
+        "listen": "127.0.0.1:0",
+        "upstream": echo_addr.to_string(),
+    });
+    let (status, proxy) = api(metrics_addr, "POST", "/proxies", Some(&spec)).await;
+    assert_eq!(status, 201, "{}", proxy);
+    let listen: SocketAddr = proxy["listen"].as_str().unwrap().parse().unwrap();
+
+    let toxic = serde_json::json!({ "type": "latency", "attributes": { "latency": 300 } });
+    let (status, _) = api(metrics_addr, "POST", "/proxies/echo/toxics", Some(&toxic)).await;
+    assert_eq!(status, 200);
+
+    let mut stream = TcpStream::connect(listen).await.unwrap();
+    let start = Instant::now();
+    stream.write_all(b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    assert!(start.elapsed() >= Duration::from_millis(270));
+
+    // Disabling a proxy closes its open connections and stops listening.
+    let disable = serde_json::json!({ "enabled": false });
+    let (status, _) = api(metrics_addr, "POST", "/proxies/echo", Some(&disable)).await;
+    assert_eq!(status, 200);
+    let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf))
+        .await
+        .expect("disabled proxy kept the connection open")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(TcpStream::connect(listen).await.is_err());
+
+    proxy_handle.abort();
+    echo_handle.abort();
+}
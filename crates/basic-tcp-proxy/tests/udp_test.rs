@@ -0,0 +1,84 @@
+use std::{net::SocketAddr, time::Duration};
+
+use basic_tcp_proxy::{Config, MetricsSnapshot, Proxy, UdpRouteConfig};
+use tokio::{net::UdpSocket, sync::watch, time::timeout};
+
+async fn start_udp_echo() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+        loop {
+            let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..n], peer).await.unwrap();
+        }
+    });
+    addr
+}
+
+async fn echo(client: &UdpSocket, msg: &[u8]) {
+    client.send(msg).await.unwrap();
+    let mut buf = [0u8; 2048];
+    let n = timeout(Duration::from_secs(5), client.recv(&mut buf))
+        .await
+        .expect("no echo through the proxy")
+        .unwrap();
+    assert_eq!(&buf[..n], msg);
+}
+
+async fn wait_for(
+    metrics_rx: &mut watch::Receiver<MetricsSnapshot>,
+    done: impl Fn(&MetricsSnapshot) -> bool,
+) -> MetricsSnapshot {
+    timeout(Duration::from_secs(5), metrics_rx.wait_for(done))
+        .await
+        .expect("metrics never settled")
+        .unwrap()
+        .clone()
+}
+
+#[tokio::test]
+async fn test_udp_sessions_relay_and_expire() {
+    let echo_addr = start_udp_echo().await;
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        udp_routes: vec![UdpRouteConfig {
+            name: "echo".to_string(),
+            listen_addr: "127.0.0.1:0".to_string(),
+            target_addr: echo_addr.to_string(),
+            idle_timeout_secs: 1,
+        }],
+        ..Config::default()
+    };
+    let (mut proxy, _) = Proxy::new(config).await.unwrap();
+    let udp_addr = proxy.udp_route_addr("echo").unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    first.connect(udp_addr).await.unwrap();
+    second.connect(udp_addr).await.unwrap();
+
+    echo(&first, b"ping").await;
+    echo(&second, b"statsd.counter:1|c").await;
+    echo(&first, b"pong").await;
+
+    let snapshot = wait_for(&mut metrics_rx, |s| s.bytes_downstream == 26).await;
+    assert_eq!(snapshot.active_connections, 2);
+    assert_eq!(snapshot.total_connections, 2);
+    assert_eq!(snapshot.bytes_upstream, 26);
+
+    // Both sessions expire after a second without traffic.
+    let snapshot = wait_for(&mut metrics_rx, |s| s.active_connections == 0).await;
+    assert_eq!(snapshot.total_connections, 2);
+
+    // A client that comes back after expiry gets a fresh session.
+    echo(&first, b"again").await;
+    let snapshot = wait_for(&mut metrics_rx, |s| s.total_connections == 3).await;
+    assert_eq!(snapshot.active_connections, 1);
+
+    proxy_handle.abort();
+}
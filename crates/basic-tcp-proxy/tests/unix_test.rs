@@ -0,0 +1,119 @@
+use std::{os::unix::fs::PermissionsExt, path::Path, time::Duration};
+
+use basic_tcp_proxy::{Config, Proxy, RouteConfig, UnixSocketConfig};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixListener, UnixStream},
+};
+
+async fn echo_through<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, msg: &[u8]) {
+    stream.write_all(msg).await.unwrap();
+    let mut buf = vec![0u8; msg.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, msg);
+}
+
+async fn wait_until_removed(path: &Path) {
+    for _ in 0..40 {
+        if !path.exists() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("{} was not removed", path.display());
+}
+
+#[tokio::test]
+async fn test_unix_socket_bridging() {
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-unix-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let listen_path = dir.join("proxy.sock");
+    let target_path = dir.join("sidecar.sock");
+
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let sidecar = UnixListener::bind(&target_path).unwrap();
+    let sidecar_handle = tokio::spawn(async move {
+        loop {
+            let (stream, _) = sidecar.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.into_split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+
+    // A socket file left behind by an earlier run.
+    drop(std::os::unix::net::UnixListener::bind(&listen_path).unwrap());
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        unix_socket: UnixSocketConfig {
+            mode: Some(0o600),
+            ..UnixSocketConfig::default()
+        },
+        routes: vec![
+            RouteConfig {
+                name: "uds-in".to_string(),
+                listen_addr: format!("unix:{}", listen_path.display()),
+                target_addr: echo_addr.to_string(),
+                ..RouteConfig::default()
+            },
+            RouteConfig {
+                name: "uds-out".to_string(),
+                listen_addr: "127.0.0.1:0".to_string(),
+                target_addr: format!("unix:{}", target_path.display()),
+                ..RouteConfig::default()
+            },
+        ],
+        ..Config::default()
+    };
+    let (mut proxy, _) = Proxy::new(config).await.unwrap();
+    assert_eq!(proxy.route_addr("uds-in"), None);
+    let uds_out_addr = proxy.route_addr("uds-out").unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let mode = std::fs::metadata(&listen_path)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // Unix client → TCP target, twice at once, then TCP client → Unix target.
+    let first = UnixStream::connect(&listen_path).await.unwrap();
+    let second = UnixStream::connect(&listen_path).await.unwrap();
+    echo_through(first, b"from a unix client").await;
+    echo_through(second, b"another unix client").await;
+    echo_through(
+        TcpStream::connect(uds_out_addr).await.unwrap(),
+        b"to a unix sidecar",
+    )
+    .await;
+
+    let snapshot = tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.total_connections == 3 && s.active_connections == 0),
+    )
+    .await
+    .expect("connections never closed")
+    .unwrap()
+    .clone();
+    assert_eq!(snapshot.bytes_upstream, 18 + 19 + 17);
+
+    // Stopping the proxy removes the socket file it created.
+    proxy_handle.abort();
+    wait_until_removed(&listen_path).await;
+
+    echo_handle.abort();
+    sidecar_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
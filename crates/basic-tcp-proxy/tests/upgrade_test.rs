@@ -0,0 +1,91 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use basic_tcp_proxy::{Config, Proxy};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[tokio::test]
+async fn test_listener_handoff_refuses_no_connections() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let echo_handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-upgrade-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let upgrade_socket = dir.join("upgrade.sock");
+
+    let old_config = Config {
+        target_addr: echo_addr.to_string(),
+        upgrade_socket: Some(upgrade_socket.clone()),
+        grace_period_secs: 5,
+        ..Config::default()
+    };
+    let (mut old_proxy, proxy_addr) = Proxy::new(old_config).await.unwrap();
+    let old_handle = tokio::spawn(async move { old_proxy.run().await });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let succeeded = Arc::new(AtomicU64::new(0));
+    let failed = Arc::new(AtomicU64::new(0));
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        let (running, succeeded, failed) = (running.clone(), succeeded.clone(), failed.clone());
+        clients.push(tokio::spawn(async move {
+            while running.load(Ordering::Relaxed) {
+                let attempt = async {
+                    let mut stream = TcpStream::connect(proxy_addr).await?;
+                    stream.write_all(b"ping").await?;
+                    let mut buf = [0u8; 4];
+                    stream.read_exact(&mut buf).await?;
+                    Ok::<_, std::io::Error>(buf)
+                };
+                match attempt.await {
+                    Ok(buf) if &buf == b"ping" => succeeded.fetch_add(1, Ordering::Relaxed),
+                    _ => failed.fetch_add(1, Ordering::Relaxed),
+                };
+            }
+        }));
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let new_config = Config {
+        listen_addr: proxy_addr.to_string(),
+        target_addr: echo_addr.to_string(),
+        upgrade_socket: Some(upgrade_socket.clone()),
+        ..Config::default()
+    };
+    let (mut new_proxy, new_addr) = Proxy::new(new_config).await.unwrap();
+    assert_eq!(new_addr, proxy_addr);
+    let new_metrics = new_proxy.metrics();
+    let new_handle = tokio::spawn(async move { new_proxy.run().await });
+
+    tokio::time::timeout(Duration::from_secs(5), old_handle)
+        .await
+        .expect("old proxy did not drain")
+        .unwrap()
+        .unwrap();
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    running.store(false, Ordering::Relaxed);
+    for client in clients {
+        client.await.unwrap();
+    }
+
+    assert_eq!(failed.load(Ordering::Relaxed), 0);
+    assert!(succeeded.load(Ordering::Relaxed) > 0);
+    assert!(new_metrics.borrow().total_connections > 0);
+
+    new_handle.abort();
+    echo_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,307 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use basic_tcp_proxy::{
+    BandwidthShaper, CaptureRegistry, Config, ConnectionLimiter, DEFAULT_ROUTE, HttpPool, MuxPool,
+    Proxy, Resolver, ReverseServices, RouteContext, ToxicRegistry, WarmPool, WarmPoolConfig,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, copy},
+    net::{TcpListener, TcpStream, UnixListener},
+    sync::{mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+// Echoes on every connection except the first `close_first`, which it closes shortly after
+// accepting them, and counts the connections it accepted.
+async fn target(close_first: usize) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            if counter.fetch_add(1, Ordering::SeqCst) < close_first {
+                tokio::spawn(async move {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    drop(stream);
+                });
+                continue;
+            }
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.into_split();
+                let _ = copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+    (addr, accepted)
+}
+
+async fn wait_accepted(accepted: &AtomicUsize, count: usize) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while accepted.load(Ordering::SeqCst) < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("pool was never filled");
+}
+
+async fn echo(proxy_addr: SocketAddr) {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut buf))
+        .await
+        .expect("no echo through the proxy")
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn test_warm_pool_hits_and_discards_closed_connections() {
+    // The target closes the first two pooled connections, as an idle timeout would.
+    let (target_addr, accepted) = target(2).await;
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: target_addr.to_string(),
+        warm_pool: Some(WarmPoolConfig {
+            size: 2,
+            max_idle_secs: 30,
+        }),
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    // The pool fills before any client shows up.
+    wait_accepted(&accepted, 2).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // Both pooled connections fail validation, so the client gets a fresh one.
+    echo(proxy_addr).await;
+    // That connection, then two replacements for the pool.
+    wait_accepted(&accepted, 5).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    echo(proxy_addr).await;
+
+    let snapshot = tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.total_connections == 2 && s.active_connections == 0),
+    )
+    .await
+    .expect("connections never closed in the metrics")
+    .unwrap()
+    .clone();
+    assert_eq!((snapshot.warm_pool_hits, snapshot.warm_pool_misses), (1, 1));
+    assert!(snapshot.to_plain_text().contains("warm_pool_hits 1"));
+    // Only the pool's own refill connected after the hit.
+    wait_accepted(&accepted, 6).await;
+    assert_eq!(accepted.load(Ordering::SeqCst), 6);
+
+    proxy_handle.abort();
+}
+
+fn free_port() -> u16 {
+    let reserved = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    reserved.local_addr().unwrap().port()
+}
+
+fn write_config(path: &Path, pooled_port: u16, target_addr: SocketAddr) {
+    let content = format!(
+        "listen_addr = \"127.0.0.1:0\"\ntarget_addr = \"{target_addr}\"\nwatch_config = true\nwatch_interval_secs = 1\n\n[[routes]]\nname = \"pooled\"\nlisten_addr = \"127.0.0.1:{pooled_port}\"\ntarget_addr = \"{target_addr}\"\n\n[routes.warm_pool]\nsize = 2\n"
+    );
+    std::fs::write(path, content).unwrap();
+}
+
+#[tokio::test]
+async fn test_warm_pool_survives_route_respawn() {
+    let (target_addr, accepted) = target(0).await;
+    let dir =
+        std::env::temp_dir().join(format!("basic-tcp-proxy-warm-pool-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("proxy.toml");
+    write_config(&path, free_port(), target_addr);
+
+    let (mut proxy, _) = Proxy::new(Config::from_file(&path).unwrap()).await.unwrap();
+    let mut metrics_rx = proxy.metrics();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+    wait_accepted(&accepted, 2).await;
+
+    // A new listen address re-spawns the route under the same name; the old refill task's
+    // cleanup must leave the new task's connections alone.
+    let new_port = free_port();
+    write_config(&path, new_port, target_addr);
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.config_reloads == 1),
+    )
+    .await
+    .expect("config was never reloaded")
+    .unwrap();
+    wait_accepted(&accepted, 4).await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    echo(SocketAddr::from(([127, 0, 0, 1], new_port))).await;
+    let snapshot = tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.total_connections == 1 && s.active_connections == 0),
+    )
+    .await
+    .expect("connection never closed in the metrics")
+    .unwrap()
+    .clone();
+    assert_eq!((snapshot.warm_pool_hits, snapshot.warm_pool_misses), (1, 0));
+
+    proxy_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn test_warm_pool_keeps_successor_connections() {
+    let (target_addr, accepted) = target(0).await;
+    let config = Config {
+        target_addr: target_addr.to_string(),
+        warm_pool: Some(WarmPoolConfig {
+            size: 2,
+            max_idle_secs: 30,
+        }),
+        ..Config::default()
+    };
+    let (_config_tx, config_rx) = watch::channel(Arc::new(config.clone()));
+    let (metrics_tx, _metrics_rx) = mpsc::channel(16);
+    let warm_pool = WarmPool::new();
+    let ctx = RouteContext {
+        route: DEFAULT_ROUTE.to_string(),
+        config_rx,
+        graceful_token: CancellationToken::new(),
+        metrics_tx,
+        limiter: ConnectionLimiter::new(),
+        shaper: BandwidthShaper::new(),
+        toxics: ToxicRegistry::new(&config),
+        capture: CaptureRegistry::new(&config),
+        resolver: Resolver::new(&config),
+        http_pool: HttpPool::new(),
+        mux_pool: MuxPool::new(),
+        reverse_services: ReverseServices::new(),
+        warm_pool: Arc::clone(&warm_pool),
+    };
+
+    // The route as it ran before a reload, then its re-spawn under the same name, which fills
+    // its own connections before the old task gets to exit.
+    let old_stop = CancellationToken::new();
+    let old = tokio::spawn({
+        let (ctx, stop) = (ctx.clone(), old_stop.clone());
+        async move { ctx.warm_pool.refill(&ctx, &stop).await }
+    });
+    wait_accepted(&accepted, 2).await;
+    let new_stop = CancellationToken::new();
+    let new = tokio::spawn({
+        let (ctx, stop) = (ctx.clone(), new_stop.clone());
+        async move { ctx.warm_pool.refill(&ctx, &stop).await }
+    });
+    wait_accepted(&accepted, 4).await;
+    old_stop.cancel();
+    old.await.unwrap();
+
+    let max_idle = Duration::from_secs(30);
+    let target = target_addr.to_string();
+    assert!(
+        warm_pool
+            .checkout(DEFAULT_ROUTE, &target, max_idle)
+            .is_some()
+    );
+    assert!(
+        warm_pool
+            .checkout(DEFAULT_ROUTE, &target, max_idle)
+            .is_some()
+    );
+    assert!(
+        warm_pool
+            .checkout(DEFAULT_ROUTE, &target, max_idle)
+            .is_none()
+    );
+
+    new_stop.cancel();
+    new.await.unwrap();
+}
+
+// Greets every connection and closes it, as a target with a short idle timeout would.
+async fn greet_and_close<S: AsyncWriteExt + Unpin>(mut stream: S) {
+    let _ = stream.write_all(b"220 bye\r\n").await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+}
+
+#[tokio::test]
+async fn test_warm_pool_discards_greeted_and_closed_connections() {
+    let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp_addr = tcp.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = tcp.accept().await.unwrap();
+            tokio::spawn(greet_and_close(stream));
+        }
+    });
+    let dir = std::env::temp_dir().join(format!("basic-tcp-proxy-warm-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let unix_path = dir.join("target.sock");
+    let unix = UnixListener::bind(&unix_path).unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = unix.accept().await.unwrap();
+            tokio::spawn(greet_and_close(stream));
+        }
+    });
+
+    for target_addr in [
+        tcp_addr.to_string(),
+        format!("unix:{}", unix_path.display()),
+    ] {
+        let config = Config {
+            listen_addr: "127.0.0.1:0".to_string(),
+            target_addr,
+            warm_pool: Some(WarmPoolConfig {
+                size: 2,
+                max_idle_secs: 30,
+            }),
+            ..Config::default()
+        };
+        let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+        let mut metrics_rx = proxy.metrics();
+        let proxy_handle = tokio::spawn(async move {
+            proxy.run().await.unwrap();
+        });
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // The greeting is still queued on the pooled connections, but the close behind it
+        // must keep them from being handed out.
+        let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+        let mut greeting = [0u8; 9];
+        stream.read_exact(&mut greeting).await.unwrap();
+        let snapshot = tokio::time::timeout(
+            Duration::from_secs(5),
+            metrics_rx.wait_for(|s| s.total_connections == 1),
+        )
+        .await
+        .expect("connection never showed up in the metrics")
+        .unwrap()
+        .clone();
+        assert_eq!((snapshot.warm_pool_hits, snapshot.warm_pool_misses), (0, 1));
+        proxy_handle.abort();
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,235 @@
+use std::{net::SocketAddr, time::Duration};
+
+use basic_tcp_proxy::{
+    Config, MetricsSnapshot, Proxy, TunnelSide, WsTunnelConfig, websocket_accept_key,
+};
+use echo_server::EchoServer;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+};
+
+const TOKEN: &str = "s3cret";
+// The sample key from RFC 6455 section 1.3.
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+fn tunnel(side: TunnelSide, token: &str) -> WsTunnelConfig {
+    WsTunnelConfig {
+        side,
+        token: token.to_string(),
+        path: "/tunnel".to_string(),
+        host: None,
+        connect_attempts: 10,
+        retry_backoff_ms: 50,
+    }
+}
+
+async fn start(config: Config) -> (SocketAddr, watch::Receiver<MetricsSnapshot>) {
+    let (mut proxy, addr) = Proxy::new(config).await.unwrap();
+    let metrics_rx = proxy.metrics();
+    tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+    (addr, metrics_rx)
+}
+
+async fn handshake(server_addr: SocketAddr, token: &str) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    let request = format!(
+        "GET /tunnel HTTP/1.1\r\nHost: tunnel.test\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\nAuthorization: Bearer {}\r\n\r\n",
+        KEY, token
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).await.unwrap();
+        head.push(byte[0]);
+    }
+    (stream, String::from_utf8(head).unwrap())
+}
+
+async fn wait_closed(metrics_rx: &mut watch::Receiver<MetricsSnapshot>) -> MetricsSnapshot {
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        metrics_rx.wait_for(|s| s.total_connections > 0 && s.active_connections == 0),
+    )
+    .await
+    .expect("tunnel never closed in the metrics")
+    .unwrap()
+    .clone()
+}
+
+#[tokio::test]
+async fn test_ws_tunnel_round_trip_with_reconnect() {
+    assert_eq!(websocket_accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+
+    // The tunnel server is not up yet when the first client connects.
+    let reserved = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server_addr = reserved.local_addr().unwrap();
+    drop(reserved);
+
+    let (client_addr, mut client_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: server_addr.to_string(),
+        ws_tunnel: Some(tunnel(TunnelSide::Client, TOKEN)),
+        ..Config::default()
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(client_addr).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    let (_, mut server_metrics) = start(Config {
+        listen_addr: server_addr.to_string(),
+        target_addr: echo_addr.to_string(),
+        ws_tunnel: Some(tunnel(TunnelSide::Server, TOKEN)),
+        ..Config::default()
+    })
+    .await;
+
+    let mut buf = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_exact(&mut buf))
+        .await
+        .expect("no echo through the tunnel")
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+    stream.write_all(b" world").await.unwrap();
+    let mut buf = [0u8; 6];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b" world");
+    drop(stream);
+
+    // Both sides count the payload, not the WebSocket framing around it.
+    for metrics_rx in [&mut client_metrics, &mut server_metrics] {
+        let snapshot = wait_closed(metrics_rx).await;
+        assert_eq!(snapshot.total_connections, 1);
+        assert_eq!(snapshot.bytes_upstream, 11);
+        assert_eq!(snapshot.bytes_downstream, 11);
+    }
+
+    let (_, head) = handshake(server_addr, "wrong").await;
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+
+    // A plain WebSocket client gets the RFC accept value and a masked frame echoed back unmasked.
+    let (mut raw, head) = handshake(server_addr, TOKEN).await;
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    assert!(
+        head.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="),
+        "{}",
+        head
+    );
+    let mask = [0x11, 0x22, 0x33, 0x44];
+    let mut frame = vec![0x82, 0x80 | 4];
+    frame.extend_from_slice(&mask);
+    frame.extend(b"ping".iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+    raw.write_all(&frame).await.unwrap();
+    let mut echoed = [0u8; 6];
+    tokio::time::timeout(Duration::from_secs(5), raw.read_exact(&mut echoed))
+        .await
+        .expect("no frame through the tunnel")
+        .unwrap();
+    assert_eq!(&echoed, b"\x82\x04ping");
+}
+
+#[tokio::test]
+async fn test_ws_tunnel_rejects_wrong_token() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    let (server_addr, _) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        ws_tunnel: Some(tunnel(TunnelSide::Server, TOKEN)),
+        ..Config::default()
+    })
+    .await;
+    let (client_addr, mut client_metrics) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: server_addr.to_string(),
+        ws_tunnel: Some(tunnel(TunnelSide::Client, "wrong")),
+        ..Config::default()
+    })
+    .await;
+
+    // A refused token is not retried: the client is disconnected right away.
+    let mut stream = TcpStream::connect(client_addr).await.unwrap();
+    let mut buf = [0u8; 1];
+    let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut buf))
+        .await
+        .expect("client was never disconnected")
+        .unwrap_or(0);
+    assert_eq!(n, 0);
+    assert_eq!(client_metrics.borrow_and_update().total_connections, 0);
+}
+
+#[tokio::test]
+async fn test_ws_tunnel_rejects_unsendable_settings() {
+    let config = |ws_tunnel: WsTunnelConfig| Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        ws_tunnel: Some(ws_tunnel),
+        ..Config::default()
+    };
+    assert!(config(tunnel(TunnelSide::Client, TOKEN)).validate().is_ok());
+    assert!(
+        config(tunnel(TunnelSide::Client, "s3cret\r\nX-Injected: 1"))
+            .validate()
+            .is_err()
+    );
+    let mut bad_path = tunnel(TunnelSide::Client, TOKEN);
+    bad_path.path = "/my tunnel".to_string();
+    assert!(config(bad_path).validate().is_err());
+    let mut bad_host = tunnel(TunnelSide::Client, TOKEN);
+    bad_host.host = Some("tunnel.test\n".to_string());
+    assert!(config(bad_host).validate().is_err());
+}
+
+#[tokio::test]
+async fn test_ws_tunnel_closes_on_protocol_errors() {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    let (server_addr, _) = start(Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        ws_tunnel: Some(tunnel(TunnelSide::Server, TOKEN)),
+        ..Config::default()
+    })
+    .await;
+
+    let masked_header = |first: u8, len: u8| vec![first, 0x80 | len, 0, 0, 0, 0];
+    let mut long_ping = masked_header(0x89, 126);
+    long_ping.extend_from_slice(&[0u8; 128]);
+    let frames = [
+        // Unmasked, as only a server may send.
+        b"\x82\x04ping".to_vec(),
+        // RSV1 set without an extension.
+        [masked_header(0xc2, 4), b"ping".to_vec()].concat(),
+        // A reserved opcode.
+        masked_header(0x83, 0),
+        // A fragmented ping.
+        masked_header(0x09, 0),
+        long_ping,
+    ];
+    for frame in frames {
+        let (mut raw, head) = handshake(server_addr, TOKEN).await;
+        assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+        raw.write_all(&frame).await.unwrap();
+        let mut close = [0u8; 4];
+        tokio::time::timeout(Duration::from_secs(5), raw.read_exact(&mut close))
+            .await
+            .expect("no close frame")
+            .unwrap();
+        // A close frame with status 1002.
+        assert_eq!(close, [0x88, 0x02, 0x03, 0xea], "{:02x?}", frame);
+    }
+}
@@ -1,4 +1,7 @@
-use std::{fs, io, path::Path};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use serde::Deserialize;
 
@@ -32,6 +35,26 @@ pub struct Config {
     pub message_size: usize,
     #[serde(default)]
     pub scenarios: Vec<Scenario>,
+    pub replay: Option<ReplayConfig>,
+}
+
+// Replays recorded sessions against `target_addr` instead of generating load.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    pub path: PathBuf,
+    pub preserve_timing: bool,
+    pub response_timeout_ms: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("recordings"),
+            preserve_timing: true,
+            response_timeout_ms: 2000,
+        }
+    }
 }
 
 impl Default for Config {
@@ -42,6 +65,7 @@ impl Default for Config {
             duration_secs: 30,
             message_size: 1024,
             scenarios: Vec::new(),
+            replay: None,
         }
     }
 }
@@ -1,7 +1,9 @@
 pub mod config;
+pub mod replay;
 pub mod report;
 pub mod worker;
 
-pub use config::{Config, ConfigError, Scenario};
+pub use config::{Config, ConfigError, ReplayConfig, Scenario};
+pub use replay::{ReplayResult, load_recordings, print_replay, replay_session, start_delays};
 pub use report::{Report, ScenarioResult, print_matrix};
 pub use worker::{WorkerStats, run_worker};
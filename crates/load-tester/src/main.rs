@@ -1,6 +1,9 @@
 use std::time::{Duration, Instant};
 
-use load_tester::{Config, Report, ScenarioResult, print_matrix, run_worker};
+use load_tester::{
+    Config, ReplayConfig, ReplayResult, Report, ScenarioResult, load_recordings, print_matrix,
+    print_replay, replay_session, run_worker, start_delays,
+};
 use tokio::{select, time::sleep};
 use tokio_util::sync::CancellationToken;
 
@@ -11,7 +14,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Config::default()
     });
 
-    if config.is_matrix_mode() {
+    if let Some(replay) = &config.replay {
+        let all_matched = run_replay_mode(&config.target_addr, replay).await?;
+        if !all_matched {
+            std::process::exit(1);
+        }
+    } else if config.is_matrix_mode() {
         run_matrix_mode(&config).await;
     } else {
         run_single_mode(&config).await;
@@ -93,6 +101,50 @@ async fn run_matrix_mode(config: &Config) {
     print_matrix(&results);
 }
 
+async fn run_replay_mode(
+    target_addr: &str,
+    replay: &ReplayConfig,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let recordings = load_recordings(&replay.path)?;
+
+    println!("========================================");
+    println!("     Load Tester - Replay Mode");
+    println!("========================================");
+    println!("Target:          {}", target_addr);
+    println!(
+        "Recordings:      {} from {}",
+        recordings.len(),
+        replay.path.display()
+    );
+    println!(
+        "Timing:          {}",
+        if replay.preserve_timing {
+            "original"
+        } else {
+            "as fast as possible"
+        }
+    );
+    println!("========================================\n");
+
+    let delays = start_delays(&recordings);
+    let response_timeout = Duration::from_millis(replay.response_timeout_ms);
+    let mut handles = Vec::with_capacity(recordings.len());
+    for ((name, recording), delay) in recordings.into_iter().zip(delays) {
+        let target = target_addr.to_string();
+        let start_delay = replay.preserve_timing.then_some(delay);
+        handles.push(tokio::spawn(async move {
+            replay_session(&target, name, &recording, start_delay, response_timeout).await
+        }));
+    }
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await?);
+    }
+    print_replay(&results);
+    Ok(results.iter().all(ReplayResult::is_match))
+}
+
 async fn run_scenario(
     target_addr: &str,
     connections: usize,
@@ -0,0 +1,195 @@
+use std::{
+    fs,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use basic_tcp_proxy::{Direction, Recording, RecordingError};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    join,
+    net::TcpStream,
+    time::{Instant, sleep_until, timeout},
+};
+
+pub struct ReplayResult {
+    pub name: String,
+    pub bytes_sent: usize,
+    pub expected: Vec<u8>,
+    pub received: Vec<u8>,
+    pub error: Option<String>,
+}
+
+impl ReplayResult {
+    // Offset of the first byte where the response differs from the recording.
+    pub fn mismatch(&self) -> Option<usize> {
+        let common = self
+            .expected
+            .iter()
+            .zip(&self.received)
+            .position(|(expected, received)| expected != received);
+        match common {
+            Some(offset) => Some(offset),
+            None if self.expected.len() != self.received.len() => {
+                Some(self.expected.len().min(self.received.len()))
+            }
+            None => None,
+        }
+    }
+
+    pub fn is_match(&self) -> bool {
+        self.error.is_none() && self.mismatch().is_none()
+    }
+}
+
+// A single recording file, or every `.rec` file in a directory, ordered by name.
+pub fn load_recordings(path: &Path) -> Result<Vec<(String, Recording)>, RecordingError> {
+    let mut files = if path.is_dir() {
+        fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|file| file.extension().is_some_and(|ext| ext == "rec"))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+    files.sort();
+
+    files
+        .into_iter()
+        .map(|file| {
+            let name = file.file_name().map_or_else(
+                || file.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            );
+            Ok((name, Recording::from_file(&file)?))
+        })
+        .collect()
+}
+
+// Plays the client side of a recording against `target_addr` and collects the responses.
+// With `start_delay` set, chunks are sent at their recorded offsets after that delay; without it
+// they are sent back to back. Reading stops at EOF, or after `response_timeout` of silence once
+// everything was sent.
+pub async fn replay_session(
+    target_addr: &str,
+    name: String,
+    recording: &Recording,
+    start_delay: Option<Duration>,
+    response_timeout: Duration,
+) -> ReplayResult {
+    let mut result = ReplayResult {
+        name,
+        bytes_sent: 0,
+        expected: recording.stream(Direction::Downstream),
+        received: Vec::new(),
+        error: None,
+    };
+    let start = Instant::now() + start_delay.unwrap_or_default();
+    if start_delay.is_some() {
+        sleep_until(start).await;
+    }
+
+    let stream = match TcpStream::connect(target_addr).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            result.error = Some(format!("connect failed: {}", e));
+            return result;
+        }
+    };
+    let (mut read, mut write) = stream.into_split();
+    let sent_all = AtomicBool::new(false);
+
+    let send_requests = async {
+        let mut sent = 0;
+        for chunk in &recording.chunks {
+            if chunk.direction != Direction::Upstream {
+                continue;
+            }
+            if start_delay.is_some() {
+                sleep_until(start + chunk.offset).await;
+            }
+            write.write_all(&chunk.data).await?;
+            sent += chunk.data.len();
+        }
+        write.shutdown().await?;
+        sent_all.store(true, Ordering::Relaxed);
+        Ok::<_, std::io::Error>(sent)
+    };
+
+    let read_responses = async {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match timeout(response_timeout, read.read(&mut buf)).await {
+                Ok(Ok(0) | Err(_)) => break,
+                Ok(Ok(n)) => received.extend_from_slice(&buf[..n]),
+                Err(_) if sent_all.load(Ordering::Relaxed) => break,
+                Err(_) => {}
+            }
+        }
+        received
+    };
+
+    let (sent, received) = join!(send_requests, read_responses);
+    match sent {
+        Ok(sent) => result.bytes_sent = sent,
+        Err(e) => result.error = Some(format!("send failed: {}", e)),
+    }
+    result.received = received;
+    result
+}
+
+// Sessions keep their recorded start times relative to the earliest one.
+pub fn start_delays(recordings: &[(String, Recording)]) -> Vec<Duration> {
+    let earliest = recordings
+        .iter()
+        .map(|(_, recording)| recording.header.started_at)
+        .min()
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    recordings
+        .iter()
+        .map(|(_, recording)| {
+            recording
+                .header
+                .started_at
+                .duration_since(earliest)
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn snippet(data: &[u8], offset: usize) -> String {
+    let end = (offset + 16).min(data.len());
+    format!("\"{}\"", data[offset.min(end)..end].escape_ascii())
+}
+
+pub fn print_replay(results: &[ReplayResult]) {
+    println!("=== Replay Results ===");
+    for result in results {
+        if let Some(error) = &result.error {
+            println!("  FAIL      {}: {}", result.name, error);
+        } else if let Some(offset) = result.mismatch() {
+            println!(
+                "  MISMATCH  {}: differs at byte {} (expected {} bytes, got {})",
+                result.name,
+                offset,
+                result.expected.len(),
+                result.received.len()
+            );
+            println!("            expected {}", snippet(&result.expected, offset));
+            println!("            received {}", snippet(&result.received, offset));
+        } else {
+            println!(
+                "  OK        {}: sent {} bytes, {} bytes matched",
+                result.name,
+                result.bytes_sent,
+                result.received.len()
+            );
+        }
+    }
+    let matched = results.iter().filter(|result| result.is_match()).count();
+    println!();
+    println!("Matched:         {}/{}", matched, results.len());
+}
@@ -0,0 +1,152 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use basic_tcp_proxy::{Config, Direction, Proxy, RecordConfig, Recording};
+use echo_server::EchoServer;
+use load_tester::{load_recordings, replay_session, start_delays};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+async fn start_echo() -> (SocketAddr, JoinHandle<()>) {
+    let (echo_server, echo_addr) = EchoServer::bind("127.0.0.1:0").await.unwrap();
+    let handle = tokio::spawn(async move {
+        echo_server.run().await.unwrap();
+    });
+    (echo_addr, handle)
+}
+
+// Echoes everything back uppercased, so replayed responses differ from the recording.
+async fn start_shouting_echo() -> (SocketAddr, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 1024];
+                loop {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            let reply = buf[..n].to_ascii_uppercase();
+                            if stream.write_all(&reply).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    (addr, handle)
+}
+
+async fn wait_for_recording(dir: &Path) -> PathBuf {
+    for _ in 0..40 {
+        let recorded = std::fs::read_dir(dir)
+            .ok()
+            .and_then(|mut entries| entries.find_map(|entry| Some(entry.ok()?.path())));
+        // The file is only complete once the writer has seen both legs finish.
+        if let Some(path) = recorded
+            && Recording::from_file(&path).is_ok_and(|r| r.chunks.len() == 4)
+        {
+            return path;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("no recording in {}", dir.display());
+}
+
+#[tokio::test]
+async fn test_record_and_replay_session() {
+    let (echo_addr, echo_handle) = start_echo().await;
+    let dir = std::env::temp_dir().join(format!("load-tester-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let config = Config {
+        listen_addr: "127.0.0.1:0".to_string(),
+        target_addr: echo_addr.to_string(),
+        record: RecordConfig {
+            dir: dir.clone(),
+            routes: vec!["default".to_string()],
+        },
+        ..Config::default()
+    };
+    let (mut proxy, proxy_addr) = Proxy::new(config).await.unwrap();
+    let proxy_handle = tokio::spawn(async move {
+        proxy.run().await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let client_addr = stream.local_addr().unwrap();
+    for msg in [&b"hello"[..], b"world"] {
+        stream.write_all(msg).await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, msg);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    drop(stream);
+
+    let path = wait_for_recording(&dir).await;
+    let recording = Recording::from_file(&path).unwrap();
+    assert_eq!(recording.header.route, "default");
+    assert_eq!(recording.header.client_addr, client_addr.to_string());
+    assert_eq!(recording.header.target_addr, echo_addr.to_string());
+    let directions: Vec<Direction> = recording.chunks.iter().map(|c| c.direction).collect();
+    assert_eq!(
+        directions,
+        [
+            Direction::Upstream,
+            Direction::Downstream,
+            Direction::Upstream,
+            Direction::Downstream
+        ]
+    );
+    assert!(recording.chunks[2].offset >= recording.chunks[0].offset + Duration::from_millis(100));
+    assert_eq!(recording.stream(Direction::Upstream), b"helloworld");
+    assert_eq!(recording.stream(Direction::Downstream), b"helloworld");
+
+    let recordings = load_recordings(&dir).unwrap();
+    assert_eq!(recordings.len(), 1);
+    assert_eq!(start_delays(&recordings), [Duration::ZERO]);
+    let (name, recording) = &recordings[0];
+
+    // Preserving timing replays the pause between the two messages.
+    let start = tokio::time::Instant::now();
+    let result = replay_session(
+        &echo_addr.to_string(),
+        name.clone(),
+        recording,
+        Some(Duration::ZERO),
+        Duration::from_millis(200),
+    )
+    .await;
+    assert!(result.is_match(), "{:?}", result.mismatch());
+    assert_eq!(result.bytes_sent, 10);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    let (shouting_addr, shouting_handle) = start_shouting_echo().await;
+    let result = replay_session(
+        &shouting_addr.to_string(),
+        name.clone(),
+        recording,
+        None,
+        Duration::from_millis(200),
+    )
+    .await;
+    assert!(!result.is_match());
+    assert_eq!(result.mismatch(), Some(0));
+    assert_eq!(result.received, b"HELLOWORLD");
+
+    proxy_handle.abort();
+    echo_handle.abort();
+    shouting_handle.abort();
+    let _ = std::fs::remove_dir_all(&dir);
+}